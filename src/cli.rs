@@ -1,12 +1,26 @@
 //! Command-line interface
 
-use crate::utils;
+mod config;
 
+use pmg::model::{try_read_objects_from, try_read_sample_description_from};
+use pmg::utils::{self, AngleUnit};
+use pmg::{
+    BootstrapSettings, Criterion, FitSettings, McmcSettings, Model, OptionalParam, Params, Priors,
+    RadialFlow, RotationCurveKind, RotationCurveModel, SigmaProfile, SpatialDistribution,
+};
+
+use core::fmt::Debug;
 use core::ops::Range;
+use core::str::FromStr;
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use clap::{builder::TypedValueParser, Parser};
+use anyhow::{Context, Result};
+use clap::{builder::TypedValueParser, ArgAction, CommandFactory, FromArgMatches, Parser};
+use num::Float;
+use serde::de::DeserializeOwned;
 
 /// Computation goal
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
@@ -15,13 +29,18 @@ pub enum Goal {
     Objects,
     /// Fit the model of the Galaxy to the data
     Fit,
+    /// Simulate a catalogue using the parameters
+    /// and the input objects as templates
+    Simulate,
+    /// Run an injection-recovery campaign: simulate
+    /// catalogues and fit the model to each of them
+    Recovery,
 }
 
 /// Parser of angles in the hours-minutes-seconds form
 #[derive(Clone)]
 struct HMSParser;
 
-#[allow(clippy::indexing_slicing)]
 impl TypedValueParser for HMSParser {
     type Value = f64;
 
@@ -33,13 +52,9 @@ impl TypedValueParser for HMSParser {
     ) -> Result<Self::Value, clap::Error> {
         // If the OS string can be converted to a regular string
         if let Some(string) = value.to_str() {
-            // If the string can be split into a vector of floats
-            if let Ok(vec) = utils::str2vec(string) {
-                // If there are at least three float numbers
-                if vec.len() >= 3 {
-                    // Convert these to the radians
-                    return Ok(utils::hms2rad(vec[0], vec[1], vec[2]));
-                }
+            // If the string can be parsed as an angle in this form
+            if let Ok(angle) = utils::parse_angle(string, AngleUnit::Hms) {
+                return Ok(angle);
             }
         }
         // Otherwise, return an error
@@ -55,7 +70,6 @@ impl TypedValueParser for HMSParser {
 #[derive(Clone)]
 struct DMSParser;
 
-#[allow(clippy::indexing_slicing)]
 impl TypedValueParser for DMSParser {
     type Value = f64;
 
@@ -67,13 +81,9 @@ impl TypedValueParser for DMSParser {
     ) -> Result<Self::Value, clap::Error> {
         // If the OS string can be converted to a regular string
         if let Some(string) = value.to_str() {
-            // If the string can be split into a vector of floats
-            if let Ok(vec) = utils::str2vec(string) {
-                // If there are at least three float numbers
-                if vec.len() >= 3 {
-                    // Convert these to the radians
-                    return Ok(utils::dms2rad(vec[0], vec[1], vec[2]));
-                }
+            // If the string can be parsed as an angle in this form
+            if let Ok(angle) = utils::parse_angle(string, AngleUnit::Dms) {
+                return Ok(angle);
             }
         }
         // Otherwise, return an error
@@ -100,10 +110,9 @@ impl TypedValueParser for DecParser {
     ) -> Result<Self::Value, clap::Error> {
         // If the OS string can be converted to a regular string
         if let Some(string) = value.to_str() {
-            // If the string can be parsed as a float
-            if let Ok(decimal) = string.parse::<f64>() {
-                // Convert it to radians
-                return Ok(decimal.to_radians());
+            // If the string can be parsed as an angle in this form
+            if let Ok(angle) = utils::parse_angle(string, AngleUnit::Degrees) {
+                return Ok(angle);
             }
         }
         // Otherwise, return an error
@@ -156,6 +165,47 @@ impl TypedValueParser for RangeParser {
     }
 }
 
+/// Optimal degree of the polynomial of the rotation curve
+#[derive(Clone, Copy, Debug)]
+pub enum NBest {
+    /// Choose the degree via an information criterion
+    Auto,
+    /// Use the specified degree
+    Degree(usize),
+}
+
+/// Parser of the optimal degree
+#[derive(Clone)]
+struct NBestParser;
+
+impl TypedValueParser for NBestParser {
+    type Value = NBest;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        // If the OS string can be converted to a regular string
+        if let Some(string) = value.to_str() {
+            if string == "auto" {
+                return Ok(NBest::Auto);
+            }
+            // If the string can be parsed as an integer
+            if let Ok(n) = string.parse::<usize>() {
+                return Ok(NBest::Degree(n));
+            }
+        }
+        // Otherwise, return an error
+        Err(clap::Error::raw(
+            clap::error::ErrorKind::InvalidValue,
+            "Couldn't parse a degree (or `auto`) from the string\n",
+        )
+        .with_cmd(cmd))
+    }
+}
+
 /// Parser of paths
 #[derive(Clone)]
 pub struct PathBufParser;
@@ -194,7 +244,7 @@ impl TypedValueParser for PathBufParser {
 /// Command-line interface arguments
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Parser)]
-#[command(author, version, about)]
+#[command(author, version, about, args_override_self = true)]
 #[command(help_template(
     "{before-help}{name} {version}\n\
     {author-with-newline}{about-with-newline}\n\
@@ -202,40 +252,135 @@ impl TypedValueParser for PathBufParser {
     {all-args}{after-help}"
 ))]
 pub struct Args {
+    /// Configuration file (TOML)
+    ///
+    /// Its keys are the names of the other arguments written with
+    /// underscores, e.g. `r_0` or `with_errors`. The arguments
+    /// specified in the command line take precedence over it.
+    #[arg(long, value_parser = PathBufParser)]
+    pub config: Option<PathBuf>,
     /// Input file
-    #[arg(short, required = true, value_parser = PathBufParser)]
+    ///
+    /// The units of the equatorial coordinates (`HMS angle`, `DMS angle`,
+    /// `degrees`, or `radians`) can be declared in brackets in the descriptions
+    /// of the columns, e.g. `#  2 alpha: Right ascension [degrees]`. Otherwise,
+    /// sexagesimal values are taken as HMS (DMS) angles and bare numbers as
+    /// decimal degrees.
+    ///
+    /// The optional columns `mu_x_mu_y_corr`, `par_mu_x_corr`, and `par_mu_y_corr`
+    /// hold the correlation coefficients of the parallax and the proper motions.
+    /// Those missing are assumed to be zero. The optional columns `par_ep`,
+    /// `par_em`, `v_lsr_ep`, and `v_lsr_em` hold the asymmetric uncertainties,
+    /// which override `par_e` and `v_lsr_e`.
+    #[arg(short, long, required = true, value_parser = PathBufParser)]
     pub input: PathBuf,
     /// Output directory
-    #[arg(short, required = true)]
+    #[arg(short, long, required = true)]
     pub output_dir: PathBuf,
     /// Computation goal
     #[arg(long, required = true)]
     pub goal: Goal,
     /// Optimal degree of the polynomial of the rotation curve
     ///
-    /// Maximum supported value is 8.
-    #[arg(long, default_value_t = 1)]
-    pub n_best: usize,
+    /// Maximum supported value is 8. If `auto` is specified, the degree
+    /// is chosen via `--n-best-criterion` after the first fit.
+    #[arg(long, default_value = "1", value_parser = NBestParser)]
+    pub n_best: NBest,
+    /// Information criterion to choose the optimal degree with
+    /// if `--n-best=auto` (`aic`, `aicc`, or `bic`)
+    #[arg(long, default_value_t = Criterion::Bic)]
+    pub n_best_criterion: Criterion,
     /// Maximum degree of the polynomial of the rotation curve
     ///
-    /// Maximum supported value is 8.
+    /// Maximum supported value is 8. For the families of the rotation curve
+    /// other than the Taylor series, the degree is the number of the fitted
+    /// shape parameters plus one, so it's limited by their number.
     #[arg(long, default_value_t = 6)]
     pub n_max: usize,
+    /// Family of the rotation curve (`taylor`, `power-law`,
+    /// `brand-blitz`, `urc`, or `flat-linear`)
+    ///
+    /// The `theta_i` parameters are used by the Taylor series only;
+    /// the shape parameters of the other families start from their
+    /// canonical values.
+    #[arg(long, default_value_t = RotationCurveKind::Taylor)]
+    pub rotcurve: RotationCurveKind,
     /// Try to define the confidence intervals (fit goal only)
-    #[arg(long)]
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true")]
     pub with_errors: bool,
     /// Try to compute conditional profiles (fit goal only)
-    #[arg(long)]
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true")]
     pub with_conditional_profiles: bool,
     /// Disable the inner optimization (fit goal only)
-    #[arg(long)]
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true")]
     pub disable_inner: bool,
     /// Disable checks for outliers
-    #[arg(long)]
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true")]
     pub disable_outliers: bool,
+    /// Compute the leave-one-out influence of the
+    /// objects on the optimal model (fit goal only)
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true")]
+    pub with_influence: bool,
+    /// Sample the posterior distribution of the parameters
+    /// of the optimal model via MCMC (fit goal only)
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true")]
+    pub with_mcmc: bool,
+    /// Number of the walkers of the ensemble sampler
+    #[arg(long, default_value_t = 32, help_heading = "MCMC")]
+    pub mcmc_walkers: usize,
+    /// Number of the steps of the ensemble sampler (including the burn-in ones)
+    #[arg(long, default_value_t = 1000, help_heading = "MCMC")]
+    pub mcmc_steps: usize,
+    /// Number of the burn-in steps of the ensemble sampler
+    #[arg(long, default_value_t = 200, help_heading = "MCMC")]
+    pub mcmc_burn: usize,
+    /// Number of the sets of the objects resampled with replacement
+    /// to refit the models on (fit goal only)
+    ///
+    /// Percentile intervals of the parameters are written to
+    /// the `bootstrap.dat` files of the respective degrees.
+    #[arg(long, help_heading = "Bootstrap")]
+    pub bootstrap: Option<usize>,
+    /// Redo the checks for outliers on each resampled set
+    ///
+    /// By default, the outliers found in the main fit are excluded.
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", help_heading = "Bootstrap")]
+    pub bootstrap_redo_outliers: bool,
+    /// Number of the folds in the K-fold cross-validation
+    /// of the degrees (fit goal only)
+    ///
+    /// Predictive losses are written to the `cross_validation.dat` file.
+    #[arg(long, help_heading = "Cross-validation")]
+    pub cv_folds: Option<usize>,
     /// Tolerance of the L-BFGS algorithm
     #[arg(long, default_value_t = 1e-15)]
     pub lbfgs_tolerance: f64,
+    /// Seed of the random number generator
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Number of the trials in the injection-recovery campaign
+    #[arg(long, default_value_t = 100, help_heading = "Simulation")]
+    pub n_trials: usize,
+    /// Relative perturbation of the start points of the fits in the
+    /// injection-recovery campaign (a standard deviation)
+    #[arg(long, default_value_t = 0.05, help_heading = "Simulation")]
+    pub start_jitter: f64,
+    /// Number of the objects sampled from the spatial distribution
+    ///
+    /// If not specified, the positions and the parallaxes of the input
+    /// objects are used instead. Otherwise, the input objects only
+    /// serve as a pool of the uncertainties, the types and the sources.
+    #[arg(long, help_heading = "Simulation")]
+    pub sim_n_objects: Option<usize>,
+    /// Radial scale length of the exponential disk (kpc)
+    #[arg(long, default_value_t = 3., help_heading = "Simulation")]
+    pub sim_r_g_scale: f64,
+    /// Vertical scale height of the disk (kpc)
+    #[arg(long, default_value_t = 0.05, help_heading = "Simulation")]
+    pub sim_z_scale: f64,
+    /// Maximum heliocentric distance of the objects (kpc)
+    #[arg(long, default_value_t = 10., help_heading = "Simulation")]
+    pub sim_r_h_max: f64,
     /// Galactocentric distance to the Sun (kpc)
     ///
     /// Sources: Reid et al. (2019); Gromov, Nikiforov (2021)
@@ -254,7 +399,7 @@ pub struct Args {
     pub u_sun: f64,
     /// Residual motion of the Sun toward l = 90 degrees (km/s)
     ///
-    /// This is only used for fitting.
+    /// This is only used for fitting and simulation.
     ///
     /// Sources: Rastorguev et al. (2017)
     #[arg(long, default_value_t = 19.0, help_heading = "Parameters")]
@@ -264,6 +409,21 @@ pub struct Args {
     /// Sources: Reid et al. (2019); Gromov, Nikiforov (2021)
     #[arg(long, default_value_t = 7.7, help_heading = "Parameters")]
     pub w_sun: f64,
+    /// Height of the Sun above the Galactic plane (pc)
+    ///
+    /// The Galactocentric frame is tilted with respect to the heliocentric
+    /// Galactic one by the angle `asin(z_sun / R_0)`, as in Astropy. The
+    /// value of 20.8 pc is suggested by Bennett, Bovy (2019).
+    #[arg(
+        long,
+        default_value_t = 0.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub z_sun: f64,
+    /// Fit the height of the Sun above the Galactic plane, too
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", help_heading = "Parameters")]
+    pub fit_z_sun: bool,
     /// Radial component of the ellipsoid of natural standard deviations (km/s)
     #[arg(long, default_value_t = 12., help_heading = "Parameters")]
     pub sigma_r_g: f64,
@@ -273,6 +433,226 @@ pub struct Args {
     /// Vertical component of the ellipsoid of natural standard deviations (km/s)
     #[arg(long, default_value_t = 3., help_heading = "Parameters")]
     pub sigma_z: f64,
+    /// Vertex deviation of the ellipsoid of natural standard deviations (degrees)
+    ///
+    /// The radial axis of the ellipsoid is turned by this angle from the
+    /// direction to the Galactic center toward the direction of rotation.
+    #[arg(
+        long,
+        default_value_t = 0.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub l_v: f64,
+    /// Fit the vertex deviation, too (together with the sigmas)
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", help_heading = "Parameters")]
+    pub fit_l_v: bool,
+    /// Tilt of the ellipsoid of natural standard deviations in the R-Z plane (degrees)
+    ///
+    /// The radial axis of the ellipsoid is turned by this angle (after
+    /// the vertex deviation) toward the north Galactic pole.
+    #[arg(
+        long,
+        default_value_t = 0.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub alpha_rz: f64,
+    /// Fit the tilt of the ellipsoid in the R-Z plane, too (together with the sigmas)
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", help_heading = "Parameters")]
+    pub fit_alpha_rz: bool,
+    /// Radial profile of the natural standard deviations
+    /// (`constant`, `exponential`, or `linear`)
+    ///
+    /// The sigmas are then taken at R = R_0, and the scale lengths
+    /// of the profile are fitted together with them. The linear profile
+    /// is clamped at zero beyond R = R_0 + h.
+    #[arg(long, default_value_t = SigmaProfile::Constant, help_heading = "Parameters")]
+    pub sigma_profile: SigmaProfile,
+    /// Scale length of the radial profile of `sigma_R` (kpc)
+    #[arg(
+        long,
+        default_value_t = 10.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub h_sigma_r: f64,
+    /// Scale length of the radial profile of `sigma_theta` (kpc)
+    #[arg(
+        long,
+        default_value_t = 10.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub h_sigma_theta: f64,
+    /// Scale length of the radial profile of `sigma_Z` (kpc)
+    #[arg(
+        long,
+        default_value_t = 10.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub h_sigma_z: f64,
+    /// The second derivative of the linear rotation velocity (km/s/kpc^2)
+    #[arg(long, default_value_t = 0., help_heading = "Parameters")]
+    pub theta_2: f64,
+    /// The third derivative of the linear rotation velocity (km/s/kpc^3)
+    #[arg(long, default_value_t = 0., help_heading = "Parameters")]
+    pub theta_3: f64,
+    /// The 4th derivative of the linear rotation velocity (km/s/kpc^4)
+    #[arg(long, default_value_t = 0., help_heading = "Parameters")]
+    pub theta_4: f64,
+    /// The 5th derivative of the linear rotation velocity (km/s/kpc^5)
+    #[arg(long, default_value_t = 0., help_heading = "Parameters")]
+    pub theta_5: f64,
+    /// The 6th derivative of the linear rotation velocity (km/s/kpc^6)
+    #[arg(long, default_value_t = 0., help_heading = "Parameters")]
+    pub theta_6: f64,
+    /// The 7th derivative of the linear rotation velocity (km/s/kpc^7)
+    #[arg(long, default_value_t = 0., help_heading = "Parameters")]
+    pub theta_7: f64,
+    /// The 8th derivative of the linear rotation velocity (km/s/kpc^8)
+    #[arg(long, default_value_t = 0., help_heading = "Parameters")]
+    pub theta_8: f64,
+    /// Number of the spiral arms
+    ///
+    /// If specified, the perturbations by the spiral density wave
+    /// (Lin, Shu 1964) are added to the model, and their amplitudes,
+    /// the pitch angle and the phase of the Sun are fitted, too.
+    #[arg(long, help_heading = "Parameters")]
+    pub spiral_arms: Option<u32>,
+    /// Amplitude of the radial perturbation by the spiral density wave (km/s)
+    ///
+    /// Source: Rastorguev et al. (2017)
+    #[arg(long, default_value_t = 7., help_heading = "Parameters")]
+    pub f_r: f64,
+    /// Amplitude of the azimuthal perturbation by the spiral density wave (km/s)
+    ///
+    /// Source: Rastorguev et al. (2017)
+    #[arg(long, default_value_t = 4., help_heading = "Parameters")]
+    pub f_theta: f64,
+    /// Pitch angle of the spiral pattern (degrees)
+    ///
+    /// Negative values correspond to the trailing spirals.
+    ///
+    /// Source: Rastorguev et al. (2017)
+    #[arg(long, default_value_t = -13., allow_negative_numbers = true, help_heading = "Parameters")]
+    pub pitch_angle: f64,
+    /// Phase of the spiral density wave at the Sun (degrees)
+    ///
+    /// Source: Rastorguev et al. (2017)
+    #[arg(long, default_value_t = -140., allow_negative_numbers = true, help_heading = "Parameters")]
+    pub chi_0: f64,
+    /// Add the perturbation by the central bar (m = 2) to the model
+    ///
+    /// Its amplitude, orientation and radial extent are fitted, too.
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", help_heading = "Parameters")]
+    pub bar: bool,
+    /// Amplitude of the perturbation by the bar (km/s)
+    #[arg(
+        long,
+        default_value_t = 10.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub v_bar: f64,
+    /// Angle between the major axis of the bar and the Sun-GC line (degrees)
+    ///
+    /// The angle is counted in the direction of the Galactic rotation,
+    /// so the near end of the bar is at positive longitudes.
+    ///
+    /// Source: Bland-Hawthorn, Gerhard (2016)
+    #[arg(
+        long,
+        default_value_t = 27.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub phi_bar: f64,
+    /// Radial extent of the perturbation by the bar (kpc)
+    ///
+    /// The perturbation peaks at this Galactocentric distance.
+    #[arg(long, default_value_t = 3., help_heading = "Parameters")]
+    pub r_bar: f64,
+    /// Global radial velocity field (`none`, `constant`, or `k-term`)
+    ///
+    /// Either a constant radial velocity (`V_flow`) or the one linear in R
+    /// (`K_flow * R`) is added to the model, and its parameter is fitted, too.
+    /// Positive values correspond to an expansion of the disk.
+    #[arg(long, default_value_t = RadialFlow::None, help_heading = "Parameters")]
+    pub radial_flow: RadialFlow,
+    /// Constant radial velocity of the global radial flow (km/s)
+    #[arg(
+        long,
+        default_value_t = 0.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub v_flow: f64,
+    /// K term of the global radial flow (km/s/kpc)
+    #[arg(
+        long,
+        default_value_t = 0.,
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub k_flow: f64,
+    /// Fit the zero-point offsets of the parallaxes, one for each source of the data
+    ///
+    /// The offsets (`delta_varpi_1`, `delta_varpi_2`, etc., in the order of
+    /// the first appearance of the sources in the input file) are subtracted
+    /// from the observed parallaxes.
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", help_heading = "Parameters")]
+    pub fit_par_offsets: bool,
+    /// Error floors of the velocities of the objects from a source of the data (km/s, can be repeated)
+    ///
+    /// The form is `SOURCE=V_R[:V_T]`, where `V_R` is added in quadrature to the
+    /// uncertainties in the radial velocities and `V_T` (equal to `V_R` if omitted)
+    /// to the uncertainties in the transverse velocities. The source `*` matches
+    /// the sources not listed explicitly, while unmatched sources have zero
+    /// floors. Specifying this option replaces the default table.
+    ///
+    /// Source: Reid et al. (2019)
+    #[arg(
+        long = "error-floor",
+        action = ArgAction::Append,
+        value_name = "SOURCE=V_R[:V_T]",
+        default_values = ["*=10", "Reid=0"],
+        help_heading = "Parameters"
+    )]
+    pub error_floors: Vec<String>,
+    /// Fit the error floors of the velocities, a pair for each source of the data
+    ///
+    /// The floors (`floor_v_r_1`, `floor_v_t_1`, etc., in the order of the
+    /// first appearance of the sources in the input file) start from their
+    /// specified values and are fitted in the L' = 1 run only. Note that
+    /// a zero floor stays zero, since the likelihood depends on its square.
+    #[arg(long, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", help_heading = "Parameters")]
+    pub fit_error_floors: bool,
+    /// Fix a parameter while fitting (can be repeated)
+    ///
+    /// The forms are `NAME` and `NAME=VALUE`, where `NAME` is the name of
+    /// the parameter as in the output files, e.g. `R_0`. Without a value,
+    /// the parameter is fixed at its initial value.
+    #[arg(
+        long,
+        action = ArgAction::Append,
+        value_name = "NAME[=VALUE]",
+        allow_negative_numbers = true,
+        help_heading = "Parameters"
+    )]
+    pub fix: Vec<String>,
+    /// Prior of a parameter (can be repeated)
+    ///
+    /// The forms are `NAME=uniform:LOW:HIGH`, `NAME=normal:MEAN:STD_DEV`, and
+    /// `NAME=log-normal:MEDIAN:STD_DEV` (the standard deviation of the natural
+    /// logarithm), where `NAME` is the name of the parameter as in the output
+    /// files, e.g. `R_0`. The parameters without a prior have a flat one.
+    ///
+    /// The priors penalize the cost function in the fits, the confidence
+    /// intervals, and the profiles, and define the posterior in the MCMC.
+    #[arg(long, action = ArgAction::Append, help_heading = "Parameters")]
+    pub prior: Vec<String>,
     /// Linear rotation velocity of the Sun (km/s)
     ///
     /// This is only used for computing per-object data.
@@ -315,9 +695,179 @@ pub struct Args {
     /// Sources: Reid et al. (2009); Gromov, Nikiforov (2021)
     #[arg(long, default_value_t = 7.7, help_heading = "Parameters")]
     pub w_sun_standard: f64,
+    /// Resolved configuration (TOML)
+    #[arg(skip)]
+    pub resolved_config: String,
 }
 
-/// Parse the arguments
-pub fn parse() -> Args {
-    Args::parse()
+impl Args {
+    /// Try to build a model from the arguments
+    pub fn try_build_model<F>(&self, output_dir: PathBuf) -> Result<Model<F>>
+    where
+        F: Float + Debug + Default + DeserializeOwned + FromStr,
+        <F as FromStr>::Err: Error + Send + Sync + 'static,
+    {
+        let mut params = Params {
+            r_0: utils::cast(self.r_0)?,
+            omega_0: utils::cast(self.omega_0)?,
+            a: utils::cast(self.a)?,
+            u_sun: utils::cast(self.u_sun)?,
+            v_sun: utils::cast(self.v_sun)?,
+            w_sun: utils::cast(self.w_sun)?,
+            z_sun: utils::cast(self.z_sun)?,
+            sigma_r_g: utils::cast(self.sigma_r_g)?,
+            sigma_theta: utils::cast(self.sigma_theta)?,
+            sigma_z: utils::cast(self.sigma_z)?,
+            l_v: utils::cast(self.l_v)?,
+            alpha_rz: utils::cast(self.alpha_rz)?,
+            h_sigma_r: utils::cast(self.h_sigma_r)?,
+            h_sigma_theta: utils::cast(self.h_sigma_theta)?,
+            h_sigma_z: utils::cast(self.h_sigma_z)?,
+            v_flow: utils::cast(self.v_flow)?,
+            k_flow: utils::cast(self.k_flow)?,
+            theta_2: utils::cast(self.theta_2)?,
+            theta_3: utils::cast(self.theta_3)?,
+            theta_4: utils::cast(self.theta_4)?,
+            theta_5: utils::cast(self.theta_5)?,
+            theta_6: utils::cast(self.theta_6)?,
+            theta_7: utils::cast(self.theta_7)?,
+            theta_8: utils::cast(self.theta_8)?,
+            alpha_ngp: utils::cast(self.alpha_ngp)?,
+            delta_ngp: utils::cast(self.delta_ngp)?,
+            theta_sun: utils::cast(self.theta_sun)?,
+            l_ncp: utils::cast(self.l_ncp)?,
+            k: utils::cast(self.k)?,
+            u_sun_standard: utils::cast(self.u_sun_standard)?,
+            v_sun_standard: utils::cast(self.v_sun_standard)?,
+            w_sun_standard: utils::cast(self.w_sun_standard)?,
+            f_r: utils::cast(self.f_r)?,
+            f_theta: utils::cast(self.f_theta)?,
+            i: utils::cast(self.pitch_angle)?,
+            chi_0: utils::cast(self.chi_0)?,
+            rotcurve: self.rotcurve,
+            v_bar: utils::cast(self.v_bar)?,
+            phi_bar: utils::cast(self.phi_bar)?,
+            r_bar: utils::cast(self.r_bar)?,
+            spiral_arms: self.spiral_arms,
+            bar: self.bar,
+            fitted: [
+                (self.fit_z_sun, OptionalParam::ZSun),
+                (self.fit_l_v, OptionalParam::VertexDeviation),
+                (self.fit_alpha_rz, OptionalParam::TiltRz),
+                (self.fit_error_floors, OptionalParam::ErrorFloors),
+            ]
+            .into_iter()
+            .filter_map(|(enabled, optional)| enabled.then_some(optional))
+            .collect(),
+            sigma_profile: self.sigma_profile,
+            radial_flow: self.radial_flow,
+            ..Default::default()
+        };
+        if self.rotcurve != RotationCurveKind::Taylor {
+            // Reset the shape parameters to their default values
+            params.update_with(&params.to_vec(1));
+        }
+
+        let objects = try_read_objects_from(&self.input)
+            .with_context(|| format!("Couldn't load the data from the file {:?}", self.input))?;
+        let sample_description = try_read_sample_description_from(&self.input)?;
+
+        if self.fit_par_offsets {
+            params
+                .try_init_par_offsets(&objects)
+                .with_context(|| "Couldn't define the zero-point offsets of the parallaxes")?;
+        }
+        params
+            .try_init_error_floors(&objects, &self.error_floors)
+            .with_context(|| "Couldn't define the error floors of the velocities")?;
+        for spec in &self.fix {
+            let (name, value) = match spec.split_once('=') {
+                Some((name, raw_value)) => {
+                    let value = raw_value
+                        .parse::<f64>()
+                        .with_context(|| format!("Couldn't parse the value in `{spec}`"))?;
+                    (name, Some(utils::cast(value)?))
+                }
+                None => (spec.as_str(), None),
+            };
+            params
+                .try_fix(name, value)
+                .with_context(|| format!("Couldn't fix the parameter `{spec}`"))?;
+        }
+
+        let priors = Priors::try_parse_from(&self.prior, &params.names(params.rotcurve.n_max()))?;
+
+        Model::builder()
+            .params(params)
+            .priors(priors)
+            .objects(objects)
+            .disable_inner(self.disable_inner)
+            .lbfgs_tolerance(utils::cast(self.lbfgs_tolerance)?)
+            .sample_description(sample_description)
+            .output_dir(output_dir)
+            .build()
+    }
+    /// Get the settings of the posterior sampling
+    pub const fn mcmc_settings(&self) -> McmcSettings {
+        McmcSettings {
+            n_walkers: self.mcmc_walkers,
+            n_steps: self.mcmc_steps,
+            n_burn: self.mcmc_burn,
+        }
+    }
+    /// Get the settings of the bootstrap, if it's requested
+    pub fn bootstrap_settings(&self) -> Option<BootstrapSettings> {
+        self.bootstrap.map(|n_samples| BootstrapSettings {
+            n_samples,
+            redo_outliers: self.bootstrap_redo_outliers,
+        })
+    }
+    /// Get the spatial distribution of the simulated objects
+    pub fn spatial_distribution<F>(&self) -> Result<Option<SpatialDistribution<F>>>
+    where
+        F: Float + Debug,
+    {
+        self.sim_n_objects
+            .map(|n_objects| {
+                Ok(SpatialDistribution {
+                    n_objects,
+                    r_g_scale: utils::cast(self.sim_r_g_scale)?,
+                    z_scale: utils::cast(self.sim_z_scale)?,
+                    r_h_max: utils::cast(self.sim_r_h_max)?,
+                })
+            })
+            .transpose()
+    }
+    /// Get the settings of the fitting pipeline
+    pub fn fit_settings(&self) -> FitSettings {
+        let (n_best, n_best_criterion) = match self.n_best {
+            NBest::Auto => (1, Some(self.n_best_criterion)),
+            NBest::Degree(n) => (n, None),
+        };
+        FitSettings {
+            n_best,
+            n_best_criterion,
+            n_max: self.n_max.min(self.rotcurve.n_max()),
+            with_errors: self.with_errors,
+            with_frozen_profiles: false,
+            disable_outliers: self.disable_outliers,
+        }
+    }
+}
+
+/// Parse the arguments, taking the configuration file into account
+pub fn parse() -> Result<Args> {
+    let mut raw_args: Vec<OsString> = env::args_os().collect();
+    // Put the arguments from the configuration file right after
+    // the name of the binary, so that the explicit ones override them
+    if let Some(path) = config::find_config_path(&raw_args) {
+        let config_args = config::try_read_config_from(&path, &raw_args)
+            .with_context(|| "Couldn't read the configuration file")?;
+        let index = raw_args.len().min(1);
+        raw_args.splice(index..index, config_args);
+    }
+    let matches = Args::command().get_matches_from(raw_args);
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    args.resolved_config = config::resolve_config(&matches)?;
+    Ok(args)
 }
@@ -0,0 +1,169 @@
+//! Configuration file
+
+use super::Args;
+
+use core::fmt::Write as _;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{ArgAction, ArgMatches, CommandFactory};
+use indoc::indoc;
+use toml::{Table, Value};
+
+/// ID of the argument which specifies the configuration file
+const CONFIG_ID: &str = "config";
+
+/// Find the path to the configuration file in the raw arguments
+pub fn find_config_path(raw_args: &[OsString]) -> Option<PathBuf> {
+    let mut iter = raw_args.iter().filter_map(|raw_arg| raw_arg.to_str());
+    while let Some(arg) = iter.next() {
+        if arg == "--config" {
+            return iter.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// Check whether the argument with the long name is present in the raw arguments
+fn is_present(raw_args: &[OsString], long: &str) -> bool {
+    raw_args
+        .iter()
+        .filter_map(|raw_arg| raw_arg.to_str()?.strip_prefix("--"))
+        .any(|arg| {
+            arg == long
+                || arg
+                    .strip_prefix(long)
+                    .map_or(false, |rest| rest.starts_with('='))
+        })
+}
+
+/// Try to read the configuration file and convert
+/// its entries to the equivalent command-line arguments
+///
+/// The values of the repeated arguments present in the raw arguments are
+/// dropped, since otherwise the explicit values would be added to them.
+pub fn try_read_config_from(path: &Path, raw_args: &[OsString]) -> Result<Vec<OsString>> {
+    let string = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read from the file {path:?}"))?;
+    let table: Table = string
+        .parse()
+        .with_context(|| format!("Couldn't parse the configuration file {path:?}"))?;
+
+    let command = Args::command();
+    let mut config_args = Vec::new();
+    for (key, value) in table {
+        if key == CONFIG_ID {
+            bail!("The configuration file can't point to another one");
+        }
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str())
+            .ok_or_else(|| anyhow!("Unknown key `{key}` in the configuration file"))?;
+        let long = arg
+            .get_long()
+            .ok_or_else(|| anyhow!("Unknown key `{key}` in the configuration file"))?;
+        if matches!(arg.get_action(), ArgAction::Append) && is_present(raw_args, long) {
+            continue;
+        }
+        // Arrays are expanded into repeated arguments
+        let values = if let Value::Array(array) = value {
+            array
+        } else {
+            vec![value]
+        };
+        for item in values {
+            let raw_value = match item {
+                Value::String(text) => text,
+                Value::Integer(integer) => integer.to_string(),
+                Value::Float(float) => float.to_string(),
+                Value::Boolean(boolean) => boolean.to_string(),
+                Value::Datetime(_) | Value::Array(_) | Value::Table(_) => {
+                    bail!(
+                        "The value of the key `{key}` should be a string, a number, a boolean, \
+                        or an array of these"
+                    )
+                }
+            };
+            config_args.push(OsString::from(format!("--{long}={raw_value}")));
+        }
+    }
+    Ok(config_args)
+}
+
+/// Convert a raw value of an argument to a TOML value
+fn to_value(string: &str) -> Value {
+    if let Ok(boolean) = string.parse::<bool>() {
+        Value::Boolean(boolean)
+    } else if let Ok(integer) = string.parse::<i64>() {
+        Value::Integer(integer)
+    } else if let Ok(float) = string.parse::<f64>() {
+        Value::Float(float)
+    } else {
+        Value::String(string.to_owned())
+    }
+}
+
+/// Resolve the configuration from the matched arguments
+pub fn resolve_config(matches: &ArgMatches) -> Result<String> {
+    let mut resolved_config = String::from(indoc!(
+        "
+        # Resolved configuration
+        #
+        # It can be passed back via the `--config` option
+
+        "
+    ));
+
+    for arg in Args::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        if id == CONFIG_ID {
+            continue;
+        }
+        let Some(raw_values) = matches.get_raw(id) else {
+            continue;
+        };
+        let mut values = raw_values
+            .map(|raw_value| {
+                raw_value
+                    .to_str()
+                    .map(to_value)
+                    .ok_or_else(|| anyhow!("The value of the `{id}` argument isn't valid Unicode"))
+            })
+            .collect::<Result<Vec<Value>>>()?;
+        // Repeated arguments are collected into arrays
+        let value = if matches!(arg.get_action(), ArgAction::Append) {
+            Value::Array(values)
+        } else if let Some(value) = values.pop() {
+            value
+        } else {
+            continue;
+        };
+        writeln!(resolved_config, "{id} = {value}")?;
+    }
+
+    Ok(resolved_config)
+}
+
+#[cfg(test)]
+use anyhow::ensure;
+
+#[test]
+fn test() -> Result<()> {
+    let path = std::env::temp_dir().join("pmg_test_config.toml");
+    fs::write(&path, "fix = [\"R_0\"]\nn_max = 5\n")?;
+    // The repeated arguments are taken from the file...
+    let config_args = try_read_config_from(&path, &[])?;
+    ensure!(config_args == ["--fix=R_0", "--n-max=5"]);
+    // ...unless they're present in the command line
+    let raw_args = ["pmg", "--fix=A", "--n-max", "3"].map(OsString::from);
+    let filtered_args = try_read_config_from(&path, &raw_args)?;
+    ensure!(filtered_args == ["--n-max=5"]);
+    ensure!(!is_present(&raw_args, "fi") && is_present(&raw_args, "n-max"));
+    fs::remove_file(&path)?;
+    Ok(())
+}
@@ -0,0 +1,25 @@
+//! This library crate allows a user to infer the parameters
+//! of the Galaxy by optimising over its parametric model.
+
+// These items were written as the internals of the binary crate, and
+// exposing them here made the lints for the exported API apply. Their
+// structures are meant to be built via the struct literals and updated
+// in place, and the panics come from the unwraps of the values that are
+// guaranteed to be computed by the earlier stages of the pipeline.
+#![allow(clippy::exhaustive_enums)]
+#![allow(clippy::exhaustive_structs)]
+#![allow(clippy::missing_inline_in_public_items)]
+#![allow(clippy::missing_panics_doc)]
+#![allow(clippy::must_use_candidate)]
+#![allow(clippy::return_self_not_must_use)]
+
+extern crate alloc;
+
+pub mod model;
+pub mod utils;
+
+pub use model::{
+    BootstrapSettings, Criterion, FitLogs, FitResults, FitSettings, LogWriter, McmcSettings, Model,
+    ModelBuilder, Object, OptionalParam, Params, Priors, RadialFlow, Record, RecoveryResults,
+    RotationCurveKind, RotationCurveModel, SigmaProfile, SimulatedCatalogue, SpatialDistribution,
+};
@@ -1,289 +1,228 @@
-//! This binary crate allows a user to infer the parameters
-//! of the Galaxy by optimising over its parametric model.
+//! This binary crate provides a command-line
+//! interface to the `pmg` library crate
 
 extern crate alloc;
 
 mod cli;
-mod model;
-mod utils;
 
 use cli::{Args, Goal};
-use model::Model;
+use pmg::{FitLogs, FitSettings, LogWriter, Model};
 
 use alloc::rc::Rc;
 use core::cell::RefCell;
 use core::fmt::{Debug, Display};
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufWriter, Write};
 
 use anyhow::{Context, Result};
 use indoc::formatdoc;
 use indoc::indoc;
 use num::Float;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 
 /// Run the program
 #[allow(clippy::indexing_slicing)]
-#[allow(clippy::missing_panics_doc)]
-#[allow(clippy::needless_range_loop)]
 #[allow(clippy::print_stderr)]
 #[allow(clippy::too_many_lines)]
-#[allow(clippy::unwrap_used)]
 #[allow(clippy::use_debug)]
 pub fn main() -> Result<()> {
-    let args = cli::parse();
+    let args = cli::parse()?;
+
+    fs::create_dir_all(&args.output_dir)
+        .with_context(|| format!("Couldn't create the output directory {:?}", args.output_dir))?;
+    fs::write(
+        args.output_dir.join("resolved_config.toml"),
+        &args.resolved_config,
+    )
+    .with_context(|| "Couldn't write the resolved configuration to a file")?;
 
     match args.goal {
         Goal::Objects => {
             let output_dir = args.output_dir.join("objects");
-            let mut model = Model::<f64>::try_from(&args, output_dir)
+            fs::create_dir_all(&output_dir)
+                .with_context(|| format!("Couldn't create the output directory {output_dir:?}"))?;
+            let mut model = args
+                .try_build_model::<f64>(output_dir)
                 .with_context(|| "Couldn't load the data from the input files")?;
             model.compute_objects();
             model
                 .write_objects_data()
                 .with_context(|| "Couldn't write the model data")?;
         }
+        Goal::Simulate => {
+            let model = args
+                .try_build_model::<f64>(args.output_dir.clone())
+                .with_context(|| "Couldn't load the data from the input files")?;
+            let distribution = args.spatial_distribution()?;
+            let mut rng = ChaCha8Rng::seed_from_u64(args.seed);
+            let catalogue = model
+                .simulate(distribution.as_ref(), &mut rng)
+                .with_context(|| "Couldn't simulate a catalogue")?;
+            model.serialize_to_simulated(&catalogue, args.seed)?;
+        }
+        Goal::Recovery => {
+            let model = args
+                .try_build_model::<f64>(args.output_dir.clone())
+                .with_context(|| "Couldn't load the data from the input files")?;
+            let distribution = args.spatial_distribution()?;
+            let mut rng = ChaCha8Rng::seed_from_u64(args.seed);
+            let results = model
+                .run_recovery(
+                    &args.fit_settings(),
+                    distribution.as_ref(),
+                    args.n_trials,
+                    args.start_jitter,
+                    &mut rng,
+                )
+                .with_context(|| "Couldn't run the injection-recovery campaign")?;
+            model.serialize_to_recovery(&results, args.seed)?;
+        }
         Goal::Fit => {
-            // Prepare several models
-            let mut models = Vec::with_capacity(args.n_max);
-            let mut fit_log_writers = Vec::with_capacity(args.n_max);
-            for i in 0..args.n_max {
-                let n = i + 1;
-                let output_dir = args.output_dir.join(format!("n = {n}"));
+            let model = args
+                .try_build_model::<f64>(args.output_dir.clone())
+                .with_context(|| "Couldn't load the data from the input files")?;
 
-                let model = Model::<f64>::try_from(&args, output_dir)
-                    .with_context(|| "Couldn't load the data from the input files")?;
+            // Prepare the output directories and the logs
+            let n_max = args.fit_settings().n_max;
+            let mut fit_log_writers = Vec::with_capacity(n_max);
+            for n in 1..=n_max {
+                let output_dir = model.degree_output_dir(n);
+                fs::create_dir_all(&output_dir).with_context(|| {
+                    format!("Couldn't create the output directory {output_dir:?}")
+                })?;
 
-                let fit_log_path = model.output_dir.join("fit.log");
+                let fit_log_path = output_dir.join("fit.log");
                 let fit_log_file = File::create(fit_log_path)
                     .with_context(|| "Couldn't create the `fit.log` file")?;
-                let fit_log_writer = Rc::new(RefCell::new(BufWriter::new(fit_log_file)));
+                let fit_log_writer: LogWriter = Rc::new(RefCell::new(BufWriter::new(fit_log_file)));
 
-                models.push(model);
                 fit_log_writers.push(fit_log_writer);
             }
 
             let outliers_log_path = &args.output_dir.join("outliers.log");
             let outliers_log_file = File::create(outliers_log_path)
                 .with_context(|| "Couldn't create the `outliers.log` file")?;
-            let mut outliers_log_writer = BufWriter::new(outliers_log_file);
 
             let errors_log_path = &args.output_dir.join("errors.log");
             let errors_log_file = File::create(errors_log_path)
                 .with_context(|| "Couldn't create the `errors.log` file")?;
-            let errors_log_writer = Rc::new(RefCell::new(BufWriter::new(errors_log_file)));
-
-            let n_data_path = &args.output_dir.join("n.dat");
-            let mut n_data_file =
-                File::create(n_data_path).with_context(|| "Couldn't create the `n.dat` file")?;
-
-            writeln!(
-                outliers_log_writer,
-                "{}",
-                indoc!(
-                    "
-                Outliers
-
-                `m` is the index of the discrepancy (starting from 1), as in the array [V_r, mu_l', mu_b, par_r]."
-                ),
-            )?;
-
-            let best_i = args.n_best - 1;
-            let best_n = args.n_best;
-
-            writeln!(n_data_file, "# Progression of the number of the objects\nn")?;
-            writeln!(n_data_file, "{}", models[best_i].objects.borrow().len())?;
-
-            let mut sample_iteration = 0;
-            for l_stroke in [3, 1] {
-                'samples: loop {
-                    // Fit the parameters for each model
-                    for i in 0..args.n_max {
-                        let n = i + 1;
-
-                        let model = &mut models[i];
-                        let fit_log_writer = &fit_log_writers[i];
-
-                        // Try to fit a model with the specified degree
-                        model
-                            .try_fit_params(n, sample_iteration, l_stroke, fit_log_writer)
-                            .with_context(|| "Couldn't fit the model")?;
-                    }
 
-                    // Check for the outliers via the best model
-                    {
-                        let best_model = &mut models[best_i];
-                        let before_nonoutliers_count = best_model.count_non_outliers();
-
-                        writeln!(
-                            outliers_log_writer,
-                            "\nsample_iteration: {sample_iteration}",
-                        )?;
-                        writeln!(
-                            outliers_log_writer,
-                            "before_nonoutliers_count: {before_nonoutliers_count}"
-                        )?;
-                        writeln!(outliers_log_writer, "best_n: {best_n}")?;
-                        writeln!(outliers_log_writer, "l_stroke: {l_stroke}")?;
-
-                        if args.disable_outliers {
-                            break 'samples;
-                        }
-
-                        let (one_dimensional_outliers, multi_dimensional_outliers) = best_model
-                            .find_outliers(l_stroke)
-                            .with_context(|| "Couldn't check for outliers")?;
-
-                        if one_dimensional_outliers.vec.is_empty()
-                            && multi_dimensional_outliers.vec.is_empty()
-                        {
-                            break 'samples;
-                        }
-
-                        let objects = best_model.objects.borrow();
-                        if !one_dimensional_outliers.vec.is_empty() {
-                            writeln!(
-                                outliers_log_writer,
-                                "\none-dimensional:\nm{s:4}rel_discrepancy{s:1}kappa{s:13}k_005{s:13}i{s:3}source name",
-                                s = " "
-                            )?;
-                            for &(m, i, rel_discrepancy) in &one_dimensional_outliers.vec {
-                                let object = &objects[i];
-                                writeln!(
-                                    outliers_log_writer,
-                                    "{} {rel_discrepancy:>18.15} {:<17.15} {:<17.15} {:<3} {:6} {}",
-                                    m + 1,
-                                    one_dimensional_outliers.kappa,
-                                    one_dimensional_outliers.k_005,
-                                    i + 1,
-                                    object.source.as_ref().unwrap(),
-                                    object.name.as_ref().unwrap(),
-                                )?;
-                            }
-                        }
-                        if !multi_dimensional_outliers.vec.is_empty() {
-                            writeln!(
-                                outliers_log_writer,
-                                "\n{}:\n{s:18}z{s:1}kappa{s:14}k_005{s:14}i{s:3}source name",
-                                if best_model.disable_inner {
-                                    "three-dimensional"
-                                } else {
-                                    "four-dimensional"
-                                },
-                                s = " ",
-                            )?;
-                            for &(i, rel_discrepancy) in &multi_dimensional_outliers.vec {
-                                let object = &objects[i];
-                                writeln!(
-                                    outliers_log_writer,
-                                    "{rel_discrepancy:>19.15} {:<18.15} {:<18.15} {:<3} {:6} {}",
-                                    multi_dimensional_outliers.kappa,
-                                    multi_dimensional_outliers.k_005,
-                                    i + 1,
-                                    object.source.as_ref().unwrap(),
-                                    object.name.as_ref().unwrap(),
-                                )?;
-                            }
-                        }
-
-                        outliers_log_writer.flush()?;
-                    }
-
-                    // Update the outliers
-                    let outliers_mask = models[best_i].get_outliers_mask();
-                    for i in 0..models.len() {
-                        if i != best_i {
-                            let model = &mut models[i];
-                            model.apply_outliers_mask(&outliers_mask);
-                        }
-                    }
-
-                    sample_iteration += 1;
-                }
-
-                outliers_log_writer.flush()?;
-                for fit_log_writer in &fit_log_writers {
-                    fit_log_writer.borrow_mut().flush()?;
-                }
-
-                let l_stroke_n = models[best_i].count_non_outliers();
-                writeln!(n_data_file, "{l_stroke_n}")?;
-
-                for i in 0..args.n_max {
+            let logs = FitLogs {
+                fit: fit_log_writers,
+                outliers: Rc::new(RefCell::new(BufWriter::new(outliers_log_file))),
+                errors: Rc::new(RefCell::new(BufWriter::new(errors_log_file))),
+            };
+
+            let mut rng = ChaCha8Rng::seed_from_u64(args.seed);
+            let fit_settings = FitSettings {
+                with_frozen_profiles: true,
+                ..args.fit_settings()
+            };
+            let results = model.fit_with(&fit_settings, &logs, |l_stroke, n_best, models| {
+                let best_i = n_best - 1;
+                for (i, fitted_model) in models.iter_mut().enumerate() {
                     let n = i + 1;
 
-                    let model = &mut models[i];
-                    if model.fit_params.is_none() {
+                    if fitted_model.fit_params.is_none() {
                         continue;
                     }
 
                     if l_stroke == 1 {
-                        model.l_stroke_1_n = Some(l_stroke_n);
-                    } else {
-                        model.l_stroke_3_n = Some(l_stroke_n);
-                    }
-
-                    model
-                        .try_compute_frozen_profiles(l_stroke)
-                        .with_context(|| "Couldn't compute frozen profiles")?;
-
-                    if args.with_errors {
-                        writeln!(errors_log_writer.borrow_mut(), "n: {n}\n")?;
-                        let res = model
-                            .try_fit_errors(&errors_log_writer, l_stroke)
-                            .with_context(|| "Couldn't compute the errors");
-                        match res {
-                            Ok(_) => {
-                                model.serialize_to_fit_params().with_context(|| {
-                                    "Couldn't write the fitted parameters to a file"
-                                })?;
-                            }
-                            Err(ref err) => {
-                                eprintln!("{err:?}");
-                            }
-                        }
-                    }
-
-                    errors_log_writer.borrow_mut().flush()?;
-
-                    if l_stroke == 1 {
-                        model.compute_covariance_matrix()?;
-                        model.post_fit();
-                        model.write_fit_data()?;
+                        fitted_model
+                            .write_covariance_matrix()
+                            .with_context(|| "Couldn't write the covariance matrix to a file")?;
+                        fitted_model.write_fit_data()?;
 
-                        if !model.disable_inner {
-                            model
+                        if !fitted_model.disable_inner {
+                            fitted_model
                                 .write_parallaxes()
                                 .with_context(|| "Couldn't write the parallaxes to a file")?;
 
-                            if n == best_n {
-                                model.analyze_inner_profiles().with_context(|| {
+                            if n == n_best {
+                                fitted_model.analyze_inner_profiles().with_context(|| {
                                     "Couldn't compute the profiles of the inner targer function"
                                 })?;
                             }
                         }
+
+                        if args.with_influence && n == n_best {
+                            let influences = fitted_model
+                                .compute_influence()
+                                .with_context(|| "Couldn't compute the influence of the objects")?;
+                            fitted_model
+                                .write_influence(&influences)
+                                .with_context(|| "Couldn't write the influence of the objects")?;
+                        }
+
+                        if args.with_mcmc && n == n_best {
+                            let mcmc_results = fitted_model
+                                .sample_posterior(&args.mcmc_settings(), &mut rng)
+                                .with_context(|| "Couldn't sample the posterior distribution")?;
+                            fitted_model
+                                .write_mcmc(&mcmc_results)
+                                .with_context(|| "Couldn't write the results of the sampling")?;
+                        }
                     }
 
-                    write_fit_params_to_plain(&args, &models)
-                        .with_context(|| "Couldn't write to the `fit_params.plain` file")?;
+                    if l_stroke == 3 && fitted_model.errors_fitted {
+                        fitted_model
+                            .serialize_to_fit_params()
+                            .with_context(|| "Couldn't write the fitted parameters to a file")?;
+                    }
                 }
 
+                write_fit_params_to_plain(&args, models)
+                    .with_context(|| "Couldn't write to the `fit_params.plain` file")?;
+
                 if !args.disable_inner && l_stroke == 1 {
-                    write_delta_varpi(&args, &models, l_stroke_n)?;
+                    write_delta_varpi(&args, models, models[best_i].count_non_outliers())?;
                 }
 
-                let best_model = &mut models[best_i];
                 if args.with_conditional_profiles {
-                    let res = best_model
+                    let res = models[best_i]
                         .try_compute_conditional_profiles(l_stroke)
                         .with_context(|| "Couldn't compute the conditional profiles");
                     if let Err(ref err) = res {
                         eprintln!("{err:?}");
                     }
                 }
+
+                Ok(())
+            })?;
+
+            let n_data_path = &args.output_dir.join("n.dat");
+            let mut n_data_file =
+                File::create(n_data_path).with_context(|| "Couldn't create the `n.dat` file")?;
+
+            writeln!(n_data_file, "# Progression of the number of the objects\nn")?;
+            for n_objects in &results.n_objects {
+                writeln!(n_data_file, "{n_objects}")?;
             }
 
-            serialize_n_results(&args, &models)
+            serialize_n_results(&args, &results.models)
                 .with_context(|| "Couldn't serialize the `n` results")?;
+            model
+                .serialize_to_n_selection(&results)
+                .with_context(|| "Couldn't serialize the information criteria")?;
+
+            if let Some(bootstrap_settings) = args.bootstrap_settings() {
+                let bootstrap_results = results
+                    .run_bootstrap(&bootstrap_settings, &mut rng)
+                    .with_context(|| "Couldn't run the bootstrap")?;
+                results
+                    .write_bootstrap(&bootstrap_results)
+                    .with_context(|| "Couldn't write the results of the bootstrap")?;
+            }
+
+            if let Some(n_folds) = args.cv_folds {
+                let cv_results = results
+                    .run_cross_validation(n_folds, &mut rng)
+                    .with_context(|| "Couldn't run the cross-validation")?;
+                model
+                    .serialize_to_cross_validation(&cv_results)
+                    .with_context(|| "Couldn't write the results of the cross-validation")?;
+            }
         }
     }
     Ok(())
@@ -294,7 +233,7 @@ pub fn main() -> Result<()> {
 #[allow(clippy::unwrap_used)]
 fn serialize_n_results<F>(args: &Args, models: &[Model<F>]) -> Result<()>
 where
-    F: Display,
+    F: Float + Display,
 {
     let l_1_file = File::create(args.output_dir.join("L_1.dat"))?;
     let mut l_1_writer = BufWriter::new(l_1_file);
@@ -307,7 +246,10 @@ where
             "
             # Best costs (L_1) as a dependency of the degree
             # of the polynomial of the rotation curve
-            n L_1"
+            #
+            # The cost is split into the negative log likelihood
+            # and the penalty by the priors of the parameters
+            n L_1 likelihood prior"
         )
     )?;
 
@@ -327,10 +269,12 @@ where
         };
 
         let n = i + 1;
-        let l_1 = model.best_cost.as_ref().unwrap();
+        let l_1 = *model.best_cost.as_ref().unwrap();
+        let prior = model.best_prior_cost.unwrap_or_else(F::zero);
+        let likelihood = l_1 - prior;
         let sigma_theta = &model.fit_params.as_ref().unwrap().sigma_theta;
 
-        writeln!(l_1_writer, "{n} {l_1}")?;
+        writeln!(l_1_writer, "{n} {l_1} {likelihood} {prior}")?;
         writeln!(sigma_theta_writer, "{n} {sigma_theta}")?;
     }
 
@@ -2,36 +2,55 @@
 
 extern crate alloc;
 
+mod builder;
 pub mod fit;
 mod io;
 mod objects;
 mod params;
+mod priors;
+mod recovery;
 mod sample_description;
-
-use crate::cli::Args;
-use crate::utils;
-pub use fit::{ProfileType, RotationCurve, Triple, Triples};
-pub use objects::{Object, Objects};
-pub use params::{Params, N_MAX, PARAMS_N, PARAMS_NAMES};
+mod simulate;
+
+// The builder of the model and the trait of the models of the rotation
+// curve are named after the model on purpose: they are used at the top level
+#[allow(clippy::module_name_repetitions)]
+pub use builder::ModelBuilder;
+#[allow(clippy::module_name_repetitions)]
+pub use fit::RotationCurveModel;
+pub use fit::{
+    BootstrapResults, BootstrapSettings, Criterion, FitLogs, FitResults, FitSettings,
+    HessianMethod, LogWriter, McmcResults, McmcSettings, ProfileType, RadialFlow, RotationCurve,
+    RotationCurveKind, SigmaProfile, Triple, Triples,
+};
+pub use io::input::Record;
+pub use objects::{try_read_objects_from, Object, Objects, ObservedCovariance};
+pub use params::{
+    compute_derived, OptionalParam, Params, DERIVED_NAMES, N_MAX, PARAMS_N, PARAMS_NAMES,
+    SIGMA_NAMES,
+};
+pub use priors::{Prior, Priors};
+pub use recovery::{RecoveryFailure, RecoveryResults, RecoverySummary, RecoveryTrial};
+pub use sample_description::try_read_sample_description_from;
+pub use simulate::{SimulatedCatalogue, SpatialDistribution};
 
 use alloc::rc::Rc;
 use core::cell::RefCell;
 use core::fmt::{Debug, Display};
-use core::str::FromStr;
-use std::error::Error;
-use std::fs;
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 use nalgebra::DMatrix;
 use num::Float;
-use serde::{de::DeserializeOwned, Serialize};
+use serde::Serialize;
 
 /// Model of the Galaxy
 #[derive(Debug, Clone, Default)]
 pub struct Model<F> {
     /// Initial model parameters
     pub params: Params<F>,
+    /// Priors of the model parameters
+    pub priors: Priors<F>,
     /// Data objects
     pub objects: Objects<F>,
 
@@ -47,14 +66,22 @@ pub struct Model<F> {
     pub l_stroke_1_n: Option<usize>,
     /// The best value of the cost function
     pub best_cost: Option<F>,
+    /// Contribution of the priors to the best value of the cost function
+    pub best_prior_cost: Option<F>,
     /// Fit of the model (parameters)
     pub fit_params: Option<Params<F>>,
     /// Fit of the model (rotation curve)
     pub fit_rotcurve: Option<RotationCurve<F>>,
     /// Triples
     pub triples: Rc<RefCell<Vec<Triples<F>>>>,
+    /// Hessian matrix
+    pub hessian_matrix: Option<DMatrix<F>>,
+    /// Method of the computation of the Hessian
+    pub hessian_method: Option<HessianMethod>,
     /// Covariance matrix
     pub covariance_matrix: Option<DMatrix<F>>,
+    /// Have the confidence intervals been defined in the last run?
+    pub errors_fitted: bool,
 
     /// Sample description
     pub sample_description: Option<String>,
@@ -63,6 +90,13 @@ pub struct Model<F> {
 }
 
 impl<F> Model<F> {
+    /// Start building a model
+    pub fn builder() -> ModelBuilder<F>
+    where
+        F: Float,
+    {
+        ModelBuilder::default()
+    }
     /// Perform per-object computations
     pub fn compute_objects(&mut self)
     where
@@ -114,60 +148,13 @@ impl<F> Model<F> {
             .with_context(|| "Couldn't write the objects to a file")?;
         self.serialize_to_fit_params()
             .with_context(|| "Couldn't write the fitted parameters to a file")?;
+        self.serialize_to_fit_par_offsets()
+            .with_context(|| "Couldn't write the fitted offsets of the parallaxes to a file")?;
+        self.serialize_to_fit_error_floors()
+            .with_context(|| "Couldn't write the fitted error floors to a file")?;
         self.serialize_to_fit_rotcurve()
             .with_context(|| "Couldn't write the fitted rotation curve to a file")?;
 
         Ok(())
     }
-    /// Create a model from the arguments with a specific degree
-    /// of the polynomial of the rotation curve
-    pub fn try_from(args: &Args, output_dir: PathBuf) -> Result<Self>
-    where
-        F: Float + Debug + Default + DeserializeOwned + FromStr,
-        <F as FromStr>::Err: Error + Send + Sync + 'static,
-    {
-        let mut model = Self {
-            params: Params {
-                r_0: utils::cast(args.r_0)?,
-                omega_0: utils::cast(args.omega_0)?,
-                a: utils::cast(args.a)?,
-                u_sun: utils::cast(args.u_sun)?,
-                v_sun: utils::cast(args.v_sun)?,
-                w_sun: utils::cast(args.w_sun)?,
-                sigma_r_g: utils::cast(args.sigma_r_g)?,
-                sigma_theta: utils::cast(args.sigma_theta)?,
-                sigma_z: utils::cast(args.sigma_z)?,
-                alpha_ngp: utils::cast(args.alpha_ngp)?,
-                delta_ngp: utils::cast(args.delta_ngp)?,
-                theta_sun: utils::cast(args.theta_sun)?,
-                l_ncp: utils::cast(args.l_ncp)?,
-                k: utils::cast(args.k)?,
-                u_sun_standard: utils::cast(args.u_sun_standard)?,
-                v_sun_standard: utils::cast(args.v_sun_standard)?,
-                w_sun_standard: utils::cast(args.w_sun_standard)?,
-                ..Default::default()
-            },
-            output_dir,
-            disable_inner: args.disable_inner,
-            lbfgs_tolerance: utils::cast(args.lbfgs_tolerance)?,
-            ..Default::default()
-        };
-
-        fs::create_dir_all(&model.output_dir).with_context(|| {
-            format!(
-                "Couldn't create the output directory {:?}",
-                &model.output_dir
-            )
-        })?;
-
-        model.try_read_sample_description_from(&args.input)?;
-        model
-            .try_load_data_from(&args.input)
-            .with_context(|| format!("Couldn't load the data from the file {:?}", args.input))?;
-
-        let triple = vec![Triple::<F>::default(); 4];
-        model.triples = Rc::new(RefCell::new(vec![triple; model.objects.borrow().len()]));
-
-        Ok(model)
-    }
 }
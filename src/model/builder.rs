@@ -0,0 +1,125 @@
+//! Model builder
+
+extern crate alloc;
+
+use super::{Model, Object, Params, Priors, RotationCurveModel, Triple};
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use num::Float;
+
+/// Default tolerance of the L-BFGS algorithm
+pub const LBFGS_TOLERANCE: f64 = 1e-15;
+
+/// Builder of the model of the Galaxy
+#[derive(Debug, Clone)]
+pub struct ModelBuilder<F> {
+    /// Initial model parameters
+    params: Option<Params<F>>,
+    /// Priors of the model parameters
+    priors: Priors<F>,
+    /// Data objects
+    objects: Vec<Object<F>>,
+    /// Disable the inner optimization?
+    disable_inner: bool,
+    /// Tolerance of the L-BFGS algorithm
+    lbfgs_tolerance: F,
+    /// Sample description
+    sample_description: Option<String>,
+    /// Output directory
+    output_dir: PathBuf,
+}
+
+impl<F> Default for ModelBuilder<F>
+where
+    F: Float,
+{
+    #[allow(clippy::unwrap_used)]
+    fn default() -> Self {
+        Self {
+            params: None,
+            priors: Priors::default(),
+            objects: Vec::new(),
+            disable_inner: false,
+            lbfgs_tolerance: F::from(LBFGS_TOLERANCE).unwrap(),
+            sample_description: None,
+            output_dir: PathBuf::new(),
+        }
+    }
+}
+
+impl<F> ModelBuilder<F> {
+    /// Set the initial model parameters
+    #[must_use]
+    pub fn params(mut self, params: Params<F>) -> Self {
+        self.params = Some(params);
+        self
+    }
+    /// Set the priors of the model parameters
+    #[must_use]
+    pub fn priors(mut self, priors: Priors<F>) -> Self {
+        self.priors = priors;
+        self
+    }
+    /// Set the data objects
+    #[must_use]
+    pub fn objects(mut self, objects: Vec<Object<F>>) -> Self {
+        self.objects = objects;
+        self
+    }
+    /// Disable the inner optimization?
+    #[must_use]
+    pub const fn disable_inner(mut self, disable_inner: bool) -> Self {
+        self.disable_inner = disable_inner;
+        self
+    }
+    /// Set the tolerance of the L-BFGS algorithm
+    #[must_use]
+    pub fn lbfgs_tolerance(mut self, lbfgs_tolerance: F) -> Self {
+        self.lbfgs_tolerance = lbfgs_tolerance;
+        self
+    }
+    /// Set the sample description
+    #[must_use]
+    pub fn sample_description(mut self, sample_description: Option<String>) -> Self {
+        self.sample_description = sample_description;
+        self
+    }
+    /// Set the output directory
+    ///
+    /// Nothing is written there unless one of the `write_*`
+    /// or `serialize_*` methods of the model is called
+    #[must_use]
+    pub fn output_dir(mut self, output_dir: PathBuf) -> Self {
+        self.output_dir = output_dir;
+        self
+    }
+    /// Build the model
+    pub fn build(self) -> Result<Model<F>>
+    where
+        F: Float + Default,
+    {
+        let params = self
+            .params
+            .ok_or_else(|| anyhow!("The initial parameters of the model weren't specified"))?;
+        params.rotcurve.check_shape(&params)?;
+
+        let triple = vec![Triple::<F>::default(); 4];
+        let triples = vec![triple; self.objects.len()];
+
+        Ok(Model {
+            params,
+            priors: self.priors,
+            objects: Rc::new(RefCell::new(self.objects)),
+            disable_inner: self.disable_inner,
+            lbfgs_tolerance: self.lbfgs_tolerance,
+            triples: Rc::new(RefCell::new(triples)),
+            sample_description: self.sample_description,
+            output_dir: self.output_dir,
+            ..Default::default()
+        })
+    }
+}
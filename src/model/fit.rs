@@ -2,32 +2,66 @@
 
 #![allow(clippy::module_name_repetitions)]
 
+extern crate alloc;
+
+mod bootstrap;
 mod covariance;
+mod cross_validation;
+mod dispersions;
 mod errors;
 mod errors_logger;
 mod fit_logger;
+mod floors;
 mod frozen_outer;
+mod influence;
 mod inner;
+mod mcmc;
+mod offsets;
 mod outer;
 mod outliers;
 mod parallaxes;
 pub mod params;
+mod perturbations;
+mod pipeline;
 mod profiles;
 pub mod rotcurve;
+mod selection;
 mod sigma_outer;
 mod steepest_descent;
 
 use super::io;
-use super::{Model, Object, Objects, Params, PARAMS_N, PARAMS_NAMES};
+use super::{
+    Model, Object, Objects, ObservedCovariance, OptionalParam, Params, Priors, PARAMS_N,
+    SIGMA_NAMES,
+};
 
+pub use bootstrap::{BootstrapResults, BootstrapSettings, BootstrapSummary};
+pub use covariance::HessianMethod;
+pub use cross_validation::{CrossValidationFold, CrossValidationResults, CrossValidationSummary};
+pub use dispersions::{compute_sigmas, SigmaProfile};
 pub use errors::ConfidenceIntervalProblem;
 pub use errors_logger::ErrorsLogger;
 pub use fit_logger::FitLogger;
+pub use floors::{compute_error_floors, ErrorFloor, ANY_SOURCE, ERROR_FLOOR_NAMES};
 pub use frozen_outer::FrozenOuterOptimizationProblem;
+pub use influence::Influence;
 pub use inner::{
-    compute_relative_discrepancy, prepare_inner_problem, InnerOptimizationProblem, Triple, Triples,
+    compute_relative_discrepancy, prepare_inner_problem, prepare_inner_problem_with,
+    InnerOptimizationProblem, Triple, Triples,
 };
+pub use mcmc::{McmcAcceptance, McmcResults, McmcSettings, McmcSummary};
+pub use offsets::{compute_par_offset, ParallaxOffset, PAR_OFFSET_NAMES};
 pub use outer::OuterOptimizationProblem;
+pub use perturbations::RadialFlow;
+pub use pipeline::{FitLogs, FitResults, FitSettings};
 pub use profiles::ProfileType;
-pub use rotcurve::RotationCurve;
+pub use rotcurve::{RotationCurve, RotationCurveKind, RotationCurveModel};
+pub use selection::{compute_n_selection, select_n, Criterion, NSelection};
 pub use sigma_outer::SigmaOuterOptimizationProblem;
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use std::io::Write;
+
+/// Shared writer of a log
+pub type LogWriter = Rc<RefCell<dyn Write>>;
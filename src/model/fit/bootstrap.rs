@@ -0,0 +1,398 @@
+//! Bootstrap of the fitted parameters
+
+use super::io::output;
+use super::{FitResults, FitSettings, Object};
+use crate::model::params::{compute_derived, DERIVED_NAMES};
+use crate::utils::{self, FiniteDiff};
+
+use core::fmt::{Debug, Display};
+use core::iter::{self, Sum};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use anyhow::{ensure, Context, Result};
+use argmin::core::ArgminFloat;
+use argmin_math::{
+    ArgminAdd, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax, ArgminMul, ArgminSignum,
+    ArgminSub, ArgminZeroLike,
+};
+use indoc::formatdoc;
+use itertools::izip;
+use nalgebra::ComplexField;
+use num::Float;
+use numeric_literals::replace_float_literals;
+use rand::Rng;
+use serde::Serialize;
+
+/// Settings of the bootstrap
+#[derive(Debug, Clone)]
+pub struct BootstrapSettings {
+    /// Number of the resampled sets of the objects
+    pub n_samples: usize,
+    /// Redo the checks for outliers on each resampled set?
+    ///
+    /// Otherwise, the outliers found in the main fit are
+    /// excluded, and the checks are disabled in the refits.
+    pub redo_outliers: bool,
+}
+
+/// Percentile intervals of a parameter
+#[derive(Debug, Clone, Serialize)]
+pub struct BootstrapSummary<F> {
+    /// Name of the parameter
+    pub name: &'static str,
+    /// Value from the main fit
+    pub fit: F,
+    /// Median of the bootstrap values
+    pub median: F,
+    /// 2.5th percentile
+    pub q_025: F,
+    /// 15.9th percentile
+    pub q_159: F,
+    /// 84.1st percentile
+    pub q_841: F,
+    /// 97.5th percentile
+    pub q_975: F,
+    /// Upper uncertainty, `q_841 - fit`
+    pub ep: F,
+    /// Lower uncertainty, `fit - q_159`
+    pub em: F,
+}
+
+/// Results of the bootstrap
+#[derive(Debug, Clone)]
+pub struct BootstrapResults<F> {
+    /// Fitted parameters, extended with the derived ones
+    /// (one per resampled set, for each degree starting from 1)
+    pub samples: Vec<Vec<Vec<F>>>,
+    /// Percentile intervals (for each degree starting from 1)
+    pub summaries: Vec<Vec<BootstrapSummary<F>>>,
+    /// Number of the failed refits
+    pub n_failed: usize,
+    /// Settings of the bootstrap
+    pub settings: BootstrapSettings,
+}
+
+/// Compute the percentile intervals of a parameter
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+fn summarize<F>(name: &'static str, fit: F, values: &[F]) -> BootstrapSummary<F>
+where
+    F: Float + Debug,
+{
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let q_159 = utils::quantile(&sorted, 0.158_655_254);
+    let q_841 = utils::quantile(&sorted, 0.841_344_746);
+    BootstrapSummary {
+        name,
+        fit,
+        median: utils::quantile(&sorted, 0.5),
+        q_025: utils::quantile(&sorted, 0.025),
+        q_159,
+        q_841,
+        q_975: utils::quantile(&sorted, 0.975),
+        ep: q_841 - fit,
+        em: fit - q_159,
+    }
+}
+
+/// Extend the point in the parameter space with the derived parameters
+fn extend_with_derived<F>(mut p: Vec<F>) -> Vec<F>
+where
+    F: Float,
+{
+    let derived = compute_derived(&p);
+    p.extend(derived);
+    p
+}
+
+impl<F> FitResults<F>
+where
+    F: Float
+        + Debug
+        + Default
+        + Display
+        + Sync
+        + Send
+        + Sum
+        + ArgminFloat
+        + ArgminL2Norm<F>
+        + ArgminSub<F, F>
+        + ArgminAdd<F, F>
+        + ArgminDot<F, F>
+        + ArgminMul<F, F>
+        + ArgminZeroLike
+        + ArgminMul<Vec<F>, Vec<F>>
+        + ComplexField,
+    Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminSub<F, Vec<F>>,
+    Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminAdd<F, Vec<F>>,
+    Vec<F>: ArgminMul<F, Vec<F>>,
+    Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminL1Norm<F>,
+    Vec<F>: ArgminSignum,
+    Vec<F>: ArgminMinMax,
+    Vec<F>: ArgminDot<Vec<F>, F>,
+    Vec<F>: ArgminL2Norm<F>,
+    Vec<F>: FiniteDiff<F>,
+{
+    /// Refit the models on the sets of the objects resampled with
+    /// replacement and compute the percentile intervals of the parameters
+    ///
+    /// Source: Efron, Tibshirani (1993)
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::print_stderr)]
+    #[allow(clippy::use_debug)]
+    pub fn run_bootstrap<R>(
+        &self,
+        settings: &BootstrapSettings,
+        rng: &mut R,
+    ) -> Result<BootstrapResults<F>>
+    where
+        R: Rng,
+    {
+        ensure!(
+            settings.n_samples >= 2,
+            "There should be at least two resampled sets"
+        );
+
+        let best_model = self.best_model();
+        let pool: Vec<Object<F>> = best_model
+            .objects
+            .borrow()
+            .iter()
+            .filter(|object| settings.redo_outliers || !object.outlier)
+            .cloned()
+            .map(|mut object| {
+                object.outlier = false;
+                object
+            })
+            .collect();
+        ensure!(!pool.is_empty(), "There are no objects to resample");
+
+        let fit_settings = FitSettings {
+            n_best: self.n_best,
+            n_best_criterion: None,
+            n_max: self.models.len(),
+            with_errors: false,
+            with_frozen_profiles: false,
+            disable_outliers: !settings.redo_outliers,
+        };
+
+        let mut samples = vec![Vec::with_capacity(settings.n_samples); self.models.len()];
+        let mut n_failed = 0;
+        for sample in 1..=settings.n_samples {
+            let objects = iter::repeat_with(|| pool[rng.gen_range(0..pool.len())].clone())
+                .take(pool.len())
+                .collect();
+            let model = best_model.with_objects(objects);
+
+            let results = match model.fit(&fit_settings) {
+                Ok(results) => results,
+                Err(err) => {
+                    eprintln!("Bootstrap sample #{sample} failed: {err:?}");
+                    n_failed += 1;
+                    continue;
+                }
+            };
+
+            for (i, fitted_model) in results.models.iter().enumerate() {
+                let n = i + 1;
+                if let Some(ref fit_params) = fitted_model.fit_params {
+                    samples[i].push(extend_with_derived(fit_params.to_vec(n)));
+                }
+            }
+        }
+
+        ensure!(
+            settings.n_samples - n_failed >= 2,
+            "There should be at least two successful refits"
+        );
+
+        let mut summaries = Vec::with_capacity(self.models.len());
+        for (i, (model, degree_samples)) in self.models.iter().zip(&samples).enumerate() {
+            let n = i + 1;
+            let (Some(fit_params), false) = (model.fit_params.as_ref(), degree_samples.is_empty())
+            else {
+                summaries.push(Vec::new());
+                continue;
+            };
+            let fit_p = extend_with_derived(fit_params.to_vec(n));
+            let names = fit_params.names(n);
+            let free_names = names.iter().take(fit_p.len() - DERIVED_NAMES.len());
+            let degree_summaries = free_names
+                .chain(DERIVED_NAMES.iter())
+                .zip(fit_p)
+                .enumerate()
+                .map(|(j, (&name, fit))| {
+                    let values: Vec<F> = degree_samples.iter().map(|p| p[j]).collect();
+                    summarize(name, fit, &values)
+                })
+                .collect();
+            summaries.push(degree_summaries);
+        }
+
+        Ok(BootstrapResults {
+            samples,
+            summaries,
+            n_failed,
+            settings: settings.clone(),
+        })
+    }
+}
+
+impl<F> FitResults<F> {
+    /// Write the results of the bootstrap to the output
+    /// directories of the models with the respective degrees
+    pub fn write_bootstrap(&self, results: &BootstrapResults<F>) -> Result<()>
+    where
+        F: Display + Serialize,
+    {
+        let n_samples = results.settings.n_samples;
+        let n_failed = results.n_failed;
+        let outliers = if results.settings.redo_outliers {
+            "redone on each resampled set"
+        } else {
+            "reused from the main fit"
+        };
+        for (model, samples, summaries) in izip!(&self.models, &results.samples, &results.summaries)
+        {
+            if summaries.is_empty() {
+                continue;
+            }
+
+            // Write the samples
+            let samples_path = &model.output_dir.join("bootstrap_samples.dat");
+            let samples_file = File::create(samples_path)
+                .with_context(|| "Couldn't create the `bootstrap_samples.dat` file")?;
+            let mut samples_writer = BufWriter::new(samples_file);
+            writeln!(
+                samples_writer,
+                "{}",
+                formatdoc!(
+                    "
+                    # Fitted parameters on the resampled sets of the objects
+                    #
+                    # Number of the resampled sets: {n_samples}
+                    # Number of the failed refits: {n_failed}
+                    # Outliers: {outliers}
+                    #"
+                )
+            )?;
+            write!(samples_writer, "sample")?;
+            for summary in summaries {
+                write!(samples_writer, " {}", summary.name)?;
+            }
+            writeln!(samples_writer)?;
+            for (k, p) in samples.iter().enumerate() {
+                write!(samples_writer, "{}", k + 1)?;
+                for x in p {
+                    write!(samples_writer, " {x}")?;
+                }
+                writeln!(samples_writer)?;
+            }
+            samples_writer.flush()?;
+
+            // Write the percentile intervals
+            let header = formatdoc!(
+                "
+                # Percentile intervals of the parameters from the bootstrap
+                #
+                # Descriptions:
+                #
+                #  1 name: Name of the parameter
+                #  2 fit: Value from the main fit
+                #  3 median: Median of the bootstrap values
+                #  4 q_025: 2.5th percentile
+                #  5 q_159: 15.9th percentile
+                #  6 q_841: 84.1st percentile
+                #  7 q_975: 97.5th percentile
+                #  8 ep: Upper uncertainty, `q_841 - fit`
+                #  9 em: Lower uncertainty, `fit - q_159`
+                #
+                # Number of the resampled sets: {n_samples}
+                # Number of the failed refits: {n_failed}
+                # Outliers: {outliers}
+                #
+                "
+            );
+            output::serialize_to(&model.output_dir, "bootstrap", &header, summaries)
+                .with_context(|| "Couldn't write the percentile intervals")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use super::{Model, Params};
+#[cfg(test)]
+use crate::model::simulate::{simulate_test_objects, test_params};
+#[cfg(test)]
+use rand::SeedableRng;
+#[cfg(test)]
+use rand_chacha::ChaCha8Rng;
+#[cfg(test)]
+use rand_distr::StandardNormal;
+
+#[test]
+#[allow(clippy::indexing_slicing)]
+fn test() -> Result<()> {
+    // The percentiles of the standard normal distribution should be recovered
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let values: Vec<f64> = iter::repeat_with(|| rng.sample(StandardNormal))
+        .take(10_000)
+        .collect();
+    let summary = summarize("x", 0., &values);
+    for (q, expected) in [
+        (summary.q_025, -1.96),
+        (summary.q_159, -1.),
+        (summary.median, 0.),
+        (summary.q_841, 1.),
+        (summary.q_975, 1.96),
+    ] {
+        ensure!(
+            Float::abs(q - expected) < 0.05,
+            "The percentile should be close to {expected}: {q}"
+        );
+    }
+    // Refit a small catalogue with a fixed parameter
+    let params = Params {
+        fixed: vec!["A"],
+        ..test_params()
+    };
+    let objects = simulate_test_objects(&params, 30, 0)?;
+    let model = Model::builder()
+        .params(params.clone())
+        .objects(objects)
+        .disable_inner(true)
+        .build()?;
+    let results = model.fit(&FitSettings {
+        n_max: 1,
+        disable_outliers: true,
+        ..Default::default()
+    })?;
+    let settings = BootstrapSettings {
+        n_samples: 3,
+        redo_outliers: false,
+    };
+    let bootstrap = results.run_bootstrap(&settings, &mut rng)?;
+    ensure!(bootstrap.n_failed == 0);
+    ensure!(bootstrap.samples[0].len() == settings.n_samples);
+    // The percentiles should be ordered, and the fixed parameter shouldn't vary
+    for parameter_summary in &bootstrap.summaries[0] {
+        ensure!(
+            parameter_summary.q_025 <= parameter_summary.q_159
+                && parameter_summary.q_159 <= parameter_summary.median
+                && parameter_summary.median <= parameter_summary.q_841
+                && parameter_summary.q_841 <= parameter_summary.q_975,
+            "The percentiles of `{}` should be ordered",
+            parameter_summary.name
+        );
+    }
+    ensure!(bootstrap.samples[0]
+        .iter()
+        .all(|p| (p[2] - params.a).abs() < f64::EPSILON));
+    Ok(())
+}
@@ -2,8 +2,8 @@
 
 extern crate alloc;
 
+use super::outer::compute_lifted_cost;
 use super::{Model, OuterOptimizationProblem};
-use crate::model::PARAMS_NAMES;
 use crate::utils::FiniteDiff;
 
 use core::fmt::{Debug, Display};
@@ -17,12 +17,44 @@ use argmin_math::{
     ArgminAdd, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax, ArgminMul, ArgminSignum,
     ArgminSub, ArgminZeroLike,
 };
+use autodiff::{F as Dual, FT};
+use itertools::izip;
 use nalgebra::{ComplexField, DMatrix};
 use num::Float;
 use numeric_literals::replace_float_literals;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+/// Number of the rows in the tableau of the Richardson extrapolation
+const N_TAB: usize = 6;
+/// Factor by which the step is shrunk between the rows of the tableau
+const STEP_FACTOR: f64 = 1.4;
+/// Factor by which the error may grow before the extrapolation is stopped
+const SAFE_FACTOR: f64 = 2.;
+
+/// Method of the computation of the Hessian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HessianMethod {
+    /// Nested forward-mode automatic differentiation (hyper-dual numbers)
+    HyperDual,
+    /// Central differences of the gradient with the Richardson extrapolation
+    Richardson,
+}
+
+impl Display for HessianMethod {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let string = match *self {
+            Self::HyperDual => "hyper-dual numbers",
+            Self::Richardson => "Richardson extrapolation",
+        };
+        write!(f, "{string}")
+    }
+}
 
 impl<F> Model<F> {
     /// Compute the covariance matrix
+    ///
+    /// The Hessian is exact if the inner optimization is disabled.
+    /// Otherwise, the analytic gradient is differentiated numerically.
     #[allow(clippy::indexing_slicing)]
     #[allow(clippy::unwrap_in_result)]
     #[allow(clippy::unwrap_used)]
@@ -57,69 +89,138 @@ impl<F> Model<F> {
         Vec<F>: ArgminL2Norm<F>,
         Vec<F>: FiniteDiff<F>,
     {
-        let covariance_plain_path = &self.output_dir.join("covariance.plain");
-        let covariance_plain_file = File::create(covariance_plain_path)
-            .with_context(|| "Couldn't create the `covariance.plain` file")?;
-        let mut covariance_plain_writer = BufWriter::new(covariance_plain_file);
-
-        let covariance_dat_path = &self.output_dir.join("covariance.dat");
-        let covariance_dat_file = File::create(covariance_dat_path)
-            .with_context(|| "Couldn't create the `covariance.dat` file")?;
-        let mut covariance_dat_writer = BufWriter::new(covariance_dat_file);
-
         let n = self.n.unwrap();
 
         let problem = OuterOptimizationProblem {
-            disable_inner: false,
+            disable_inner: self.disable_inner,
             objects: &self.objects,
             params: &self.params,
+            priors: &self.priors,
             triples: &self.triples,
             output_dir: &self.output_dir,
         };
 
-        let best_p = self.fit_params.as_ref().unwrap().to_vec(n, false);
+        let best_p = self.fit_params.as_ref().unwrap().to_vec(n);
         let m = best_p.len();
 
-        let hessian_vec = compute_hessian(&problem, &best_p)?;
+        let (hessian_vec, hessian_method) = if self.disable_inner {
+            (
+                compute_hyper_dual_hessian(&problem, &best_p)?,
+                HessianMethod::HyperDual,
+            )
+        } else {
+            (
+                compute_richardson_hessian(&problem, &best_p)?,
+                HessianMethod::Richardson,
+            )
+        };
+        let hessian_matrix = DMatrix::from_vec(m, m, hessian_vec);
+
+        // Only the block of the free parameters is inverted,
+        // the fixed parameters get zero rows and columns
+        let free_indices: Vec<usize> = self
+            .params
+            .frozen_mask(n, false)
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &frozen)| (!frozen).then_some(i))
+            .collect();
+        let free_covariance_matrix = hessian_matrix
+            .select_rows(&free_indices)
+            .select_columns(&free_indices)
+            .try_inverse()
+            .ok_or(anyhow!("Couldn't compute the inverse of the Hessian"))?;
+        let mut covariance_matrix = DMatrix::<F>::zeros(m, m);
+        for (j, &free_j) in free_indices.iter().enumerate() {
+            for (i, &free_i) in free_indices.iter().enumerate() {
+                covariance_matrix[(free_i, free_j)] = free_covariance_matrix[(i, j)];
+            }
+        }
+
+        self.hessian_matrix = Some(hessian_matrix);
+        self.hessian_method = Some(hessian_method);
+        self.covariance_matrix = Some(covariance_matrix);
+
+        Ok(())
+    }
+    /// Write the Hessian and the covariance matrix
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    pub fn write_covariance_matrix(&self) -> Result<()>
+    where
+        F: Float + Debug + Display + ComplexField,
+    {
+        let covariance_plain_path = &self.output_dir.join("covariance.plain");
+        let covariance_plain_file = File::create(covariance_plain_path)
+            .with_context(|| "Couldn't create the `covariance.plain` file")?;
+        let mut covariance_plain_writer = BufWriter::new(covariance_plain_file);
+
+        let covariance_dat_path = &self.output_dir.join("covariance.dat");
+        let covariance_dat_file = File::create(covariance_dat_path)
+            .with_context(|| "Couldn't create the `covariance.dat` file")?;
+        let mut covariance_dat_writer = BufWriter::new(covariance_dat_file);
+
+        let hessian_matrix = self.hessian_matrix.as_ref().unwrap();
+        let covariance_matrix = self.covariance_matrix.as_ref().unwrap();
+        let m = hessian_matrix.nrows();
+        let names = self.params.names(self.n.unwrap());
 
         writeln!(covariance_plain_writer, "H: ")?;
         write!(covariance_plain_writer, "{:11}", " ")?;
-        for name in PARAMS_NAMES.iter().take(m) {
+        for name in names.iter().take(m) {
             write!(covariance_plain_writer, " {name:>20}")?;
         }
         writeln!(covariance_plain_writer)?;
         for j in 0..m {
-            write!(covariance_plain_writer, "{:>11}", PARAMS_NAMES[j])?;
+            write!(covariance_plain_writer, "{:>11}", names[j])?;
             for i in 0..m {
                 write!(
                     covariance_plain_writer,
                     " {:>20.15}",
-                    hessian_vec[i + j * m]
+                    hessian_matrix[(i, j)]
                 )?;
             }
             writeln!(covariance_plain_writer)?;
         }
 
-        let hessian_matrix = DMatrix::from_vec(m, m, hessian_vec);
-
-        writeln!(covariance_plain_writer, "\nminors: ")?;
-        for k in 2..=m {
-            let determinant = hessian_matrix.view((0, 0), (k, k)).determinant();
-            writeln!(covariance_plain_writer, "{k:>2}: {determinant:.15}")?;
+        // The eigenvalues are computed in double precision
+        // since it's just a diagnostic of the Hessian
+        let eigenvalues = hessian_matrix
+            .map(|x| x.to_f64().unwrap())
+            .symmetric_eigenvalues();
+        let min_eigenvalue = eigenvalues.min();
+        let max_eigenvalue = eigenvalues.max();
+        writeln!(
+            covariance_plain_writer,
+            "\nmethod: {}",
+            self.hessian_method.unwrap()
+        )?;
+        writeln!(
+            covariance_plain_writer,
+            "eigenvalues: from {min_eigenvalue:.15e} to {max_eigenvalue:.15e}"
+        )?;
+        if min_eigenvalue > 0. {
+            writeln!(
+                covariance_plain_writer,
+                "condition number: {:.15e}",
+                max_eigenvalue / min_eigenvalue
+            )?;
+        } else {
+            writeln!(
+                covariance_plain_writer,
+                "condition number: the Hessian is not positive definite"
+            )?;
         }
 
-        let covariance_matrix = hessian_matrix
-            .try_inverse()
-            .ok_or(anyhow!("Couldn't compute the inverse of the Hessian"))?;
-
         writeln!(covariance_plain_writer, "\nC: ")?;
         write!(covariance_plain_writer, "{:11}", " ")?;
-        for name in PARAMS_NAMES.iter().take(m) {
+        for name in names.iter().take(m) {
             write!(covariance_plain_writer, " {name:>20}")?;
         }
         writeln!(covariance_plain_writer)?;
         for (j, row) in covariance_matrix.row_iter().enumerate() {
-            write!(covariance_plain_writer, "{:>11}", PARAMS_NAMES[j])?;
+            write!(covariance_plain_writer, "{:>11}", names[j])?;
             for c in row.iter() {
                 write!(covariance_plain_writer, " {c:>20.15}")?;
             }
@@ -131,11 +232,7 @@ impl<F> Model<F> {
 
         writeln!(covariance_plain_writer, "\nerrors: ")?;
         for (i, error) in errors.iter().enumerate() {
-            writeln!(
-                covariance_plain_writer,
-                "{:>11}: {error:19.15}",
-                PARAMS_NAMES[i]
-            )?;
+            writeln!(covariance_plain_writer, "{:>11}: {error:19.15}", names[i])?;
         }
 
         writeln!(
@@ -143,36 +240,42 @@ impl<F> Model<F> {
             "# Linear correlation coefficients, written by rows\nr"
         )?;
 
-        let r_matrix = covariance_matrix.clone();
         writeln!(covariance_plain_writer, "\nR: ")?;
         write!(covariance_plain_writer, "{:11}", " ")?;
-        for name in PARAMS_NAMES.iter().take(m) {
+        for name in names.iter().take(m) {
             write!(covariance_plain_writer, " {name:>20}")?;
         }
         writeln!(covariance_plain_writer)?;
-        for (j, row) in r_matrix.row_iter().enumerate() {
-            write!(covariance_plain_writer, "{:>11}", PARAMS_NAMES[j])?;
+        for (j, row) in covariance_matrix.row_iter().enumerate() {
+            write!(covariance_plain_writer, "{:>11}", names[j])?;
             for (i, c) in row.iter().enumerate() {
-                let r = *c / errors[i] / errors[j];
+                // The fixed parameters aren't correlated with anything
+                let r = if errors[i] > F::zero() && errors[j] > F::zero() {
+                    *c / errors[i] / errors[j]
+                } else {
+                    F::zero()
+                };
                 write!(covariance_plain_writer, " {r:>20.15}")?;
                 writeln!(covariance_dat_writer, "{r}")?;
             }
             writeln!(covariance_plain_writer)?;
         }
 
-        self.covariance_matrix = Some(covariance_matrix);
-
         Ok(())
     }
 }
 
-/// Compute the Hessian at the best point
+/// Compute the Hessian at the best point via the nested
+/// forward-mode automatic differentiation (hyper-dual numbers)
+///
+/// This is only valid if the inner optimization is disabled.
 #[allow(clippy::indexing_slicing)]
-#[allow(clippy::similar_names)]
 #[allow(clippy::unwrap_in_result)]
 #[allow(clippy::unwrap_used)]
-#[replace_float_literals(F::from(literal).unwrap())]
-fn compute_hessian<F>(problem: &OuterOptimizationProblem<F>, best_p: &Vec<F>) -> Result<Vec<F>>
+fn compute_hyper_dual_hessian<F>(
+    problem: &OuterOptimizationProblem<F>,
+    best_p: &[F],
+) -> Result<Vec<F>>
 where
     F: Float
         + Debug
@@ -203,48 +306,272 @@ where
     Vec<F>: FiniteDiff<F>,
 {
     let m = best_p.len();
-    let m_sq = m * m;
-    let mut hessian_vec = vec![0.; m_sq];
+    let mut fit_params = problem.params.clone();
+    fit_params.update_with(best_p);
+    // Lift a constant to the hyper-dual numbers
+    let lift = |x: F| Dual::cst(FT::<F>::cst(x));
+    // Compute the per-object Hessians
+    let hessians = problem
+        .objects
+        .borrow()
+        .par_iter()
+        .filter(|object| !object.outlier)
+        .map(|object| -> Result<Vec<F>> {
+            // These don't depend on the parameters being optimized
+            let observed = object.compute_observed_covariance(&fit_params);
+
+            let mut hessian = vec![F::zero(); m * m];
+            for j in 0..m {
+                for i in 0..=j {
+                    // The value part carries the derivative by the
+                    // `i`-th parameter, the derivative part by the `j`-th
+                    let hyper_dual_p: Vec<FT<FT<F>>> = best_p
+                        .iter()
+                        .enumerate()
+                        .map(|(k, &x)| {
+                            let d_i = if k == i { F::one() } else { F::zero() };
+                            let d_j = if k == j { F::one() } else { F::zero() };
+                            Dual::new(FT::<F>::new(x, d_i), FT::<F>::new(d_j, F::zero()))
+                        })
+                        .collect();
+                    let mut hyper_dual_params = problem.params.map(lift);
+                    hyper_dual_params.update_with(&hyper_dual_p);
+                    let cost =
+                        compute_lifted_cost(object, &hyper_dual_params, observed, None, lift)?;
+                    let second_derivative = cost.deriv().deriv();
+                    hessian[i + j * m] = second_derivative;
+                    hessian[j + i * m] = second_derivative;
+                }
+            }
+            Ok(hessian)
+        })
+        .collect::<Result<Vec<Vec<F>>>>()?;
+    // We do the summing sequentially because
+    // floating-point arithmetic is not associative
+    let mut hessian_vec: Vec<F> = (0..m * m)
+        .map(|k| hessians.iter().map(|hessian| hessian[k]).sum())
+        .collect();
+    // Add the penalties of the priors
+    let penalty_diagonal = problem
+        .priors
+        .penalty_hessian_diagonal(&problem.params.names_of(m), best_p);
+    for (j, second_derivative) in penalty_diagonal.into_iter().enumerate() {
+        hessian_vec[j + j * m] = hessian_vec[j + j * m] + second_derivative;
+    }
+    Ok(hessian_vec)
+}
 
-    let h = 1e-5;
+/// Compute the Hessian at the best point via the central differences of
+/// the gradient with the Richardson extrapolation: the step is shrunk until
+/// the estimate of the error of the extrapolation stops decreasing
+///
+/// Sources: Ridders (1982); Press et al. (2007)
+#[allow(clippy::indexing_slicing)]
+#[allow(clippy::unwrap_in_result)]
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+fn compute_richardson_hessian<F>(
+    problem: &OuterOptimizationProblem<F>,
+    best_p: &[F],
+) -> Result<Vec<F>>
+where
+    F: Float
+        + Debug
+        + Default
+        + Display
+        + Sum
+        + Sync
+        + Send
+        + ArgminFloat
+        + ArgminL2Norm<F>
+        + ArgminSub<F, F>
+        + ArgminAdd<F, F>
+        + ArgminDot<F, F>
+        + ArgminMul<F, F>
+        + ArgminZeroLike
+        + ArgminMul<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminSub<F, Vec<F>>,
+    Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminAdd<F, Vec<F>>,
+    Vec<F>: ArgminMul<F, Vec<F>>,
+    Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminL1Norm<F>,
+    Vec<F>: ArgminSignum,
+    Vec<F>: ArgminMinMax,
+    Vec<F>: ArgminDot<Vec<F>, F>,
+    Vec<F>: ArgminL2Norm<F>,
+    Vec<F>: FiniteDiff<F>,
+{
+    let m = best_p.len();
+    let mut hessian_vec = vec![0.; m * m];
 
-    // The matrix is symmetric here since
-    // we're using the finite differences
+    let step_factor = F::from(STEP_FACTOR).unwrap();
+    let step_factor_sq = step_factor * step_factor;
+    let safe_factor = F::from(SAFE_FACTOR).unwrap();
+    let max_abs_diff = |a: &[F], b: &[F]| {
+        izip!(a, b).fold(0., |acc: F, (&x, &y)| Float::max(acc, Float::abs(x - y)))
+    };
 
     for j in 0..m {
-        for i in 0..m {
-            let mut p = best_p.clone();
-            hessian_vec[i + j * m] = if i == j {
-                p[i] = best_p[i] + h;
-                let plus_cost = problem.inner_cost(&p, false)?;
-
-                p[i] = best_p[i] - h;
-                let minus_cost = problem.inner_cost(&p, false)?;
+        // Compute a column of the Hessian via the central differences
+        let column_diff = |h: F| -> Result<Vec<F>> {
+            let mut p = best_p.to_vec();
+            p[j] = best_p[j] + h;
+            let plus_gradient = problem.inner_gradient(&p)?;
+            p[j] = best_p[j] - h;
+            let minus_gradient = problem.inner_gradient(&p)?;
+            Ok(izip!(plus_gradient, minus_gradient)
+                .map(|(plus, minus)| (plus - minus) / (2. * h))
+                .collect())
+        };
 
-                let best_cost = problem.inner_cost(best_p, false)?;
+        // The initial step is relatively large
+        let mut h = 1e-2 * Float::max(Float::abs(best_p[j]), 1.);
+        let mut tableau: Vec<Vec<Vec<F>>> = Vec::with_capacity(N_TAB);
+        let mut best_column = column_diff(h)?;
+        let mut error = F::infinity();
+        tableau.push(vec![best_column.clone()]);
+        for k in 1..N_TAB {
+            h = h / step_factor;
+            let mut row = vec![column_diff(h)?];
+            let mut factor = step_factor_sq;
+            for l in 1..=k {
+                let extrapolated: Vec<F> = izip!(&row[l - 1], &tableau[k - 1][l - 1])
+                    .map(|(&a, &b)| (a * factor - b) / (factor - 1.))
+                    .collect();
+                factor = factor * step_factor_sq;
+                let error_estimate = Float::max(
+                    max_abs_diff(&extrapolated, &row[l - 1]),
+                    max_abs_diff(&extrapolated, &tableau[k - 1][l - 1]),
+                );
+                if error_estimate <= error {
+                    error = error_estimate;
+                    best_column.clone_from(&extrapolated);
+                }
+                row.push(extrapolated);
+            }
+            // Stop if the higher order makes the error significantly worse
+            let worse = max_abs_diff(&row[k], &tableau[k - 1][k - 1]) >= safe_factor * error;
+            tableau.push(row);
+            if worse {
+                break;
+            }
+        }
 
-                (plus_cost - 2. * best_cost + minus_cost) / h.powi(2)
-            } else {
-                p[i] = best_p[i] + h;
-                p[j] = best_p[j] + h;
-                let pp_cost = problem.inner_cost(&p, false)?;
+        for (i, value) in best_column.into_iter().enumerate() {
+            hessian_vec[i + j * m] = value;
+        }
+    }
 
-                p[i] = best_p[i] + h;
-                p[j] = best_p[j] - h;
-                let pm_cost = problem.inner_cost(&p, false)?;
+    // Make the matrix symmetric
+    for j in 0..m {
+        for i in 0..j {
+            let value = (hessian_vec[i + j * m] + hessian_vec[j + i * m]) / 2.;
+            hessian_vec[i + j * m] = value;
+            hessian_vec[j + i * m] = value;
+        }
+    }
 
-                p[i] = best_p[i] - h;
-                p[j] = best_p[j] + h;
-                let mp_cost = problem.inner_cost(&p, false)?;
+    Ok(hessian_vec)
+}
 
-                p[i] = best_p[i] - h;
-                p[j] = best_p[j] - h;
-                let mm_cost = problem.inner_cost(&p, false)?;
+#[cfg(test)]
+use super::{ParallaxOffset, Params, Priors};
+#[cfg(test)]
+use crate::model::simulate::{simulate_test_objects, test_params};
+#[cfg(test)]
+use crate::model::{OptionalParam, Prior};
+#[cfg(test)]
+use anyhow::ensure;
 
-                (pp_cost - pm_cost - mp_cost + mm_cost) / (4. * h.powi(2))
+#[test]
+#[allow(clippy::indexing_slicing)]
+#[allow(clippy::unwrap_used)]
+fn test() -> Result<()> {
+    let true_params = test_params();
+    let objects = simulate_test_objects(&true_params, 10, 0)?;
+    // Enable an extra parameter and fix one of the main ones
+    let params = Params {
+        fitted: vec![OptionalParam::ZSun],
+        z_sun: 20.,
+        par_offsets: vec![ParallaxOffset {
+            source: "Reid".to_owned(),
+            delta_varpi: 0.01,
+            ..Default::default()
+        }],
+        fixed: vec!["A"],
+        ..true_params
+    };
+    let priors = Priors {
+        vec: vec![(
+            "omega_0",
+            Prior::Normal {
+                mean: 28.,
+                std_dev: 1.,
+            },
+        )],
+    };
+    let mut model = Model::builder()
+        .params(params.clone())
+        .priors(priors)
+        .objects(objects)
+        .disable_inner(true)
+        .build()?;
+    model.compute_objects();
+    // With the inner optimization disabled, the exact Hessian
+    // should match the extrapolated differences of the gradient
+    let n = 1;
+    let best_p = params.to_vec(n);
+    let m = best_p.len();
+    let problem = OuterOptimizationProblem {
+        disable_inner: true,
+        objects: &model.objects,
+        params: &model.params,
+        priors: &model.priors,
+        triples: &model.triples,
+        output_dir: &model.output_dir,
+    };
+    let hyper_dual_hessian = compute_hyper_dual_hessian(&problem, &best_p)?;
+    let richardson_hessian = compute_richardson_hessian(&problem, &best_p)?;
+    let scale = hyper_dual_hessian
+        .iter()
+        .fold(0., |acc: f64, x| Float::max(acc, Float::abs(*x)));
+    for (x, y) in izip!(&hyper_dual_hessian, &richardson_hessian) {
+        ensure!(
+            Float::abs(x - y) < 1e-9 * scale,
+            "The elements of the Hessians should match: {x} vs. {y}"
+        );
+    }
+    // Only the free block should be inverted, and
+    // the fixed parameter should have no covariances
+    model.n = Some(n);
+    model.fit_params = Some(params.clone());
+    model.compute_covariance_matrix()?;
+    ensure!(model.hessian_method == Some(HessianMethod::HyperDual));
+    let hessian_matrix = model.hessian_matrix.as_ref().unwrap();
+    let covariance_matrix = model.covariance_matrix.as_ref().unwrap();
+    let frozen_mask = params.frozen_mask(n, false);
+    let fixed = frozen_mask.iter().position(|&frozen| frozen).unwrap();
+    ensure!(hessian_matrix[(fixed, fixed)] > 0.);
+    for i in 0..m {
+        ensure!(covariance_matrix[(i, fixed)] == 0. && covariance_matrix[(fixed, i)] == 0.);
+    }
+    for (i, &frozen_i) in frozen_mask.iter().enumerate() {
+        for (j, &frozen_j) in frozen_mask.iter().enumerate() {
+            if frozen_i || frozen_j {
+                continue;
             }
+            let product: f64 = izip!(&frozen_mask, 0..)
+                .filter(|&(&frozen, _)| !frozen)
+                .map(|(_, k)| covariance_matrix[(i, k)] * hessian_matrix[(k, j)])
+                .sum();
+            let expected = if i == j { 1. } else { 0. };
+            ensure!(
+                Float::abs(product - expected) < 1e-9,
+                "The free block should be inverted: {product} at ({i}, {j})"
+            );
         }
     }
-
-    Ok(hessian_vec)
+    Ok(())
 }
@@ -0,0 +1,420 @@
+//! K-fold cross-validation
+
+extern crate alloc;
+
+use super::io::output;
+use super::{FitResults, FitSettings, Model, OuterOptimizationProblem};
+use crate::utils::FiniteDiff;
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt::{Debug, Display};
+use core::iter::Sum;
+
+use anyhow::{ensure, Context, Result};
+use argmin::core::ArgminFloat;
+use argmin_math::{
+    ArgminAdd, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax, ArgminMul, ArgminSignum,
+    ArgminSub, ArgminZeroLike,
+};
+use indoc::formatdoc;
+use nalgebra::ComplexField;
+use num::Float;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Serialize;
+
+/// Predictive loss of a model in a fold
+#[derive(Debug, Clone, Serialize)]
+pub struct CrossValidationFold<F> {
+    /// Index of the fold (starting from 1)
+    pub fold: usize,
+    /// Degree of the polynomial of the rotation curve
+    pub n: usize,
+    /// Number of the training objects
+    pub n_train: usize,
+    /// Number of the held-out objects
+    pub n_test: usize,
+    /// Mean contribution of the held-out objects to the cost
+    pub loss: F,
+}
+
+/// Summary of the predictive loss of a model
+#[derive(Debug, Clone, Serialize)]
+pub struct CrossValidationSummary<F> {
+    /// Degree of the polynomial of the rotation curve
+    pub n: usize,
+    /// Number of the successful folds
+    pub n_folds: usize,
+    /// Mean of the losses in the folds
+    pub loss_mean: F,
+    /// Standard deviation of the losses in the folds
+    pub loss_std: F,
+    /// Uncertainty in `loss_mean`
+    pub loss_mean_e: F,
+}
+
+/// Results of the cross-validation
+#[derive(Debug, Clone)]
+pub struct CrossValidationResults<F> {
+    /// Losses (one per fold and degree)
+    pub folds: Vec<CrossValidationFold<F>>,
+    /// Summaries (one per degree)
+    pub summaries: Vec<CrossValidationSummary<F>>,
+    /// Number of the failed folds
+    pub n_failed: usize,
+}
+
+/// Split the (shuffled) indices of the objects into the folds: the fold
+/// `k` gets every `n_folds`-th index, starting from the `k`-th one
+fn split_into_folds(indices: &[usize], n_folds: usize) -> Vec<Vec<usize>> {
+    (0..n_folds)
+        .map(|fold| {
+            indices
+                .iter()
+                .skip(fold)
+                .step_by(n_folds)
+                .copied()
+                .collect()
+        })
+        .collect()
+}
+
+impl<F> FitResults<F>
+where
+    F: Float
+        + Debug
+        + Default
+        + Display
+        + Sync
+        + Send
+        + Sum
+        + ArgminFloat
+        + ArgminL2Norm<F>
+        + ArgminSub<F, F>
+        + ArgminAdd<F, F>
+        + ArgminDot<F, F>
+        + ArgminMul<F, F>
+        + ArgminZeroLike
+        + ArgminMul<Vec<F>, Vec<F>>
+        + ComplexField,
+    Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminSub<F, Vec<F>>,
+    Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminAdd<F, Vec<F>>,
+    Vec<F>: ArgminMul<F, Vec<F>>,
+    Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminL1Norm<F>,
+    Vec<F>: ArgminSignum,
+    Vec<F>: ArgminMinMax,
+    Vec<F>: ArgminDot<Vec<F>, F>,
+    Vec<F>: ArgminL2Norm<F>,
+    Vec<F>: FiniteDiff<F>,
+{
+    /// Run the K-fold cross-validation of the degrees: split the
+    /// non-outlier objects into folds, fit the models on all folds
+    /// but one, and score the held-out objects by their contributions
+    /// to the cost (with the reduced parallaxes found in the inner
+    /// optimization, if it's enabled)
+    ///
+    /// The outliers found in the main fit are excluded.
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::print_stderr)]
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    #[allow(clippy::use_debug)]
+    pub fn run_cross_validation<R>(
+        &self,
+        n_folds: usize,
+        rng: &mut R,
+    ) -> Result<CrossValidationResults<F>>
+    where
+        R: Rng,
+    {
+        let best_model = self.best_model();
+        let objects = best_model.objects.borrow().clone();
+
+        // Assign the non-outlier objects to the folds randomly
+        let mut indices: Vec<usize> = objects
+            .iter()
+            .enumerate()
+            .filter(|&(_, object)| !object.outlier)
+            .map(|(i, _)| i)
+            .collect();
+        ensure!(
+            2 <= n_folds && n_folds <= indices.len(),
+            "The number of the folds should be between 2 and the number of the objects"
+        );
+        indices.shuffle(rng);
+
+        let fit_settings = FitSettings {
+            n_best: self.n_best,
+            n_best_criterion: None,
+            n_max: self.models.len(),
+            with_errors: false,
+            with_frozen_profiles: false,
+            disable_outliers: true,
+        };
+
+        let mut folds = Vec::new();
+        let mut n_failed = 0;
+        for (fold, test_indices) in (1..).zip(split_into_folds(&indices, n_folds)) {
+            let n_test = test_indices.len();
+            let n_train = indices.len() - n_test;
+
+            // Exclude the held-out objects from the training set
+            let mut train_objects = objects.clone();
+            for &i in &test_indices {
+                train_objects[i].outlier = true;
+            }
+            let results = match best_model.with_objects(train_objects).fit(&fit_settings) {
+                Ok(results) => results,
+                Err(err) => {
+                    eprintln!("Fold #{fold} failed: {err:?}");
+                    n_failed += 1;
+                    continue;
+                }
+            };
+
+            // Keep only the held-out objects in the testing set
+            let mut test_objects = objects.clone();
+            for object in &mut test_objects {
+                object.outlier = true;
+            }
+            for &i in &test_indices {
+                test_objects[i].outlier = false;
+            }
+            let test_model = best_model.with_objects(test_objects);
+
+            for (i, fitted_model) in results.models.iter().enumerate() {
+                let n = i + 1;
+                let Some(ref fit_params) = fitted_model.fit_params else {
+                    continue;
+                };
+                let costs = test_model
+                    .score(&fit_params.to_vec(n))
+                    .with_context(|| "Couldn't score the held-out objects")?;
+                let loss =
+                    test_indices.iter().map(|&j| costs[j]).sum::<F>() / F::from(n_test).unwrap();
+                folds.push(CrossValidationFold {
+                    fold,
+                    n,
+                    n_train,
+                    n_test,
+                    loss,
+                });
+            }
+        }
+
+        ensure!(
+            n_folds - n_failed >= 2,
+            "There should be at least two successful folds"
+        );
+
+        let mut summaries = Vec::new();
+        for n in 1..=self.models.len() {
+            let losses: Vec<F> = folds
+                .iter()
+                .filter(|record| record.n == n)
+                .map(|record| record.loss)
+                .collect();
+            if losses.len() < 2 {
+                continue;
+            }
+            let len = F::from(losses.len()).unwrap();
+            let loss_mean = losses.iter().copied().sum::<F>() / len;
+            let loss_var = losses
+                .iter()
+                .map(|&loss| Float::powi(loss - loss_mean, 2))
+                .sum::<F>()
+                / (len - F::one());
+            let loss_std = Float::sqrt(loss_var);
+            summaries.push(CrossValidationSummary {
+                n,
+                n_folds: losses.len(),
+                loss_mean,
+                loss_std,
+                loss_mean_e: loss_std / Float::sqrt(len),
+            });
+        }
+
+        Ok(CrossValidationResults {
+            folds,
+            summaries,
+            n_failed,
+        })
+    }
+}
+
+impl<F> Model<F> {
+    /// Compute the per-object contributions to the
+    /// cost at the point in the parameter space
+    fn score(&self, p: &[F]) -> Result<Vec<F>>
+    where
+        F: Float
+            + Debug
+            + Default
+            + Display
+            + Sync
+            + Send
+            + Sum
+            + ArgminFloat
+            + ArgminL2Norm<F>
+            + ArgminSub<F, F>
+            + ArgminAdd<F, F>
+            + ArgminDot<F, F>
+            + ArgminMul<F, F>
+            + ArgminZeroLike
+            + ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<F, Vec<F>>,
+        Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminAdd<F, Vec<F>>,
+        Vec<F>: ArgminMul<F, Vec<F>>,
+        Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminL1Norm<F>,
+        Vec<F>: ArgminSignum,
+        Vec<F>: ArgminMinMax,
+        Vec<F>: ArgminDot<Vec<F>, F>,
+        Vec<F>: ArgminL2Norm<F>,
+        Vec<F>: FiniteDiff<F>,
+    {
+        // Compute some of the values that don't
+        // depend on the parameters being optimized
+        self.objects.borrow_mut().iter_mut().for_each(|object| {
+            object.compute_l_b(&self.params);
+            object.compute_v_r(&self.params);
+            object.compute_r_h();
+            object.compute_mu_l_cos_b_mu_b(&self.params);
+        });
+        let problem = OuterOptimizationProblem {
+            disable_inner: self.disable_inner,
+            objects: &self.objects,
+            params: &self.params,
+            priors: &self.priors,
+            triples: &Rc::new(RefCell::new(self.triples.borrow().clone())),
+            output_dir: &self.output_dir,
+        };
+        problem.inner_costs(&p.to_vec(), false)
+    }
+    /// Serialize the results of the cross-validation
+    pub fn serialize_to_cross_validation(&self, results: &CrossValidationResults<F>) -> Result<()>
+    where
+        F: Serialize,
+    {
+        let n_failed = results.n_failed;
+        let folds_header = formatdoc!(
+            "
+            # Predictive losses of the models in the folds of the cross-validation
+            #
+            # Descriptions:
+            #
+            # 1 fold: Index of the fold (starting from 1)
+            # 2 n: Degree of the polynomial of the rotation curve
+            # 3 n_train: Number of the training objects
+            # 4 n_test: Number of the held-out objects
+            # 5 loss: Mean contribution of the held-out objects to the cost
+            #
+            # Number of the failed folds: {n_failed}
+            #
+            "
+        );
+        output::serialize_to(
+            &self.output_dir,
+            "cross_validation_folds",
+            &folds_header,
+            &results.folds,
+        )
+        .with_context(|| "Couldn't write the losses in the folds")?;
+
+        let summaries_header = formatdoc!(
+            "
+            # Predictive losses as a dependency of the degree
+            # of the polynomial of the rotation curve
+            #
+            # Descriptions:
+            #
+            # 1 n: Degree of the polynomial of the rotation curve
+            # 2 n_folds: Number of the successful folds
+            # 3 loss_mean: Mean of the losses in the folds
+            # 4 loss_std: Standard deviation of the losses in the folds
+            # 5 loss_mean_e: Uncertainty in `loss_mean`
+            #
+            # Number of the failed folds: {n_failed}
+            #
+            "
+        );
+        output::serialize_to(
+            &self.output_dir,
+            "cross_validation",
+            &summaries_header,
+            &results.summaries,
+        )
+        .with_context(|| "Couldn't write the summaries of the losses")
+    }
+}
+
+#[cfg(test)]
+use super::Params;
+#[cfg(test)]
+use crate::model::simulate::{simulate_test_objects, test_params};
+#[cfg(test)]
+use rand::SeedableRng;
+#[cfg(test)]
+use rand_chacha::ChaCha8Rng;
+
+#[test]
+#[allow(clippy::indexing_slicing)]
+fn test() -> Result<()> {
+    // The folds should cover each object exactly once,
+    // and their sizes should differ by one at most
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let mut indices: Vec<usize> = (0..23).filter(|i| i % 5 != 0).collect();
+    indices.shuffle(&mut rng);
+    let folds = split_into_folds(&indices, 4);
+    let mut covered: Vec<usize> = folds.iter().flatten().copied().collect();
+    covered.sort_unstable();
+    let mut expected = indices.clone();
+    expected.sort_unstable();
+    ensure!(covered == expected, "The folds should form a partition");
+    let sizes: Vec<usize> = folds.iter().map(Vec::len).collect();
+    ensure!(sizes.iter().max().unwrap_or(&0) - sizes.iter().min().unwrap_or(&0) <= 1);
+    // Cross-validate a fit of a small catalogue with an outlier
+    let params = Params {
+        fixed: vec!["A"],
+        ..test_params()
+    };
+    let objects = simulate_test_objects(&params, 20, 0)?;
+    let model = Model::builder()
+        .params(params)
+        .objects(objects)
+        .disable_inner(true)
+        .build()?;
+    let results = model.fit(&FitSettings {
+        n_max: 1,
+        disable_outliers: true,
+        ..Default::default()
+    })?;
+    results.best_model().objects.borrow_mut()[3].outlier = true;
+    let n_folds = 3;
+    let cross_validation = results.run_cross_validation(n_folds, &mut rng)?;
+    ensure!(cross_validation.n_failed == 0);
+    ensure!(cross_validation.folds.len() == n_folds);
+    // Each non-outlier object should be held out exactly once
+    ensure!(cross_validation
+        .folds
+        .iter()
+        .all(|fold| fold.n_train + fold.n_test == 19 && fold.loss.is_finite()));
+    ensure!(
+        cross_validation
+            .folds
+            .iter()
+            .map(|fold| fold.n_test)
+            .sum::<usize>()
+            == 19
+    );
+    ensure!(
+        cross_validation.summaries.len() == 1 && cross_validation.summaries[0].n_folds == n_folds
+    );
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! Radial profiles of the natural standard deviations
+
+use super::Params;
+
+use core::fmt::Display;
+use core::str::FromStr;
+
+#[cfg(test)]
+use anyhow::ensure;
+use anyhow::{anyhow, Result};
+use num::Float;
+use numeric_literals::replace_float_literals;
+
+/// Radial profile of the ellipsoid of natural standard deviations
+///
+/// The values of `sigma_R`, `sigma_theta` and `sigma_Z` are taken at R = R_0,
+/// each of them has its own scale length (`h_sigma_R`, `h_sigma_theta`, and
+/// `h_sigma_Z`). Positive scale lengths make the dispersions decrease outward.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SigmaProfile {
+    /// The same ellipsoid across the whole disk
+    #[default]
+    Constant,
+    /// `sigma(R) = sigma * exp(-(R - R_0) / h)`
+    Exponential,
+    /// `sigma(R) = sigma * max(0, 1 - (R - R_0) / h)`
+    ///
+    /// The profile is valid on the side of `R = R_0 + h` that contains
+    /// the Sun, the dispersions are zero beyond it.
+    Linear,
+}
+
+impl FromStr for SigmaProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string.to_lowercase().as_str() {
+            "constant" => Ok(Self::Constant),
+            "exponential" => Ok(Self::Exponential),
+            "linear" => Ok(Self::Linear),
+            _ => Err(anyhow!("Expected `constant`, `exponential`, or `linear`")),
+        }
+    }
+}
+
+impl Display for SigmaProfile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let string = match *self {
+            Self::Constant => "constant",
+            Self::Exponential => "exponential",
+            Self::Linear => "linear",
+        };
+        write!(f, "{string}")
+    }
+}
+
+/// Compute the natural standard deviations at the Galactocentric distance `r_g`
+///
+/// Returns a triple of the radial, azimuthal, and vertical components (km/s).
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+pub fn compute_sigmas<F>(r_g: F, params: &Params<F>) -> (F, F, F)
+where
+    F: Float,
+{
+    let Params {
+        r_0,
+        sigma_r_g,
+        sigma_theta,
+        sigma_z,
+        h_sigma_r,
+        h_sigma_theta,
+        h_sigma_z,
+        ..
+    } = *params;
+    let delta_r_g = r_g - r_0;
+    let factor = |h: F| match params.sigma_profile {
+        SigmaProfile::Constant => 1.,
+        SigmaProfile::Exponential => (-delta_r_g / h).exp(),
+        // The dispersions are squared later, so a negative factor
+        // would make them grow back instead of vanishing
+        SigmaProfile::Linear => (1. - delta_r_g / h).max(0.),
+    };
+    (
+        sigma_r_g * factor(h_sigma_r),
+        sigma_theta * factor(h_sigma_theta),
+        sigma_z * factor(h_sigma_z),
+    )
+}
+
+#[test]
+fn test() -> Result<()> {
+    for sigma_profile in [
+        SigmaProfile::Constant,
+        SigmaProfile::Exponential,
+        SigmaProfile::Linear,
+    ] {
+        ensure!(sigma_profile.to_string().parse::<SigmaProfile>()? == sigma_profile);
+        let params = Params {
+            r_0: 8.15,
+            sigma_r_g: 12.,
+            sigma_theta: 6.,
+            sigma_z: 3.,
+            h_sigma_r: 5.,
+            h_sigma_theta: 5.,
+            h_sigma_z: -5.,
+            sigma_profile,
+            ..Default::default()
+        };
+        // The sigmas should match at the Sun
+        let (sigma_r_g, sigma_theta, sigma_z) = compute_sigmas(params.r_0, &params);
+        ensure!(
+            (sigma_r_g - 12.).abs() < 1e-12
+                && (sigma_theta - 6.).abs() < 1e-12
+                && (sigma_z - 3.).abs() < 1e-12,
+            "The sigmas should match at R = R_0 for the {sigma_profile} profile"
+        );
+        // ...and follow the signs of the scale lengths elsewhere
+        if sigma_profile != SigmaProfile::Constant {
+            let (outer_r_g, _, outer_z) = compute_sigmas(params.r_0 + 1., &params);
+            ensure!(
+                outer_r_g < 12. && outer_z > 3.,
+                "The sigmas should decrease (increase) outward for the {sigma_profile} profile"
+            );
+        }
+        // ...and stay non-negative far from the Sun
+        if sigma_profile == SigmaProfile::Linear {
+            let (far_r_g, _, far_z) = compute_sigmas(params.r_0 + 10., &params);
+            let (_, _, far_z_inner) = compute_sigmas(params.r_0 - 10., &params);
+            ensure!(far_r_g == 0. && far_z > 3. && far_z_inner == 0.);
+        }
+    }
+    Ok(())
+}
@@ -3,16 +3,14 @@
 extern crate alloc;
 
 use super::params::{ARMIJO_PARAM, BACKTRACKING_PARAM, LBFGS_M, LBFGS_TOLERANCE_ERRORS, MAX_ITERS};
-use super::{ErrorsLogger, FrozenOuterOptimizationProblem, Triple, Triples};
-use super::{Model, Objects, Params};
+use super::{ErrorsLogger, FrozenOuterOptimizationProblem, LogWriter, Triple, Triples};
+use super::{Model, Objects, Params, Priors};
 use crate::utils::FiniteDiff;
 
 use alloc::rc::Rc;
 use core::cell::RefCell;
 use core::fmt::{Debug, Display};
 use core::iter::Sum;
-use std::fs::File;
-use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
@@ -45,6 +43,7 @@ where
     pub params: &'a Params<F>,
     pub compute_param: FN,
     pub fit_params: &'a Params<F>,
+    pub priors: &'a Priors<F>,
     pub triples: &'a Rc<RefCell<Vec<Triples<F>>>>,
     pub output_dir: &'a PathBuf,
 }
@@ -87,29 +86,22 @@ where
         Vec<F>: ArgminL2Norm<F>,
         Vec<F>: FiniteDiff<F>,
     {
-        let index = if self.l_stroke == 1 {
-            if self.index < 6 {
-                self.index
-            } else {
-                self.index - 3
-            }
-        } else {
-            self.index
-        };
-
-        // Remove the frozen parameter
-        let mut init_param = self.params.to_vec(self.n, self.l_stroke == 1);
-        init_param.remove(index);
+        // Remove the frozen parameters (the sigmas are frozen in the L' = 1 run only)
+        let remove_sigmas = self.l_stroke == 1;
+        let mut init_param = self.params.to_free_vec(self.n, remove_sigmas);
+        init_param.remove(self.params.free_index(self.n, self.index, remove_sigmas));
         // Define the problem of the outer optimization with a frozen parameter
         let problem = FrozenOuterOptimizationProblem {
             disable_inner: self.disable_inner,
             l_stroke: self.l_stroke,
-            index,
+            n: self.n,
+            index: self.index,
             param: *param,
             compute_param: &self.compute_param,
             objects: self.objects,
             params: self.params,
             fit_params: self.fit_params,
+            priors: self.priors,
             triples: self.triples,
             output_dir: self.output_dir,
         };
@@ -212,17 +204,13 @@ where
     #[allow(clippy::unwrap_used)]
     #[allow(clippy::use_debug)]
     #[replace_float_literals(F::from(literal).unwrap())]
-    pub fn try_fit_errors(
-        &mut self,
-        errors_log_writer: &Rc<RefCell<BufWriter<File>>>,
-        l_stroke: usize,
-    ) -> Result<()> {
+    pub fn try_fit_errors(&mut self, errors_log_writer: &LogWriter, l_stroke: usize) -> Result<()> {
         let n = self.n.unwrap();
 
         let triple = vec![Triple::<F>::default(); 4];
         let triples = Rc::new(RefCell::new(vec![triple; self.objects.borrow().len()]));
 
-        let best_point = self.fit_params.as_ref().unwrap().to_vec(n, false);
+        let best_point = self.fit_params.as_ref().unwrap().to_vec(n);
         let mut fit_params_ep = self.fit_params.as_ref().unwrap().to_ep_vec(n);
         let mut fit_params_em = self.fit_params.as_ref().unwrap().to_em_vec(n);
 
@@ -231,7 +219,7 @@ where
             .enumerate()
             .try_for_each(|(index, (fit_param_ep, fit_param_em))| -> Result<()> {
                 // Don't compute for the sigmas or compute for the sigmas only
-                if Params::<F>::compute_with_l_stroke(index, l_stroke) {
+                if self.params.compute_with_l_stroke(n, index, l_stroke) {
                     return Ok(());
                 }
 
@@ -266,8 +254,11 @@ where
 
         // Compute errors for derived values, too, by
         // temporarily changing the parametrization
+        // (unless the mimicked parameter is fixed)
         if l_stroke == 1 {
-            {
+            let omega_0_free = !self.params.is_fixed(n, 1);
+            let v_sun_free = !self.params.is_fixed(n, 4);
+            if omega_0_free {
                 writeln!(
                     errors_log_writer.borrow_mut(),
                     "errors for `theta_0` while mimicking under `omega_0`",
@@ -291,7 +282,7 @@ where
                 fit_params.theta_0_ep = diff_p;
                 fit_params.theta_0_em = diff_m;
             }
-            {
+            if omega_0_free {
                 writeln!(
                     errors_log_writer.borrow_mut(),
                     "errors for `theta_1` while mimicking under `omega_0`",
@@ -300,10 +291,7 @@ where
                 let param = self.fit_params.as_ref().unwrap().theta_1;
 
                 // `omega_0` = `theta_1` + 2 * `A`
-                //
-                // The index is 1 and not 2 because the
-                // frozen parameter (index 1) is removed
-                let compute_param = |theta_1: F, p: &[F]| theta_1 + 2. * p[1];
+                let compute_param = |theta_1: F, p: &[F]| theta_1 + 2. * p[2];
 
                 let (diff_p, diff_m) = self.try_fit_errors_pair(
                     l_stroke,
@@ -318,7 +306,7 @@ where
                 fit_params.theta_1_ep = diff_p;
                 fit_params.theta_1_em = diff_m;
             }
-            {
+            if v_sun_free {
                 writeln!(
                     errors_log_writer.borrow_mut(),
                     "errors for `theta_sun` while mimicking under `v_sun`",
@@ -342,7 +330,7 @@ where
                 fit_params.theta_sun_ep = diff_p;
                 fit_params.theta_sun_em = diff_m;
             }
-            {
+            if omega_0_free {
                 writeln!(
                     errors_log_writer.borrow_mut(),
                     "errors for `omega_sun` while mimicking under `omega_0`",
@@ -351,10 +339,7 @@ where
                 let param = self.fit_params.as_ref().unwrap().omega_sun;
 
                 // `omega_0` = `omega_sun` - `v_sun` / `R_0`
-                //
-                // The index is 3 and not 4 because the
-                // frozen parameter (index 1) is removed
-                let compute_param = |omega_sun: F, p: &[F]| omega_sun - p[3] / p[0];
+                let compute_param = |omega_sun: F, p: &[F]| omega_sun - p[4] / p[0];
 
                 let (diff_p, diff_m) = self.try_fit_errors_pair(
                     l_stroke,
@@ -388,7 +373,7 @@ where
         index: usize,
         param: F,
         compute_param: FN,
-        errors_log_writer: &Rc<RefCell<BufWriter<File>>>,
+        errors_log_writer: &LogWriter,
         triples: &Rc<RefCell<Vec<Triples<F>>>>,
     ) -> Result<(F, F)>
     where
@@ -398,7 +383,15 @@ where
         let max_iters = 100;
 
         let n = self.n.unwrap();
-        let fit_params = self.fit_params.as_ref().unwrap();
+        // The error floors are held at their values in the L' = 3 run
+        let (params, fit_params) = if l_stroke == 3 {
+            (
+                self.params.with_fixed_error_floors(),
+                self.fit_params.as_ref().unwrap().with_fixed_error_floors(),
+            )
+        } else {
+            (self.params.clone(), self.fit_params.clone().unwrap())
+        };
 
         writeln!(
             errors_log_writer.borrow_mut(),
@@ -416,9 +409,10 @@ where
                 index,
                 best_outer_cost: F::zero(),
                 objects: &self.objects,
-                params: &self.params,
+                params: &params,
                 compute_param: &compute_param,
-                fit_params,
+                fit_params: &fit_params,
+                priors: &self.priors,
                 triples: &Rc::clone(triples),
                 output_dir: &self.output_dir,
             };
@@ -441,9 +435,10 @@ where
                 index,
                 best_outer_cost: best_frozen_cost,
                 objects: &self.objects,
-                params: &self.params,
+                params: &params,
                 compute_param: &compute_param,
-                fit_params,
+                fit_params: &fit_params,
+                priors: &self.priors,
                 triples: &Rc::clone(triples),
                 output_dir: &self.output_dir,
             };
@@ -499,9 +494,10 @@ where
                 index,
                 best_outer_cost: best_frozen_cost,
                 objects: &self.objects,
-                params: &self.params,
+                params: &params,
                 compute_param,
-                fit_params,
+                fit_params: &fit_params,
+                priors: &self.priors,
                 triples: &Rc::clone(triples),
                 output_dir: &self.output_dir,
             };
@@ -1,12 +1,8 @@
 //! Errors logger
 
-extern crate alloc;
+use super::LogWriter;
 
-use alloc::rc::Rc;
-use core::cell::RefCell;
 use core::fmt::Display;
-use std::fs::File;
-use std::io::{BufWriter, Write};
 
 use anyhow::Result;
 use argmin::core::observers::Observe;
@@ -16,7 +12,7 @@ use argmin::core::KV;
 /// Errors logger
 #[allow(clippy::missing_docs_in_private_items)]
 pub struct ErrorsLogger {
-    pub writer: Rc<RefCell<BufWriter<File>>>,
+    pub writer: LogWriter,
 }
 
 impl<I> Observe<I> for ErrorsLogger
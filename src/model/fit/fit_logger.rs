@@ -2,13 +2,11 @@
 
 extern crate alloc;
 
-use super::{Objects, Params, Triple, Triples, PARAMS_N};
+use super::{LogWriter, Objects, Params, Triple, Triples, PARAMS_N, SIGMA_NAMES};
 
 use alloc::rc::Rc;
 use core::cell::RefCell;
 use core::fmt::{Debug, Display};
-use std::fs::File;
-use std::io::{BufWriter, Write};
 
 use anyhow::Result;
 use argmin::core::observers::Observe;
@@ -22,11 +20,12 @@ use num::Float;
 #[allow(clippy::missing_docs_in_private_items)]
 pub struct FitLogger<F> {
     pub l_stroke: usize,
+    pub n: usize,
     pub sample_iteration: usize,
     pub objects: Objects<F>,
     pub params: Params<F>,
     pub triples: Rc<RefCell<Vec<Triples<F>>>>,
-    pub writer: Rc<RefCell<BufWriter<File>>>,
+    pub writer: LogWriter,
 }
 
 impl<I, F> Observe<I> for FitLogger<F>
@@ -46,11 +45,24 @@ where
         let param = state.get_param().unwrap();
         let best_param = state.get_best_param().unwrap();
 
-        let len = param.len();
-        let mut p = [F::zero(); PARAMS_N];
-        let mut best_p = [F::zero(); PARAMS_N];
-        p[0..len].copy_from_slice(&param[0..len]);
-        best_p[0..len].copy_from_slice(&best_param[0..len]);
+        // Restore the frozen parameters, but skip
+        // the sigmas in the L' = 1 run
+        let remove_sigmas = self.l_stroke == 1;
+        let mut p = self.params.insert_frozen(self.n, param, remove_sigmas);
+        let mut best_p = self.params.insert_frozen(self.n, best_param, remove_sigmas);
+        if remove_sigmas {
+            let names = self.params.names(self.n);
+            let keep = |vec: Vec<F>| -> Vec<F> {
+                izip!(vec, &names)
+                    .filter_map(|(x, name)| (!SIGMA_NAMES.contains(name)).then_some(x))
+                    .collect()
+            };
+            p = keep(p);
+            best_p = keep(best_p);
+        }
+        let len = PARAMS_N.max(p.len());
+        p.resize(len, F::zero());
+        best_p.resize(len, F::zero());
 
         // Write the sample iteration
         writeln!(
@@ -0,0 +1,298 @@
+//! Error floors of the velocities
+
+use super::io::output;
+use super::{Model, Object, OptionalParam, Params};
+
+use core::fmt::{Debug, Display};
+
+#[cfg(test)]
+use anyhow::ensure;
+use anyhow::{anyhow, bail, Context, Result};
+use indoc::formatdoc;
+use num::Float;
+use serde::Serialize;
+
+/// Maximum number of the sources of the data with the fitted error floors
+pub const MAX_N_SOURCES: usize = 8;
+
+/// Names of the error floors of the velocities (a pair per source of the data)
+pub const ERROR_FLOOR_NAMES: [&str; 2 * MAX_N_SOURCES] = [
+    "floor_v_r_1",
+    "floor_v_t_1",
+    "floor_v_r_2",
+    "floor_v_t_2",
+    "floor_v_r_3",
+    "floor_v_t_3",
+    "floor_v_r_4",
+    "floor_v_t_4",
+    "floor_v_r_5",
+    "floor_v_t_5",
+    "floor_v_r_6",
+    "floor_v_t_6",
+    "floor_v_r_7",
+    "floor_v_t_7",
+    "floor_v_r_8",
+    "floor_v_t_8",
+];
+
+/// Wildcard matching the sources not listed explicitly
+pub const ANY_SOURCE: &str = "*";
+
+/// Error floors of the velocities of the objects from one source of the data
+///
+/// The floors are added in quadrature to the uncertainties in the radial
+/// velocity and (as transverse velocities) in the proper motions. They
+/// account for the uncertainty in transferring the maser motions to
+/// that of the central star (see Reid et al. (2019)).
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ErrorFloor<F> {
+    /// Source of the data
+    pub source: String,
+    /// Floor of the radial velocity (km/s)
+    pub v_r: F,
+    /// Plus uncertainty in `v_r`
+    pub v_r_ep: F,
+    /// Minus uncertainty in `v_r`
+    pub v_r_em: F,
+    /// Floor of the transverse velocities (km/s)
+    pub v_t: F,
+    /// Plus uncertainty in `v_t`
+    pub v_t_ep: F,
+    /// Minus uncertainty in `v_t`
+    pub v_t_em: F,
+}
+
+impl<F> ErrorFloor<F> {
+    /// Apply the function to the floors and their uncertainties
+    pub fn map<F2, FN>(&self, f: FN) -> ErrorFloor<F2>
+    where
+        F: Copy,
+        FN: Fn(F) -> F2,
+    {
+        ErrorFloor {
+            source: self.source.clone(),
+            v_r: f(self.v_r),
+            v_r_ep: f(self.v_r_ep),
+            v_r_em: f(self.v_r_em),
+            v_t: f(self.v_t),
+            v_t_ep: f(self.v_t_ep),
+            v_t_em: f(self.v_t_em),
+        }
+    }
+}
+
+/// Try to parse the specification of the error floors in the form
+/// `SOURCE=V_R[:V_T]` (the floor of the transverse velocities defaults
+/// to that of the radial velocity)
+fn try_parse_spec<F>(spec: &str) -> Result<(&str, F, F)>
+where
+    F: Float,
+{
+    let (source, values) = spec
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected the form `SOURCE=V_R[:V_T]`"))?;
+    let parse = |string: &str| -> Result<F> {
+        let value = string
+            .parse::<f64>()
+            .with_context(|| format!("Couldn't parse the floor `{string}`"))?;
+        if value < 0. {
+            bail!("Expected a non-negative floor, found {value}");
+        }
+        F::from(value).ok_or_else(|| anyhow!("Couldn't cast the floor `{string}`"))
+    };
+    let (v_r, v_t) = if let Some((v_r, v_t)) = values.split_once(':') {
+        (parse(v_r)?, parse(v_t)?)
+    } else {
+        let v_r = parse(values)?;
+        (v_r, v_r)
+    };
+    Ok((source, v_r, v_t))
+}
+
+impl<F> Params<F> {
+    /// Try to define the error floors of the velocities, one pair for each of
+    /// the sources of the objects
+    ///
+    /// The later specifications override the earlier ones. The sources
+    /// not matched by any of them have zero floors.
+    pub fn try_init_error_floors(&mut self, objects: &[Object<F>], specs: &[String]) -> Result<()>
+    where
+        F: Float,
+    {
+        let parsed = specs
+            .iter()
+            .map(|spec| {
+                try_parse_spec(spec)
+                    .with_context(|| format!("Couldn't parse the error floors `{spec}`"))
+            })
+            .collect::<Result<Vec<(&str, F, F)>>>()?;
+        let mut sources: Vec<&str> = Vec::new();
+        for source in objects.iter().filter_map(|object| object.source.as_deref()) {
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+        if self.fits(OptionalParam::ErrorFloors) && sources.len() > MAX_N_SOURCES {
+            bail!(
+                "Expected at most {MAX_N_SOURCES} sources of the data, found {}",
+                sources.len()
+            );
+        }
+        self.error_floors = sources
+            .into_iter()
+            .map(|source| {
+                let find = |pattern: &str| parsed.iter().rev().find(|spec| spec.0 == pattern);
+                let (v_r, v_t) = find(source)
+                    .or_else(|| find(ANY_SOURCE))
+                    .map_or((F::zero(), F::zero()), |&(_, v_r, v_t)| (v_r, v_t));
+                ErrorFloor {
+                    source: source.to_owned(),
+                    v_r,
+                    v_r_ep: F::zero(),
+                    v_r_em: F::zero(),
+                    v_t,
+                    v_t_ep: F::zero(),
+                    v_t_em: F::zero(),
+                }
+            })
+            .collect();
+        Ok(())
+    }
+    /// Format the error floors with their sources
+    #[allow(clippy::indexing_slicing)]
+    pub fn format_error_floors(&self) -> String
+    where
+        F: Display,
+    {
+        if self.error_floors.is_empty() {
+            return "none".to_owned();
+        }
+        let floors = self
+            .error_floors
+            .iter()
+            .zip(ERROR_FLOOR_NAMES.chunks(2))
+            .map(|(floor, names)| {
+                let (name_v_r, name_v_t) = if self.fits(OptionalParam::ErrorFloors) {
+                    (names[0], names[1])
+                } else {
+                    ("V_r", "V_t")
+                };
+                format!(
+                    "{} ({name_v_r}: {}, {name_v_t}: {})",
+                    floor.source, floor.v_r, floor.v_t
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        if self.fits(OptionalParam::ErrorFloors) {
+            format!("{floors} [km/s, fitted]")
+        } else {
+            format!("{floors} [km/s]")
+        }
+    }
+    /// Get a copy of the parameters with the error floors fixed
+    ///
+    /// The floors are fitted in the L' = 1 run only, since
+    /// otherwise they would absorb the outliers.
+    pub fn with_fixed_error_floors(&self) -> Self
+    where
+        F: Clone,
+    {
+        let mut params = self.clone();
+        if params.fits(OptionalParam::ErrorFloors) {
+            for &name in ERROR_FLOOR_NAMES.iter().take(2 * params.error_floors.len()) {
+                if !params.fixed.contains(&name) {
+                    params.fixed.push(name);
+                }
+            }
+        }
+        params
+    }
+}
+
+/// Compute the error floors of the radial and transverse velocities from the source
+///
+/// The sources missing from the table have zero floors.
+pub fn compute_error_floors<F>(source: Option<&str>, params: &Params<F>) -> (F, F)
+where
+    F: Float,
+{
+    params
+        .error_floors
+        .iter()
+        .find(|floor| Some(floor.source.as_str()) == source)
+        .map_or((F::zero(), F::zero()), |floor| (floor.v_r, floor.v_t))
+}
+
+impl<F> Model<F> {
+    /// Serialize the fitted error floors of the velocities
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    pub fn serialize_to_fit_error_floors(&self) -> Result<()>
+    where
+        F: Float + Debug + Display + Serialize,
+    {
+        let fit_params = self.fit_params.as_ref().unwrap();
+        if !fit_params.fits(OptionalParam::ErrorFloors) {
+            return Ok(());
+        }
+        let header = formatdoc!(
+            "
+            # Fit of the model (error floors of the velocities)
+            {sample_description}
+            # Descriptions:
+            #
+            # 01 source: Source of the data
+            # 02 v_r: Floor of the radial velocity [km/s]
+            # 03 v_r_ep: Plus uncertainty in `v_r` [km/s]
+            # 04 v_r_em: Minus uncertainty in `v_r` [km/s]
+            # 05 v_t: Floor of the transverse velocities [km/s]
+            # 06 v_t_ep: Plus uncertainty in `v_t` [km/s]
+            # 07 v_t_em: Minus uncertainty in `v_t` [km/s]
+            #
+            ",
+            sample_description = self.format_sample_description(),
+        );
+        output::serialize_to(
+            &self.output_dir,
+            "fit_error_floors",
+            &header,
+            &fit_params.error_floors,
+        )
+    }
+}
+
+#[test]
+fn test() -> Result<()> {
+    let objects: Vec<Object<f64>> = ["Reid", "VERA", "New"]
+        .into_iter()
+        .map(|source| Object {
+            source: Some(source.to_owned()),
+            ..Default::default()
+        })
+        .collect();
+    let specs = ["*=10", "Reid=0", "New=5:3"].map(ToOwned::to_owned);
+    let mut params = Params::<f64> {
+        fitted: vec![OptionalParam::ErrorFloors],
+        ..Default::default()
+    };
+    params.try_init_error_floors(&objects, &specs)?;
+    ensure!(compute_error_floors(Some("Reid"), &params) == (0., 0.));
+    ensure!(compute_error_floors(Some("VERA"), &params) == (10., 10.));
+    ensure!(compute_error_floors(Some("New"), &params) == (5., 3.));
+    ensure!(compute_error_floors(None, &params) == (0., 0.));
+    ensure!(params.extra_names().len() == 6);
+    // The floors are updated from the point in the parameter space
+    let mut p = params.to_vec(1);
+    let len = p.len();
+    if let Some(last) = p.last_mut() {
+        *last = 4.;
+    }
+    params.update_with(&p);
+    ensure!(compute_error_floors(Some("New"), &params) == (5., 4.));
+    // ...unless they're fixed
+    let fixed = params.with_fixed_error_floors();
+    ensure!((0..len).all(|index| fixed.is_fixed(1, index) == (index >= len - 6)));
+    ensure!(try_parse_spec::<f64>("VERA=-1").is_err());
+    Ok(())
+}
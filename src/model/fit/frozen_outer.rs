@@ -3,8 +3,7 @@
 extern crate alloc;
 
 use super::outer::{Output, Param};
-use super::{Objects, OuterOptimizationProblem, Params};
-use super::{SigmaOuterOptimizationProblem, Triples};
+use super::{Objects, OuterOptimizationProblem, Params, Priors, Triples};
 use crate::utils::FiniteDiff;
 use alloc::rc::Rc;
 use core::cell::RefCell;
@@ -31,14 +30,17 @@ where
 {
     pub disable_inner: bool,
     pub l_stroke: usize,
+    pub n: usize,
+    /// Index of the frozen parameter in the full point
     pub index: usize,
     pub param: F,
     /// Allow to compute the frozen parameter
-    /// from the free (N - 1) parameters
+    /// from the other parameters (in the full point)
     pub compute_param: FN,
     pub objects: &'a Objects<F>,
     pub params: &'a Params<F>,
     pub fit_params: &'a Params<F>,
+    pub priors: &'a Priors<F>,
     pub triples: &'a Rc<RefCell<Vec<Triples<F>>>>,
     pub output_dir: &'a PathBuf,
 }
@@ -87,35 +89,33 @@ where
     #[allow(clippy::unwrap_used)]
     #[replace_float_literals(F::from(literal).unwrap())]
     fn cost(&self, p: &Self::Param) -> Result<Self::Output> {
-        if self.l_stroke == 3 {
-            // Create an outer problem
-            let outer_problem = OuterOptimizationProblem {
-                disable_inner: self.disable_inner,
-                objects: self.objects,
-                params: self.params,
-                triples: self.triples,
-                output_dir: self.output_dir,
-            };
-            // Prepare the parameter vector
-            let mut new_p = p.clone();
-            new_p.insert(self.index, self.param);
-            // Compute the cost
-            outer_problem.cost(&new_p)
-        } else {
-            // Create an outer problem
-            let outer_problem = SigmaOuterOptimizationProblem {
-                disable_inner: self.disable_inner,
-                objects: self.objects,
-                fit_params: self.fit_params,
-                triples: self.triples,
-                output_dir: self.output_dir,
-            };
-            // Prepare the parameter vector
-            let mut new_p = p.clone();
-            new_p.insert(self.index, (self.compute_param)(self.param, &new_p));
-            // Compute the cost
-            outer_problem.cost(&new_p)
-        }
+        // The sigmas are frozen in the L' = 1 run only
+        let remove_sigmas = self.l_stroke == 1;
+        // Create an outer problem
+        let outer_problem = OuterOptimizationProblem {
+            disable_inner: self.disable_inner,
+            objects: self.objects,
+            params: if remove_sigmas {
+                self.fit_params
+            } else {
+                self.params
+            },
+            priors: self.priors,
+            triples: self.triples,
+            output_dir: self.output_dir,
+        };
+        // Prepare the parameter vector, restoring the frozen parameters
+        let mut free_p = p.clone();
+        let free_index = self
+            .fit_params
+            .free_index(self.n, self.index, remove_sigmas);
+        free_p.insert(free_index, self.param);
+        let mut new_p = self
+            .fit_params
+            .insert_frozen(self.n, &free_p, remove_sigmas);
+        new_p[self.index] = (self.compute_param)(self.param, &new_p);
+        // Compute the cost (the triples are updated in the L' = 3 run only)
+        outer_problem.inner_cost(&new_p, !remove_sigmas)
     }
 }
 
@@ -0,0 +1,262 @@
+//! Leave-one-out influence of the objects
+
+extern crate alloc;
+
+use super::{FitLogs, Model};
+use crate::utils::FiniteDiff;
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt::{Debug, Display};
+use core::iter::Sum;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use anyhow::{Context, Result};
+use argmin::core::ArgminFloat;
+use argmin_math::{
+    ArgminAdd, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax, ArgminMul, ArgminSignum,
+    ArgminSub, ArgminZeroLike,
+};
+use indoc::formatdoc;
+use itertools::izip;
+use num::Float;
+
+/// Influence of an object on the fit
+#[derive(Debug, Clone)]
+pub struct Influence<F> {
+    /// Index of the object (starting from 1)
+    pub i: usize,
+    /// Name of the object
+    pub name: String,
+    /// Source of the data
+    pub source: String,
+    /// Change in the best cost after removing the object
+    pub delta_cost: F,
+    /// Shifts of the free parameters after removing
+    /// the object, normalized by their uncertainties
+    pub shifts: Vec<F>,
+    /// Maximum absolute value of the shifts
+    pub max_shift: F,
+}
+
+impl<F> Model<F> {
+    /// Compute the influence of each non-outlier object by refitting
+    /// the model (as in the L' = 1 run) with the object removed
+    ///
+    /// The results are sorted by the maximum absolute shift.
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    pub fn compute_influence(&self) -> Result<Vec<Influence<F>>>
+    where
+        F: Float
+            + Debug
+            + Default
+            + Display
+            + Sync
+            + Send
+            + Sum
+            + ArgminFloat
+            + ArgminL2Norm<F>
+            + ArgminSub<F, F>
+            + ArgminAdd<F, F>
+            + ArgminDot<F, F>
+            + ArgminMul<F, F>
+            + ArgminZeroLike
+            + ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<F, Vec<F>>,
+        Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminAdd<F, Vec<F>>,
+        Vec<F>: ArgminMul<F, Vec<F>>,
+        Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminL1Norm<F>,
+        Vec<F>: ArgminSignum,
+        Vec<F>: ArgminMinMax,
+        Vec<F>: ArgminDot<Vec<F>, F>,
+        Vec<F>: ArgminL2Norm<F>,
+        Vec<F>: FiniteDiff<F>,
+    {
+        let n = self.n.unwrap();
+        let fit_params = self.fit_params.as_ref().unwrap();
+        let fit_p = fit_params.to_vec(n);
+        // The parameters frozen in the L' = 1 run don't move
+        // and have no uncertainties, so they're skipped
+        let frozen_mask = fit_params.frozen_mask(n, true);
+        let best_cost = self.best_cost.unwrap();
+        let covariance_matrix = self.covariance_matrix.as_ref().unwrap();
+        let logs = FitLogs::sink(1);
+
+        let objects = self.objects.borrow().clone();
+        let mut influences = Vec::new();
+        for (k, object) in objects.iter().enumerate() {
+            if object.outlier {
+                continue;
+            }
+
+            // Refit the model without the object, starting from the best point
+            let mut loo_objects = objects.clone();
+            loo_objects[k].outlier = true;
+            let mut model = Self {
+                params: fit_params.clone(),
+                objects: Rc::new(RefCell::new(loo_objects)),
+                triples: Rc::new(RefCell::new(self.triples.borrow().clone())),
+                ..self.clone()
+            };
+            model
+                .try_fit_params(n, 0, 1, &logs.fit[0])
+                .with_context(|| {
+                    format!("Couldn't refit the model without the object #{}", k + 1)
+                })?;
+
+            let loo_p = model.fit_params.as_ref().unwrap().to_vec(n);
+            let shifts: Vec<F> = izip!(0.., &fit_p, &loo_p, &frozen_mask)
+                .filter(|&(_, _, _, &frozen)| !frozen)
+                .map(|(j, &x, &loo_x, _)| (loo_x - x) / covariance_matrix[(j, j)].sqrt())
+                .collect();
+            let max_shift = shifts
+                .iter()
+                .fold(F::zero(), |acc, shift| acc.max(shift.abs()));
+
+            influences.push(Influence {
+                i: k + 1,
+                name: object.name.clone().unwrap_or_default(),
+                source: object.source.clone().unwrap_or_default(),
+                delta_cost: model.best_cost.unwrap() - best_cost,
+                shifts,
+                max_shift,
+            });
+        }
+
+        influences.sort_by(|a, b| b.max_shift.partial_cmp(&a.max_shift).unwrap());
+        Ok(influences)
+    }
+    /// Write the leave-one-out influence of the objects
+    #[allow(clippy::unwrap_used)]
+    pub fn write_influence(&self, influences: &[Influence<F>]) -> Result<()>
+    where
+        F: Display,
+    {
+        let n = self.n.unwrap();
+        let fit_params = self.fit_params.as_ref().unwrap();
+        let names: Vec<&str> = fit_params
+            .names(n)
+            .into_iter()
+            .zip(fit_params.frozen_mask(n, true))
+            .filter_map(|(name, frozen)| (!frozen).then_some(name))
+            .collect();
+
+        let path = &self.output_dir.join("influence.dat");
+        let file =
+            File::create(path).with_context(|| "Couldn't create the `influence.dat` file")?;
+        let mut writer = BufWriter::new(file);
+        writeln!(
+            writer,
+            "{}",
+            formatdoc!(
+                "
+                # Leave-one-out influence of the objects
+                #
+                # Each row corresponds to a refit of the model (as in the L' = 1 run)
+                # without the object. The rows are sorted by `max_shift`.
+                #
+                # Descriptions:
+                #
+                # rank: Rank of the object
+                # i: Index of the object (starting from 1)
+                # source: Source of the data
+                # name: Name of the object
+                # delta_L: Change in the best cost after removing the object
+                # max_shift: Maximum absolute value of the shifts
+                # The rest: Shifts of the parameters, normalized by their uncertainties
+                #"
+            )
+        )?;
+        write!(writer, "rank i source name delta_L max_shift")?;
+        for name in &names {
+            write!(writer, " {name}")?;
+        }
+        writeln!(writer)?;
+        for (rank, influence) in influences.iter().enumerate() {
+            write!(
+                writer,
+                "{} {} {} \"{}\" {} {}",
+                rank + 1,
+                influence.i,
+                influence.source,
+                influence.name,
+                influence.delta_cost,
+                influence.max_shift,
+            )?;
+            for shift in &influence.shifts {
+                write!(writer, " {shift}")?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use super::{FitSettings, Params};
+#[cfg(test)]
+use crate::model::simulate::{simulate_test_objects, test_params};
+#[cfg(test)]
+use anyhow::{bail, ensure};
+
+#[test]
+#[allow(clippy::indexing_slicing)]
+#[allow(clippy::unwrap_used)]
+fn test() -> Result<()> {
+    // Duplicate one of the objects of a small catalogue
+    let params = Params {
+        fixed: vec!["A"],
+        ..test_params()
+    };
+    let mut objects = simulate_test_objects(&params, 20, 0)?;
+    objects.push(objects[0].clone());
+    let model = Model::builder()
+        .params(params.clone())
+        .objects(objects)
+        .disable_inner(true)
+        .build()?;
+    let results = model.fit(&FitSettings {
+        n_max: 1,
+        disable_outliers: true,
+        ..Default::default()
+    })?;
+    let best_model = results.best_model();
+    let influences = best_model.compute_influence()?;
+    // Each object should be refitted once, and the shifts should
+    // be reported for the free parameters except the sigmas
+    ensure!(influences.len() == 21);
+    ensure!(influences
+        .iter()
+        .all(|influence| influence.shifts.len() == 5));
+    ensure!(influences
+        .windows(2)
+        .all(|pair| pair[0].max_shift >= pair[1].max_shift));
+    // Removing either copy of the duplicated object
+    // should lead to the same shifts and costs
+    let copies: Vec<&Influence<f64>> = influences
+        .iter()
+        .filter(|influence| influence.i == 1 || influence.i == 21)
+        .collect();
+    let &[a, b] = copies.as_slice() else {
+        bail!("Expected two copies");
+    };
+    ensure!(
+        Float::abs(a.delta_cost - b.delta_cost) < 1e-6,
+        "The changes in the cost should match: {} vs. {}",
+        a.delta_cost,
+        b.delta_cost
+    );
+    for (x, y) in izip!(&a.shifts, &b.shifts) {
+        ensure!(
+            Float::abs(x - y) < 1e-6,
+            "The shifts should match: {x} vs. {y}"
+        );
+    }
+    Ok(())
+}
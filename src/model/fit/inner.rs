@@ -1,8 +1,11 @@
 //! Inner optimization problem
 
-use super::params::VEL_TERM;
+use super::dispersions::compute_sigmas;
+use super::floors::compute_error_floors;
+use super::offsets::compute_par_offset;
+use super::perturbations::compute_perturbation_velocities;
 use super::rotcurve::compute_rot_curve_series;
-use super::{Model, Object, Params};
+use super::{Model, Object, ObservedCovariance, Params};
 use crate::utils;
 
 use core::fmt::Debug;
@@ -37,16 +40,27 @@ pub type Triples<F> = Vec<Triple<F>>;
 pub struct InnerOptimizationProblem<'a, F> {
     pub l: F,
     pub b: F,
+    pub l_tilted: F,
+    pub b_tilted: F,
+    pub sin_psi: F,
+    pub cos_psi: F,
     pub v_sun: F,
     pub v_r_sun: F,
     pub v_r: F,
     pub v_r_error: F,
+    pub v_r_error_p: F,
+    pub v_r_error_m: F,
     pub mu_l_cos_b: F,
     pub mu_l_cos_b_error: F,
     pub mu_b: F,
     pub mu_b_error: F,
+    pub c_mu_l_cos_b_mu_b: F,
+    pub c_par_mu_l_cos_b: F,
+    pub c_par_mu_b: F,
     pub par: F,
     pub par_e: F,
+    pub par_ep: F,
+    pub par_em: F,
     pub fit_params: &'a Params<F>,
 }
 
@@ -64,17 +78,24 @@ impl<'a, F> InnerOptimizationProblem<'a, F> {
         let Self {
             l,
             b,
+            l_tilted,
+            b_tilted,
+            sin_psi,
+            cos_psi,
             v_sun,
             v_r_sun,
             v_r,
-            v_r_error,
+            v_r_error_p,
+            v_r_error_m,
             mu_l_cos_b,
             mu_l_cos_b_error,
             mu_b,
             mu_b_error,
             par,
-            par_e,
+            par_ep,
+            par_em,
             fit_params,
+            ..
         } = *self;
         // Create an object for the reduced values
         let mut object_r = Object {
@@ -91,7 +112,6 @@ impl<'a, F> InnerOptimizationProblem<'a, F> {
         let r_g_r = object_r.r_g.unwrap();
         // Unpack the parameters
         let Params {
-            r_0,
             omega_0,
             u_sun,
             w_sun,
@@ -99,32 +119,50 @@ impl<'a, F> InnerOptimizationProblem<'a, F> {
             ..
         } = *fit_params;
         // Compute the sines and cosines of the longitude and latitude
-        let sin_l = l.sin();
-        let sin_b = b.sin();
-        let cos_l = l.cos();
-        let cos_b = b.cos();
+        // in the frame aligned with the Galactocentric one
+        let sin_l = l_tilted.sin();
+        let sin_b = b_tilted.sin();
+        let cos_l = l_tilted.cos();
+        let cos_b = b_tilted.cos();
+        // Compute the projection of the Galactocentric
+        // distance to the Sun on the Galactic plane
+        let (_, cos_tilt) = fit_params.compute_tilt();
+        let r_0 = fit_params.r_0 * cos_tilt;
         // Compute the difference between the Galactocentric distances
+        // (in the plane, so the rotation curve is anchored at the Sun)
         let delta_r_g = r_g_r - r_0;
         // Compute the sum of the terms in the series of the rotation curve
         let rot_curve_series = compute_rot_curve_series(delta_r_g, fit_params);
+        // Compute the perturbations of the velocity
+        let (v_r_pert, v_l_pert, v_b_pert) =
+            compute_perturbation_velocities(l_tilted, b_tilted, r_h_r, r_g_r, fit_params);
         // Compute the full model velocity
         let v_r_rot = rot_curve_series * r_0 / r_g_r * sin_l * cos_b;
-        let v_r_mod = v_r_rot + v_r_sun;
+        let v_r_mod = v_r_rot + v_r_sun + v_r_pert;
         // Compute the model proper motion in longitude
         let mu_l_cos_b_rot =
             rot_curve_series * (r_0 * cos_l / r_h_r - cos_b) / r_g_r - omega_0 * cos_b;
         let mu_l_cos_b_sun = (u_sun * sin_l - v_sun * cos_l) / r_h_r;
-        let mu_l_cos_b_mod = (mu_l_cos_b_rot + mu_l_cos_b_sun) / k;
+        let mu_l_cos_b_tilted = (mu_l_cos_b_rot + mu_l_cos_b_sun + v_l_pert / r_h_r) / k;
         // Compute the model proper motion in latitude
         let mu_b_rot = -rot_curve_series * r_0 / r_g_r / r_h_r * sin_l * sin_b;
         let mu_b_sun = (u_sun * cos_l * sin_b + v_sun * sin_l * sin_b - w_sun * cos_b) / r_h_r;
-        let mu_b_mod = (mu_b_rot + mu_b_sun) / k;
+        let mu_b_tilted = (mu_b_rot + mu_b_sun + v_b_pert / r_h_r) / k;
+        // Rotate the proper motions to the Galactic frame
+        let mu_l_cos_b_mod = cos_psi * mu_l_cos_b_tilted + sin_psi * mu_b_tilted;
+        let mu_b_mod = -sin_psi * mu_l_cos_b_tilted + cos_psi * mu_b_tilted;
         // Return the triples
         vec![
+            // The likelihood of the radial velocity is a split
+            // normal distribution, too (see the parallax below)
             Triple {
                 observed: v_r,
                 model: v_r_mod,
-                error: v_r_error,
+                error: if v_r_mod > v_r {
+                    v_r_error_p
+                } else {
+                    v_r_error_m
+                },
             },
             Triple {
                 observed: mu_l_cos_b,
@@ -136,13 +174,51 @@ impl<'a, F> InnerOptimizationProblem<'a, F> {
                 model: mu_b_mod,
                 error: mu_b_error,
             },
+            // The likelihood of the parallax is a split normal
+            // distribution, so the error depends on the side
             Triple {
                 observed: par,
                 model: par_r,
-                error: par_e,
+                error: if par_r > par { par_ep } else { par_em },
             },
         ]
     }
+    /// Decompose the covariance matrix of the parallax, `mu_l * cos(b)`,
+    /// and `mu_b` (in this order) as `L * D * L^T`, with the specified
+    /// uncertainty in the parallax (that is, on one side of the split
+    /// normal distribution)
+    ///
+    /// The covariances with the parallax are rescaled to keep the correlation
+    /// coefficients, so only the first elements of the results depend on the
+    /// side. Returns the diagonal of `D` and the subdiagonal elements of `L`.
+    fn decompose_covariance(&self, par_e: F) -> ([F; 3], [F; 3])
+    where
+        F: Float,
+    {
+        // Avoid dividing by a zero uncertainty in the parallax when
+        // there is no correlation with it (e.g., in the simulated data)
+        let divide = |c: F, d: F| if c.is_zero() { c } else { c / d };
+        let d_1 = par_e.powi(2);
+        let l_21 = divide(self.c_par_mu_l_cos_b, self.par_e * par_e);
+        let l_31 = divide(self.c_par_mu_b, self.par_e * par_e);
+        let d_2 = self.mu_l_cos_b_error.powi(2) - l_21.powi(2) * d_1;
+        let l_32 = (self.c_mu_l_cos_b_mu_b - l_31 * l_21 * d_1) / d_2;
+        let d_3 = self.mu_b_error.powi(2) - l_31.powi(2) * d_1 - l_32.powi(2) * d_2;
+        ([d_1, d_2, d_3], [l_21, l_31, l_32])
+    }
+    /// Compute the logarithm of the normalization factor of the likelihood
+    ///
+    /// The terms that don't depend on the parameters (the uncertainties
+    /// in the parallax, the powers of `2 * pi`) are dropped.
+    #[allow(clippy::unwrap_used)]
+    #[replace_float_literals(F::from(literal).unwrap())]
+    pub fn compute_ln_norm(&self) -> F
+    where
+        F: Float,
+    {
+        let ([_, d_2, d_3], _) = self.decompose_covariance(self.par_e);
+        self.v_r_error.ln() + 0.5 * (d_2.ln() + d_3.ln())
+    }
 }
 
 impl<'a, F> CostFunction for InnerOptimizationProblem<'a, F>
@@ -153,17 +229,26 @@ where
     type Output = F;
 
     // Find the reduced parallax
+    #[allow(clippy::indexing_slicing)]
     fn cost(&self, p: &Self::Param) -> Result<Self::Output> {
         let par_r = *p;
         // Compute the discrepancies
         let triples = self.compute_triples(par_r);
-        // Compute the sum
-        let mut sum = F::zero();
-        for triple in triples {
-            // We don't use the function below here because there is a slight difference in the
-            // squared values, which sometimes leads to huge difference in the results
-            sum = sum + (triple.observed - triple.model).powi(2) / triple.error.powi(2);
-        }
+        let [delta_v_r, delta_mu_l_cos_b, delta_mu_b, delta_par] =
+            [0, 1, 2, 3].map(|i| triples[i].observed - triples[i].model);
+        // Compute the sum of the squared Mahalanobis distance of the correlated
+        // discrepancies and the squared discrepancy in the radial velocity.
+        //
+        // We don't use the function below here because there is a slight difference in the
+        // squared values, which sometimes leads to huge difference in the results
+        let ([d_1, d_2, d_3], [l_21, l_31, l_32]) = self.decompose_covariance(triples[3].error);
+        let z_1 = delta_par;
+        let z_2 = delta_mu_l_cos_b - l_21 * z_1;
+        let z_3 = delta_mu_b - l_31 * z_1 - l_32 * z_2;
+        let sum = delta_v_r.powi(2) / triples[0].error.powi(2)
+            + z_2.powi(2) / d_2
+            + z_3.powi(2) / d_3
+            + z_1.powi(2) / d_1;
         Ok(sum)
     }
 }
@@ -177,12 +262,32 @@ where
 }
 
 /// Prepare the inner problem
+pub fn prepare_inner_problem<'a, F>(
+    object: &Object<F>,
+    fit_params: &'a Params<F>,
+) -> InnerOptimizationProblem<'a, F>
+where
+    F: Float + Debug + Default,
+{
+    // Compute the covariances of the observed proper motions
+    let observed = object.compute_observed_covariance(fit_params);
+    prepare_inner_problem_with(object, fit_params, observed)
+}
+
+/// Prepare the inner problem with the specified
+/// covariances of the observed proper motions
+///
+/// Note that the proper motions are correlated even if the input has no
+/// correlation coefficients: the unequal uncertainties in `mu_x` and `mu_y`,
+/// the turned ellipsoid of the natural dispersions, and the rotation from
+/// the frame aligned with the Galactocentric one all mix them.
 #[allow(clippy::similar_names)]
 #[allow(clippy::unwrap_used)]
 #[replace_float_literals(F::from(literal).unwrap())]
-pub fn prepare_inner_problem<'a, F>(
+pub fn prepare_inner_problem_with<'a, F>(
     object: &Object<F>,
     fit_params: &'a Params<F>,
+    observed: ObservedCovariance<F>,
 ) -> InnerOptimizationProblem<'a, F>
 where
     F: Float + Debug + Default,
@@ -190,8 +295,11 @@ where
     // Unpack the data
     let v_r = object.v_r.unwrap();
     let v_r_e = object.v_r_e.unwrap();
-    let par = object.par.unwrap();
+    let v_r_ep = object.v_r_ep.unwrap_or(v_r_e);
+    let v_r_em = object.v_r_em.unwrap_or(v_r_e);
     let par_e = object.par_e.unwrap();
+    let par_ep = object.par_ep.unwrap_or(par_e);
+    let par_em = object.par_em.unwrap_or(par_e);
     let r_h = object.r_h.unwrap();
     let l = object.l.unwrap();
     let b = object.b.unwrap();
@@ -200,82 +308,171 @@ where
     let r_g = object.r_g.unwrap();
     // Unpack the parameters
     let Params {
-        r_0,
         u_sun,
         v_sun,
         w_sun,
-        sigma_r_g,
-        sigma_theta,
-        sigma_z,
         k,
         ..
     } = *fit_params;
-    // Compute the sines and cosines of the longitude and latitude
-    let sin_l = l.sin();
-    let sin_b = b.sin();
-    let cos_l = l.cos();
-    let cos_b = b.cos();
-    // Compute their squares
-    let sin_b_sq = sin_b.powi(2);
-    let cos_l_sq = cos_l.powi(2);
-    let cos_b_sq = cos_b.powi(2);
-    // Compute the observed dispersions
-    let d_r_g = sigma_r_g.powi(2);
-    let d_theta = sigma_theta.powi(2);
-    let d_z = sigma_z.powi(2);
+    // Compute the longitude and latitude in the frame aligned with the Galactocentric
+    // one and the angle of the rotation from its local basis to the Galactic one
+    let (l_tilted, b_tilted, sin_psi, cos_psi) = object.compute_l_b_tilted(fit_params);
+    // Compute the sines and cosines of these longitude and latitude
+    let sin_l = l_tilted.sin();
+    let sin_b = b_tilted.sin();
+    let cos_l = l_tilted.cos();
+    let cos_b = b_tilted.cos();
+    // Compute the projection of the Galactocentric
+    // distance to the Sun on the Galactic plane
+    let (_, cos_tilt) = fit_params.compute_tilt();
+    let r_0 = fit_params.r_0 * cos_tilt;
     // Compute the sines and cosines of the Galactocentric longitude
     let sin_lambda = (r_h * cos_b) / r_g * sin_l;
     let cos_lambda = (r_0 - r_h * cos_b * cos_l) / r_g;
-    // Compute the squares of the sines and cosines of the `phi` angle
-    let sin_phi_sq = (sin_lambda * cos_l + cos_lambda * sin_l).powi(2);
-    let cos_phi_sq = (cos_lambda * cos_l - sin_lambda * sin_l).powi(2);
+    // Compute the sines and cosines of the `phi` angle
+    let sin_phi = sin_lambda * cos_l + cos_lambda * sin_l;
+    let cos_phi = cos_lambda * cos_l - sin_lambda * sin_l;
     // Compute the natural dispersions
-    let d_v_r_natural =
-        d_r_g * cos_phi_sq * cos_b_sq + d_theta * sin_phi_sq * cos_l_sq + d_z * sin_b_sq;
-    let d_v_l_natural = d_r_g * sin_phi_sq + d_theta * cos_phi_sq;
-    let d_v_b_natural =
-        d_r_g * cos_phi_sq * sin_b_sq + d_theta * sin_phi_sq * sin_b_sq + d_z * cos_b_sq;
+    let covariance = compute_natural_covariance(r_g, fit_params);
+    let [d_v_r_natural, d_v_l_natural, d_v_b_natural, c_v_l_v_b_natural] =
+        project_natural_covariance(covariance, sin_phi, cos_phi, sin_b, cos_b);
     let delim = k.powi(2) * r_h.powi(2);
-    let d_mu_l_cos_b_natural = d_v_l_natural / delim;
-    let d_mu_b_natural = d_v_b_natural / delim;
-    // Compute the dispersions of the observed proper motions
-    let (d_mu_l_cos_b_observed, d_mu_b_observed) = object.compute_d_mu_l_cos_b_mu_b(fit_params);
+    let d_mu_l_cos_b_tilted_natural = d_v_l_natural / delim;
+    let d_mu_b_tilted_natural = d_v_b_natural / delim;
+    let c_mu_l_cos_b_mu_b_tilted_natural = c_v_l_v_b_natural / delim;
+    // Rotate them to the Galactic frame
+    let sin_psi_sq = sin_psi.powi(2);
+    let cos_psi_sq = cos_psi.powi(2);
+    let sin_cos_psi = sin_psi * cos_psi;
+    let d_mu_l_cos_b_natural = cos_psi_sq * d_mu_l_cos_b_tilted_natural
+        + sin_psi_sq * d_mu_b_tilted_natural
+        + 2. * sin_cos_psi * c_mu_l_cos_b_mu_b_tilted_natural;
+    let d_mu_b_natural = sin_psi_sq * d_mu_l_cos_b_tilted_natural
+        + cos_psi_sq * d_mu_b_tilted_natural
+        - 2. * sin_cos_psi * c_mu_l_cos_b_mu_b_tilted_natural;
+    let c_mu_l_cos_b_mu_b_natural = sin_cos_psi
+        * (d_mu_b_tilted_natural - d_mu_l_cos_b_tilted_natural)
+        + (cos_psi_sq - sin_psi_sq) * c_mu_l_cos_b_mu_b_tilted_natural;
+    // Compute the error floors of the source. These account for the
+    // uncertainty in transferring the maser motions to that of the
+    // central star (see Reid et al. (2019)). The floor of the transverse
+    // velocities is isotropic, so it's not affected by the rotation
+    // from the equatorial proper motions to the Galactic ones
+    let (floor_v_r, floor_v_t) = compute_error_floors(object.source.as_deref(), fit_params);
+    let d_mu_floor = floor_v_t.powi(2) / delim;
     // Compute the full errors
-    let mut d_v_r = v_r_e.powi(2) + d_v_r_natural;
-    let d_mu_l_cos_b = d_mu_l_cos_b_observed + d_mu_l_cos_b_natural;
-    let d_mu_b = d_mu_b_observed + d_mu_b_natural;
-    // We account for the uncertainty in transferring the
-    // maser motions to that of the central star by adding
-    // an error term here for non-Reid objects.
-    //
-    // See Reid et al. (2019)
-    if !object.from_reid.as_ref().unwrap() {
-        let extra_term_v = F::from(VEL_TERM).unwrap().powi(2);
-        d_v_r = d_v_r + extra_term_v;
-    }
-    let v_r_error = F::sqrt(d_v_r);
+    let d_v_r = d_v_r_natural + floor_v_r.powi(2);
+    let d_mu_l_cos_b = observed.d_mu_l_cos_b + d_mu_l_cos_b_natural + d_mu_floor;
+    let d_mu_b = observed.d_mu_b + d_mu_b_natural + d_mu_floor;
+    let v_r_error_p = F::sqrt(v_r_ep.powi(2) + d_v_r);
+    let v_r_error_m = F::sqrt(v_r_em.powi(2) + d_v_r);
     let mu_l_cos_b_error = F::sqrt(d_mu_l_cos_b);
     let mu_b_error = F::sqrt(d_mu_b);
+    // Correct the observed parallax for the zero-point offset of its source
+    let par = object.par.unwrap() - compute_par_offset(object.source.as_deref(), fit_params);
     // Compute the constant part of the model velocity
     let v_r_sun = -u_sun * cos_l * cos_b - v_sun * sin_l * cos_b - w_sun * sin_b;
     // Define a problem of the inner optimization
     InnerOptimizationProblem {
         l,
         b,
+        l_tilted,
+        b_tilted,
+        sin_psi,
+        cos_psi,
         v_sun,
         v_r_sun,
         v_r,
-        v_r_error,
+        // This is the scale of the normalization
+        // factor of the split normal distribution
+        v_r_error: (v_r_error_p + v_r_error_m) / 2.,
+        v_r_error_p,
+        v_r_error_m,
         mu_l_cos_b,
         mu_l_cos_b_error,
         mu_b,
         mu_b_error,
+        c_mu_l_cos_b_mu_b: observed.c_mu_l_cos_b_mu_b + c_mu_l_cos_b_mu_b_natural,
+        c_par_mu_l_cos_b: observed.c_par_mu_l_cos_b,
+        c_par_mu_b: observed.c_par_mu_b,
         par,
         par_e,
+        par_ep,
+        par_em,
         fit_params,
     }
 }
 
+/// Compute the covariance matrix of the natural velocities in the Galactocentric
+/// cylindrical frame at the Galactocentric distance `r_g` (the elements `R R`,
+/// `theta theta`, `Z Z`, `R theta`, `R Z`, and `theta Z`, in this order)
+///
+/// The radial axis of the ellipsoid is turned from the direction to the
+/// Galactic center toward the direction of rotation by the vertex
+/// deviation and then toward the north Galactic pole by the R-Z tilt.
+fn compute_natural_covariance<F>(r_g: F, fit_params: &Params<F>) -> [F; 6]
+where
+    F: Float + Debug,
+{
+    // Compute the dispersions along the principal axes of the ellipsoid
+    let (sigma_r_g, sigma_theta, sigma_z) = compute_sigmas(r_g, fit_params);
+    let d_1 = sigma_r_g.powi(2);
+    let d_2 = sigma_theta.powi(2);
+    let d_3 = sigma_z.powi(2);
+    // Turn the ellipsoid
+    let (sin_l_v, cos_l_v) = fit_params.l_v.to_radians().sin_cos();
+    let (sin_alpha, cos_alpha) = fit_params.alpha_rz.to_radians().sin_cos();
+    let d_r_g_turned = d_1 * cos_l_v.powi(2) + d_2 * sin_l_v.powi(2);
+    let d_r_theta_turned = -(d_1 - d_2) * sin_l_v * cos_l_v;
+    let d_r_g = d_r_g_turned * cos_alpha.powi(2) + d_3 * sin_alpha.powi(2);
+    let d_theta = d_1 * sin_l_v.powi(2) + d_2 * cos_l_v.powi(2);
+    let d_z = d_r_g_turned * sin_alpha.powi(2) + d_3 * cos_alpha.powi(2);
+    let d_r_theta = d_r_theta_turned * cos_alpha;
+    let d_r_z = (d_r_g_turned - d_3) * sin_alpha * cos_alpha;
+    let d_theta_z = d_r_theta_turned * sin_alpha;
+    [d_r_g, d_theta, d_z, d_r_theta, d_r_z, d_theta_z]
+}
+
+/// Project the covariance matrix of the natural velocities (see
+/// `compute_natural_covariance`) on the line of sight and the directions of
+/// `v_l` and `v_b` (in the frame aligned with the Galactocentric one)
+///
+/// Returns the dispersions of `v_r`, `v_l`, and `v_b`, and the covariance of the latter two.
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+fn project_natural_covariance<F>(
+    covariance: [F; 6],
+    sin_phi: F,
+    cos_phi: F,
+    sin_b: F,
+    cos_b: F,
+) -> [F; 4]
+where
+    F: Float,
+{
+    let [d_r_g, d_theta, d_z, d_r_theta, d_r_z, d_theta_z] = covariance;
+    // Compute the squares of the sines and cosines
+    let sin_phi_sq = sin_phi.powi(2);
+    let cos_phi_sq = cos_phi.powi(2);
+    let sin_b_sq = sin_b.powi(2);
+    let cos_b_sq = cos_b.powi(2);
+    // The projections of the unit vectors of the cylindrical frame on the line
+    // of sight are `-cos(phi) * cos(b)`, `sin(phi) * cos(b)`, and `sin(b)`, respectively
+    let d_v_r = d_r_g * cos_phi_sq * cos_b_sq + d_theta * sin_phi_sq * cos_b_sq + d_z * sin_b_sq
+        - 2. * cos_b * (d_r_theta * sin_phi * cos_phi * cos_b + d_r_z * cos_phi * sin_b)
+        + 2. * cos_b * d_theta_z * sin_phi * sin_b;
+    let d_v_l = d_r_g * sin_phi_sq + d_theta * cos_phi_sq + 2. * d_r_theta * sin_phi * cos_phi;
+    let d_v_b = d_r_g * cos_phi_sq * sin_b_sq + d_theta * sin_phi_sq * sin_b_sq + d_z * cos_b_sq
+        - 2. * sin_b * (d_r_theta * sin_phi * cos_phi * sin_b - d_r_z * cos_phi * cos_b)
+        - 2. * sin_b * d_theta_z * sin_phi * cos_b;
+    // The projections on the directions of `v_l` and `v_b` are `(sin(phi), cos(phi), 0)`
+    // and `(cos(phi) * sin(b), -sin(phi) * sin(b), cos(b))`, so they're correlated, too
+    let c_v_l_v_b = sin_phi * cos_phi * sin_b * (d_r_g - d_theta)
+        + (cos_phi_sq - sin_phi_sq) * sin_b * d_r_theta
+        + cos_b * (sin_phi * d_r_z + cos_phi * d_theta_z);
+    [d_v_r, d_v_l, d_v_b, c_v_l_v_b]
+}
+
 impl<F> Model<F> {
     /// Compute the profiles of the inner target function. Also, find those objects
     /// that have profiles with multiple local minima and output their coordinates
@@ -307,13 +504,12 @@ impl<F> Model<F> {
                 }
 
                 let par = object.par.unwrap();
-                let par_e = object.par_e.unwrap();
 
                 let problem = prepare_inner_problem(object, fit_params);
 
                 let n_points = 1000;
-                let start = F::max(F::epsilon(), par - 9. * par_e);
-                let end = par + 9. * par_e;
+                let start = F::max(F::epsilon(), par - 9. * problem.par_em);
+                let end = par + 9. * problem.par_ep;
                 let h = (end - start) / F::from(n_points).unwrap();
 
                 let inner_profile_path = inner_profiles_dir.join(format!("{}.dat", i + 1));
@@ -399,3 +595,275 @@ impl<F> Model<F> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+use anyhow::{bail, ensure};
+
+/// Check the natural errors of the velocities
+/// against the orientation of the ellipsoid
+#[cfg(test)]
+fn test_natural_errors(object: &Object<f64>, params: &Params<f64>) -> Result<()> {
+    let errors = |fit_params: &Params<f64>| {
+        let problem = prepare_inner_problem_with(object, fit_params, ObservedCovariance::default());
+        [
+            problem.v_r_error,
+            problem.mu_l_cos_b_error,
+            problem.mu_b_error,
+            problem.c_mu_l_cos_b_mu_b,
+        ]
+    };
+    // The errors shouldn't depend on the orientation
+    // of the ellipsoid if it's a sphere
+    let sphere = Params {
+        sigma_r_g: 5.,
+        sigma_theta: 5.,
+        sigma_z: 5.,
+        ..params.clone()
+    };
+    let turned_sphere = Params {
+        l_v: 20.,
+        alpha_rz: -10.,
+        ..sphere.clone()
+    };
+    // Turning the ellipsoid by the right angle should
+    // swap its radial and azimuthal components
+    let ellipsoid = Params {
+        sigma_r_g: 12.,
+        sigma_theta: 6.,
+        sigma_z: 3.,
+        ..params.clone()
+    };
+    let turned_ellipsoid = Params {
+        sigma_r_g: 6.,
+        sigma_theta: 12.,
+        l_v: 90.,
+        ..ellipsoid.clone()
+    };
+    // The natural dispersion in the radial velocity
+    // should be that of the sphere in any direction
+    let [v_r_error, _, _, c] = errors(&sphere);
+    ensure!(c.abs() < 1e-15, "The proper motions should be uncorrelated");
+    ensure!(
+        (v_r_error.powi(2) - 26.).abs() < 1e-12,
+        "The dispersion should be isotropic: {v_r_error}"
+    );
+    // A degenerate ellipsoid gives the natural velocities along a single
+    // direction, so their proper motions should be fully correlated (with
+    // the frame aligned with the Galactocentric one tilted, too)
+    let needle = Params {
+        sigma_r_g: 12.,
+        l_v: 30.,
+        alpha_rz: 20.,
+        z_sun: 500.,
+        ..params.clone()
+    };
+    let [_, mu_l_cos_b_error, mu_b_error, c_needle] = errors(&needle);
+    let det = mu_l_cos_b_error.powi(2) * mu_b_error.powi(2) - c_needle.powi(2);
+    ensure!(
+        det.abs() < 1e-12 * mu_l_cos_b_error.powi(2) * mu_b_error.powi(2),
+        "The covariance matrix should be singular: {det}"
+    );
+    for (original, turned) in [(sphere, turned_sphere), (ellipsoid, turned_ellipsoid)] {
+        for (x, y) in errors(&original).into_iter().zip(errors(&turned)) {
+            ensure!(
+                (x - y).abs() < 1e-12,
+                "The errors should match: {x} vs. {y}"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Check the projection of the azimuthal dispersion off the Galactic plane
+#[cfg(test)]
+fn test_off_plane(object: &Object<f64>, params: &Params<f64>) -> Result<()> {
+    // Off the plane, the azimuthal dispersion alone should be projected on the
+    // line of sight via the azimuthal unit vector, `(-y, x, 0) / R`
+    let mut off_plane = Object {
+        b: Some(0.6),
+        v_r_e: Some(0.),
+        ..object.clone()
+    };
+    off_plane.compute_r_h_nominal();
+    off_plane.compute_r_g_nominal(params);
+    let azimuthal = Params {
+        sigma_theta: 7.,
+        ..params.clone()
+    };
+    let problem = prepare_inner_problem_with(&off_plane, &azimuthal, ObservedCovariance::default());
+    let (sin_l, cos_l) = 0.7_f64.sin_cos();
+    let cos_b = 0.6_f64.cos();
+    let r_h = off_plane.r_h.unwrap_or_default();
+    let x = r_h * cos_b * cos_l - params.r_0;
+    let y = r_h * cos_b * sin_l;
+    let projection = (x * cos_b * sin_l - y * cos_b * cos_l) / x.hypot(y);
+    let expected = 7. * projection.abs();
+    ensure!(
+        (problem.v_r_error - expected).abs() < 1e-12,
+        "The azimuthal dispersion should be projected via `cos(b)`: {} vs. {expected}",
+        problem.v_r_error
+    );
+    Ok(())
+}
+
+/// Check the cost against the squared Mahalanobis distance
+#[cfg(test)]
+fn test_cost(
+    object: &Object<f64>,
+    params: &Params<f64>,
+    observed: ObservedCovariance<f64>,
+) -> Result<()> {
+    // The cost should be the squared Mahalanobis distance
+    let problem = prepare_inner_problem_with(object, params, observed);
+    let deltas: Vec<f64> = problem
+        .compute_triples(0.25)
+        .iter()
+        .map(|triple| triple.observed - triple.model)
+        .collect();
+    let &[d_v_r, d_mu_l, d_mu_b, d_par] = deltas.as_slice() else {
+        bail!("Expected four discrepancies");
+    };
+    let (var_par, var_mu_l, var_mu_b) = (
+        problem.par_e.powi(2),
+        problem.mu_l_cos_b_error.powi(2),
+        problem.mu_b_error.powi(2),
+    );
+    let (c_par_mu_l, c_par_mu_b, c_mu_l_mu_b) = (
+        observed.c_par_mu_l_cos_b,
+        observed.c_par_mu_b,
+        observed.c_mu_l_cos_b_mu_b,
+    );
+    // Invert the covariance matrix of the parallax and the proper motions
+    let cof_par = var_mu_l * var_mu_b - c_mu_l_mu_b.powi(2);
+    let cof_mu_l = var_par * var_mu_b - c_par_mu_b.powi(2);
+    let cof_mu_b = var_par * var_mu_l - c_par_mu_l.powi(2);
+    let cof_par_mu_l = c_par_mu_b * c_mu_l_mu_b - c_par_mu_l * var_mu_b;
+    let cof_par_mu_b = c_par_mu_l * c_mu_l_mu_b - var_mu_l * c_par_mu_b;
+    let cof_mu_l_mu_b = c_par_mu_l * c_par_mu_b - var_par * c_mu_l_mu_b;
+    let det = var_par * cof_par + c_par_mu_l * cof_par_mu_l + c_par_mu_b * cof_par_mu_b;
+    let distance = (d_par.powi(2) * cof_par
+        + d_mu_l.powi(2) * cof_mu_l
+        + d_mu_b.powi(2) * cof_mu_b
+        + 2. * d_par * d_mu_l * cof_par_mu_l
+        + 2. * d_par * d_mu_b * cof_par_mu_b
+        + 2. * d_mu_l * d_mu_b * cof_mu_l_mu_b)
+        / det;
+    let cost = problem.cost(&0.25)?;
+    let expected = d_v_r.powi(2) / problem.v_r_error.powi(2) + distance;
+    ensure!(
+        (cost - expected).abs() < 1e-9 * expected,
+        "The costs should match: {cost} vs. {expected}"
+    );
+    ensure!(
+        (problem.compute_ln_norm() - 0.5 * (problem.v_r_error.powi(2) * det / var_par).ln()).abs()
+            < 1e-12
+    );
+    // Without the correlations, the cost should be the sum of the squared
+    // relative discrepancies, as in the diagonal form
+    let uncorrelated = ObservedCovariance {
+        d_mu_l_cos_b: 0.01,
+        d_mu_b: 0.01,
+        ..Default::default()
+    };
+    let sphere = Params {
+        sigma_r_g: 5.,
+        sigma_theta: 5.,
+        sigma_z: 5.,
+        ..params.clone()
+    };
+    let diagonal = prepare_inner_problem_with(object, &sphere, uncorrelated);
+    let diagonal_cost = diagonal.cost(&0.25)?;
+    let diagonal_expected: f64 = diagonal
+        .compute_triples(0.25)
+        .iter()
+        .map(|triple| ((triple.observed - triple.model) / triple.error).powi(2))
+        .sum();
+    ensure!(
+        (diagonal_cost - diagonal_expected).abs() < 1e-12 * diagonal_expected,
+        "The costs should match: {diagonal_cost} vs. {diagonal_expected}"
+    );
+    Ok(())
+}
+
+/// Check the asymmetric uncertainties in the parallax and the radial velocity
+#[cfg(test)]
+fn test_asymmetric_errors(
+    object: &Object<f64>,
+    params: &Params<f64>,
+    observed: ObservedCovariance<f64>,
+) -> Result<()> {
+    let problem = prepare_inner_problem_with(object, params, observed);
+    // The uncertainty in the parallax should depend on the side,
+    // but the normalization factor of the likelihood shouldn't
+    let asymmetric = Object {
+        par_ep: Some(0.03),
+        par_em: Some(0.01),
+        ..object.clone()
+    };
+    let split = prepare_inner_problem_with(&asymmetric, params, observed);
+    let par_error = |par_r: f64| {
+        split
+            .compute_triples(par_r)
+            .last()
+            .map_or(0., |triple| triple.error)
+    };
+    ensure!((par_error(0.31) - 0.03).abs() < 1e-12 && (par_error(0.29) - 0.01).abs() < 1e-12);
+    ensure!((split.compute_ln_norm() - problem.compute_ln_norm()).abs() < 1e-12);
+    // So should the uncertainty in the radial velocity, with the
+    // mean of the sides in the normalization factor of the likelihood
+    let v_r_error = |v_r: f64| {
+        let asymmetric_v_r = Object {
+            v_r: Some(v_r),
+            v_r_ep: Some(2.),
+            v_r_em: Some(0.5),
+            ..object.clone()
+        };
+        let split_v_r = prepare_inner_problem_with(&asymmetric_v_r, params, observed);
+        let triples = split_v_r.compute_triples(0.3);
+        let error = triples.first().map_or(0., |triple| triple.error);
+        (error, split_v_r.v_r_error)
+    };
+    let (error_above, mean) = v_r_error(1e3);
+    let (error_below, _) = v_r_error(-1e3);
+    ensure!(
+        (error_above - 0.5).abs() < 1e-12
+            && (error_below - 2.).abs() < 1e-12
+            && (mean - 1.25).abs() < 1e-12,
+        "The sides of the radial velocity should be picked by the model: \
+        {error_above}, {error_below}, {mean}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test() -> Result<()> {
+    let mut object = Object {
+        l: Some(0.7_f64),
+        b: Some(0.1),
+        par: Some(0.3),
+        par_e: Some(0.02),
+        v_r: Some(0.),
+        v_r_e: Some(1.),
+        mu_l_cos_b: Some(0.),
+        mu_b: Some(0.),
+        ..Default::default()
+    };
+    let params = Params {
+        r_0: 8.15,
+        k: 4.74,
+        ..Default::default()
+    };
+    object.compute_r_h_nominal();
+    object.compute_r_g_nominal(&params);
+    test_natural_errors(&object, &params)?;
+    test_off_plane(&object, &params)?;
+    let observed = ObservedCovariance {
+        d_mu_l_cos_b: 0.01,
+        d_mu_b: 0.02,
+        c_mu_l_cos_b_mu_b: 0.005,
+        c_par_mu_l_cos_b: 0.0002,
+        c_par_mu_b: -0.0001,
+    };
+    test_cost(&object, &params, observed)?;
+    test_asymmetric_errors(&object, &params, observed)
+}
@@ -0,0 +1,580 @@
+//! Posterior sampling via the affine-invariant ensemble sampler
+
+use super::io::output;
+#[cfg(test)]
+use super::Params;
+use super::{Model, OuterOptimizationProblem};
+use crate::model::params::{compute_derived, DERIVED_NAMES};
+use crate::utils::{self, FiniteDiff};
+
+use core::fmt::{Debug, Display};
+use core::iter::Sum;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use anyhow::{ensure, Context, Result};
+use argmin::core::ArgminFloat;
+use argmin_math::{
+    ArgminAdd, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax, ArgminMul, ArgminSignum,
+    ArgminSub, ArgminZeroLike,
+};
+use indoc::formatdoc;
+use num::Float;
+use numeric_literals::replace_float_literals;
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+use rand_distr::StandardNormal;
+use serde::Serialize;
+
+/// Scale parameter of the stretch move
+const STRETCH_SCALE: f64 = 2.;
+
+/// Window factor of the automatic windowing procedure
+/// for the estimation of the autocorrelation times
+const WINDOW_FACTOR: f64 = 5.;
+
+/// Settings of the posterior sampling
+#[derive(Debug, Clone)]
+pub struct McmcSettings {
+    /// Number of the walkers
+    pub n_walkers: usize,
+    /// Number of the steps (including the burn-in ones)
+    pub n_steps: usize,
+    /// Number of the burn-in steps
+    pub n_burn: usize,
+}
+
+impl Default for McmcSettings {
+    fn default() -> Self {
+        Self {
+            n_walkers: 32,
+            n_steps: 1000,
+            n_burn: 200,
+        }
+    }
+}
+
+/// Posterior summary of a parameter
+#[derive(Debug, Clone, Serialize)]
+pub struct McmcSummary<F> {
+    /// Name of the parameter
+    pub name: &'static str,
+    /// Posterior median
+    pub median: F,
+    /// Plus uncertainty (68% credible interval)
+    pub ep: F,
+    /// Minus uncertainty (68% credible interval)
+    pub em: F,
+    /// Plus uncertainty (95% credible interval)
+    pub ep_95: F,
+    /// Minus uncertainty (95% credible interval)
+    pub em_95: F,
+    /// Integrated autocorrelation time (steps)
+    pub tau: F,
+}
+
+/// Acceptance fraction of a walker
+#[derive(Debug, Clone, Serialize)]
+pub struct McmcAcceptance<F> {
+    /// Index of the walker (starting from 1)
+    pub walker: usize,
+    /// Acceptance fraction
+    pub acceptance: F,
+}
+
+/// Results of the posterior sampling
+#[derive(Debug, Clone)]
+pub struct McmcResults<F> {
+    /// Positions of the walkers, indexed by
+    /// the step, the walker, and the parameter
+    pub chains: Vec<Vec<Vec<F>>>,
+    /// Logarithms of the posterior density, indexed by the step and the walker
+    pub ln_posteriors: Vec<Vec<F>>,
+    /// Acceptance fractions of the walkers
+    pub acceptances: Vec<McmcAcceptance<F>>,
+    /// Posterior summaries of the base and derived parameters
+    pub summaries: Vec<McmcSummary<F>>,
+    /// Number of the burn-in steps
+    pub n_burn: usize,
+}
+
+/// Estimate the integrated autocorrelation time of a quantity
+///
+/// The autocorrelation function is averaged over the walkers, and
+/// the sum is truncated via the automatic windowing procedure.
+///
+/// Source: Sokal (1997); Foreman-Mackey et al. (2013)
+#[allow(clippy::indexing_slicing)]
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+fn compute_autocorrelation_time<F>(series: &[Vec<F>]) -> F
+where
+    F: Float + Debug + Sum,
+{
+    let n_walkers = F::from(series.len()).unwrap();
+    let len = series[0].len();
+    // Center the series and compute their variances
+    let centered: Vec<Vec<F>> = series
+        .iter()
+        .map(|walker_series| {
+            let mean = walker_series.iter().copied().sum::<F>() / F::from(len).unwrap();
+            walker_series.iter().map(|&x| x - mean).collect()
+        })
+        .collect();
+    let variances: Vec<F> = centered
+        .iter()
+        .map(|walker_series| walker_series.iter().map(|&x| x * x).sum::<F>())
+        .collect();
+    let mut tau = 1.;
+    for lag in 1..len {
+        let rho = centered
+            .iter()
+            .zip(&variances)
+            .map(|(walker_series, &variance)| {
+                if variance == 0. {
+                    return 0.;
+                }
+                let covariance = walker_series[..len - lag]
+                    .iter()
+                    .zip(&walker_series[lag..])
+                    .map(|(&x, &y)| x * y)
+                    .sum::<F>();
+                covariance / variance
+            })
+            .sum::<F>()
+            / n_walkers;
+        tau = tau + 2. * rho;
+        if F::from(lag).unwrap() >= F::from(WINDOW_FACTOR).unwrap() * tau {
+            break;
+        }
+    }
+    tau
+}
+
+/// Summarize the samples of a parameter
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+fn summarize<F>(name: &'static str, series: &[Vec<F>]) -> McmcSummary<F>
+where
+    F: Float + Debug + Sum,
+{
+    let mut sorted: Vec<F> = series.iter().flatten().copied().collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = utils::quantile(&sorted, 0.5);
+    McmcSummary {
+        name,
+        median,
+        ep: utils::quantile(&sorted, 0.841_344_746) - median,
+        em: median - utils::quantile(&sorted, 0.158_655_254),
+        ep_95: utils::quantile(&sorted, 0.975) - median,
+        em_95: median - utils::quantile(&sorted, 0.025),
+        tau: compute_autocorrelation_time(series),
+    }
+}
+
+impl<F> Model<F> {
+    /// Sample the posterior distribution of the parameters of the fitted
+    /// model via the affine-invariant ensemble sampler (the stretch move)
+    ///
+    /// The negative log likelihood function is the one of the outer
+    /// optimization problem. The walkers are initialized in a small
+    /// ball around the best point, scaled by the covariance matrix.
+    ///
+    /// Source: Goodman, Weare (2010); Foreman-Mackey et al. (2013)
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::too_many_lines)]
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    #[replace_float_literals(F::from(literal).unwrap())]
+    pub fn sample_posterior<R>(
+        &self,
+        settings: &McmcSettings,
+        rng: &mut R,
+    ) -> Result<McmcResults<F>>
+    where
+        F: Float
+            + Debug
+            + Default
+            + Display
+            + Sum
+            + Sync
+            + Send
+            + ArgminFloat
+            + ArgminL2Norm<F>
+            + ArgminSub<F, F>
+            + ArgminAdd<F, F>
+            + ArgminDot<F, F>
+            + ArgminMul<F, F>
+            + ArgminZeroLike
+            + ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<F, Vec<F>>,
+        Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminAdd<F, Vec<F>>,
+        Vec<F>: ArgminMul<F, Vec<F>>,
+        Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminL1Norm<F>,
+        Vec<F>: ArgminSignum,
+        Vec<F>: ArgminMinMax,
+        Vec<F>: ArgminDot<Vec<F>, F>,
+        Vec<F>: ArgminL2Norm<F>,
+        Vec<F>: FiniteDiff<F>,
+        R: Rng,
+        Standard: Distribution<F>,
+        StandardNormal: Distribution<F>,
+    {
+        let n = self.n.unwrap();
+        let fit_params = self
+            .fit_params
+            .as_ref()
+            .with_context(|| "The model should be fitted first")?;
+        let best_p = fit_params.to_vec(n);
+        let n_params = best_p.len();
+        // The fixed parameters stay the same in all walkers
+        let frozen_mask = self.params.frozen_mask(n, false);
+        let m_free = frozen_mask.iter().filter(|&&frozen| !frozen).count();
+
+        ensure!(
+            settings.n_walkers >= 2 * m_free,
+            "There should be at least twice as many walkers as there are free parameters ({m_free})"
+        );
+        ensure!(
+            settings.n_burn + 2 <= settings.n_steps,
+            "There should be at least two steps after the burn-in"
+        );
+
+        let problem = OuterOptimizationProblem {
+            disable_inner: self.disable_inner,
+            objects: &self.objects,
+            params: fit_params,
+            priors: &self.priors,
+            triples: &self.triples,
+            output_dir: &self.output_dir,
+        };
+        // The cost is already penalized by the priors
+        let names = fit_params.names(n);
+        let ln_posterior = |p: &Vec<F>| -> F {
+            let ln_prior = self.priors.ln_density(&names, p);
+            if !ln_prior.is_finite() {
+                return F::neg_infinity();
+            }
+            match problem.inner_cost(p, false) {
+                Ok(cost) if cost.is_finite() => -cost,
+                _ => F::neg_infinity(),
+            }
+        };
+
+        // Initialize the walkers
+        let scales: Vec<F> = (0..n_params)
+            .map(|i| match self.covariance_matrix {
+                _ if frozen_mask[i] => 0.,
+                Some(ref covariance_matrix) if covariance_matrix[(i, i)] > 0. => {
+                    1e-2 * covariance_matrix[(i, i)].sqrt()
+                }
+                _ => 1e-4 * (best_p[i].abs() + 1e-4),
+            })
+            .collect();
+        let mut positions = Vec::with_capacity(settings.n_walkers);
+        let mut ln_posteriors = Vec::with_capacity(settings.n_walkers);
+        for _ in 0..settings.n_walkers {
+            let mut attempts = 0;
+            loop {
+                let p: Vec<F> = best_p
+                    .iter()
+                    .zip(&scales)
+                    .map(|(&x, &scale)| {
+                        let z: F = rng.sample(StandardNormal);
+                        x + scale * z
+                    })
+                    .collect();
+                let ln_p = ln_posterior(&p);
+                if ln_p.is_finite() {
+                    positions.push(p);
+                    ln_posteriors.push(ln_p);
+                    break;
+                }
+                attempts += 1;
+                ensure!(
+                    attempts < 100,
+                    "Couldn't initialize a walker with a finite posterior density"
+                );
+            }
+        }
+
+        // Run the sampler
+        let stretch_scale = F::from(STRETCH_SCALE).unwrap();
+        let m_float = F::from(m_free).unwrap();
+        let mut n_accepted = vec![0_usize; settings.n_walkers];
+        let mut chains = Vec::with_capacity(settings.n_steps);
+        let mut ln_posterior_chains = Vec::with_capacity(settings.n_steps);
+        for _ in 0..settings.n_steps {
+            for walker in 0..settings.n_walkers {
+                // Choose a complementary walker
+                let mut other = rng.gen_range(0..settings.n_walkers - 1);
+                if other >= walker {
+                    other += 1;
+                }
+                // Propose a new position
+                let uniform: F = rng.gen();
+                let stretch = ((stretch_scale - 1.) * uniform + 1.).powi(2) / stretch_scale;
+                let proposal: Vec<F> = positions[other]
+                    .iter()
+                    .zip(&positions[walker])
+                    .map(|(&x_other, &x_walker)| x_other + stretch * (x_walker - x_other))
+                    .collect();
+                let ln_p = ln_posterior(&proposal);
+                let ln_acceptance = (m_float - 1.) * stretch.ln() + ln_p - ln_posteriors[walker];
+                let threshold: F = rng.gen();
+                if threshold.ln() < ln_acceptance {
+                    positions[walker] = proposal;
+                    ln_posteriors[walker] = ln_p;
+                    n_accepted[walker] += 1;
+                }
+            }
+            chains.push(positions.clone());
+            ln_posterior_chains.push(ln_posteriors.clone());
+        }
+
+        // Compute the acceptance fractions
+        let n_steps_float = F::from(settings.n_steps).unwrap();
+        let acceptances = n_accepted
+            .iter()
+            .enumerate()
+            .map(|(k, &count)| McmcAcceptance {
+                walker: k + 1,
+                acceptance: F::from(count).unwrap() / n_steps_float,
+            })
+            .collect();
+
+        // Summarize the samples after the burn-in
+        let samples = &chains[settings.n_burn..];
+        let mut summaries = Vec::with_capacity(n_params + DERIVED_NAMES.len());
+        for (i, &name) in self
+            .params
+            .names(self.n.unwrap())
+            .iter()
+            .take(n_params)
+            .enumerate()
+        {
+            let series: Vec<Vec<F>> = (0..settings.n_walkers)
+                .map(|k| samples.iter().map(|step| step[k][i]).collect())
+                .collect();
+            summaries.push(summarize(name, &series));
+        }
+        for (i, &name) in DERIVED_NAMES.iter().enumerate() {
+            let series: Vec<Vec<F>> = (0..settings.n_walkers)
+                .map(|k| {
+                    samples
+                        .iter()
+                        .map(|step| compute_derived(&step[k])[i])
+                        .collect()
+                })
+                .collect();
+            summaries.push(summarize(name, &series));
+        }
+
+        // Restore the per-object values at the best point
+        problem.inner_cost(&best_p, false)?;
+
+        Ok(McmcResults {
+            chains,
+            ln_posteriors: ln_posterior_chains,
+            acceptances,
+            summaries,
+            n_burn: settings.n_burn,
+        })
+    }
+    /// Write the results of the posterior sampling
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::unwrap_used)]
+    pub fn write_mcmc(&self, results: &McmcResults<F>) -> Result<()>
+    where
+        F: Float + Debug + Display + Serialize,
+    {
+        let n_steps = results.chains.len();
+        let n_walkers = results.acceptances.len();
+        let n_burn = results.n_burn;
+        let m = results.chains[0][0].len();
+
+        // Write the chains
+        let chains_path = &self.output_dir.join("mcmc_chains.dat");
+        let chains_file = File::create(chains_path)
+            .with_context(|| "Couldn't create the `mcmc_chains.dat` file")?;
+        let mut chains_writer = BufWriter::new(chains_file);
+        writeln!(
+            chains_writer,
+            "{}",
+            formatdoc!(
+                "
+                # Chains of the walkers of the affine-invariant ensemble sampler
+                #
+                # Each row is a position of a walker after a step. The first
+                # {n_burn} steps are the burn-in ones. The last column is
+                # the logarithm of the posterior density.
+                #"
+            )
+        )?;
+        write!(chains_writer, "step walker")?;
+        for name in self.params.names(self.n.unwrap()).iter().take(m) {
+            write!(chains_writer, " {name}")?;
+        }
+        writeln!(chains_writer, " ln_posterior")?;
+        for (step, (positions, ln_posteriors)) in results
+            .chains
+            .iter()
+            .zip(&results.ln_posteriors)
+            .enumerate()
+        {
+            for (k, (position, ln_posterior)) in positions.iter().zip(ln_posteriors).enumerate() {
+                write!(chains_writer, "{} {}", step + 1, k + 1)?;
+                for x in position {
+                    write!(chains_writer, " {x}")?;
+                }
+                writeln!(chains_writer, " {ln_posterior}")?;
+            }
+        }
+
+        // Write the acceptance fractions
+        let mean_acceptance = results
+            .acceptances
+            .iter()
+            .fold(F::zero(), |acc, record| acc + record.acceptance)
+            / F::from(n_walkers).unwrap();
+        let acceptances_header = formatdoc!(
+            "
+            # Acceptance fractions of the walkers
+            #
+            # Mean acceptance fraction: {mean_acceptance}
+            #
+            "
+        );
+        output::serialize_to(
+            &self.output_dir,
+            "mcmc_acceptance",
+            &acceptances_header,
+            &results.acceptances,
+        )
+        .with_context(|| "Couldn't write the acceptance fractions")?;
+
+        // Write the summaries
+        let summaries_header = formatdoc!(
+            "
+            # Posterior medians and credible intervals of the parameters
+            #
+            # Descriptions:
+            #
+            # 1 name: Name of the parameter
+            # 2 median: Posterior median
+            # 3 ep: Plus uncertainty (68% credible interval)
+            # 4 em: Minus uncertainty (68% credible interval)
+            # 5 ep_95: Plus uncertainty (95% credible interval)
+            # 6 em_95: Minus uncertainty (95% credible interval)
+            # 7 tau: Integrated autocorrelation time [steps]
+            #
+            # Number of the walkers: {n_walkers}
+            # Number of the steps: {n_steps}
+            # Number of the burn-in steps: {n_burn}
+            #
+            "
+        );
+        output::serialize_to(
+            &self.output_dir,
+            "mcmc",
+            &summaries_header,
+            &results.summaries,
+        )
+        .with_context(|| "Couldn't write the posterior summaries")
+    }
+}
+
+#[cfg(test)]
+use crate::model::simulate::{simulate_test_objects, test_params};
+#[cfg(test)]
+use core::iter;
+#[cfg(test)]
+use rand::SeedableRng;
+#[cfg(test)]
+use rand_chacha::ChaCha8Rng;
+
+#[test]
+#[allow(clippy::indexing_slicing)]
+#[allow(clippy::unwrap_used)]
+fn test() -> Result<()> {
+    // The integrated autocorrelation time of the AR(1) process
+    // with the coefficient `phi` is `(1 + phi) / (1 - phi)`
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    for (phi, expected) in [(0., 1.), (0.5, 3.), (0.8, 9.)] {
+        let series: Vec<Vec<f64>> = iter::repeat_with(|| {
+            let mut x = 0.;
+            iter::repeat_with(|| {
+                let z: f64 = rng.sample(StandardNormal);
+                x = phi * x + Float::sqrt(1. - phi * phi) * z;
+                x
+            })
+            .take(10_000)
+            .collect()
+        })
+        .take(8)
+        .collect();
+        let tau = compute_autocorrelation_time(&series);
+        ensure!(
+            Float::abs(tau - expected) < 0.1 * expected,
+            "The autocorrelation time should be close to {expected}: {tau}"
+        );
+    }
+    // Sample the posterior of a small catalogue around the true point
+    let params = Params {
+        fixed: vec!["A"],
+        ..test_params()
+    };
+    let objects = simulate_test_objects(&params, 10, 0)?;
+    let mut model = Model::builder()
+        .params(params.clone())
+        .objects(objects)
+        .disable_inner(true)
+        .build()?;
+    model.compute_objects();
+    model.n = Some(1);
+    model.fit_params = Some(params.clone());
+    let settings = McmcSettings {
+        n_walkers: 16,
+        n_steps: 40,
+        n_burn: 10,
+    };
+    let results = model.sample_posterior(&settings, &mut ChaCha8Rng::seed_from_u64(0))?;
+    // The sampling should be reproducible with the same seed
+    let other_results = model.sample_posterior(&settings, &mut ChaCha8Rng::seed_from_u64(0))?;
+    ensure!(results.chains == other_results.chains);
+    ensure!(results.chains.len() == settings.n_steps);
+    // The fixed parameter should stay the same
+    ensure!(results
+        .chains
+        .iter()
+        .flatten()
+        .all(|position| Float::abs(position[2] - params.a) < f64::EPSILON));
+    ensure!(results
+        .ln_posteriors
+        .iter()
+        .flatten()
+        .all(|ln_p| ln_p.is_finite()));
+    ensure!(results
+        .acceptances
+        .iter()
+        .all(|walker| (0. ..=1.).contains(&walker.acceptance)));
+    ensure!(results
+        .acceptances
+        .iter()
+        .any(|walker| walker.acceptance > 0.));
+    // The credible intervals should be nested
+    for summary in &results.summaries {
+        ensure!(
+            summary.ep >= 0.
+                && summary.em >= 0.
+                && summary.ep_95 >= summary.ep
+                && summary.em_95 >= summary.em,
+            "The intervals of `{}` should be nested",
+            summary.name
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! Zero-point offsets of the parallaxes
+
+use super::io::output;
+use super::{Model, Object, Params};
+
+use core::fmt::{Debug, Display};
+
+#[cfg(test)]
+use anyhow::ensure;
+use anyhow::{bail, Result};
+use indoc::formatdoc;
+use num::Float;
+use serde::Serialize;
+
+/// Names of the zero-point offsets of the parallaxes (one per source of the data)
+pub const PAR_OFFSET_NAMES: [&str; 8] = [
+    "delta_varpi_1",
+    "delta_varpi_2",
+    "delta_varpi_3",
+    "delta_varpi_4",
+    "delta_varpi_5",
+    "delta_varpi_6",
+    "delta_varpi_7",
+    "delta_varpi_8",
+];
+
+/// Zero-point offset of the parallaxes from one source of the data
+///
+/// The offset is subtracted from the observed parallaxes
+/// before the reduced parallax is found.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ParallaxOffset<F> {
+    /// Source of the data
+    pub source: String,
+    /// Offset (mas)
+    pub delta_varpi: F,
+    /// Plus uncertainty in `delta_varpi`
+    pub delta_varpi_ep: F,
+    /// Minus uncertainty in `delta_varpi`
+    pub delta_varpi_em: F,
+}
+
+impl<F> ParallaxOffset<F> {
+    /// Apply the function to the offset and its uncertainties
+    pub fn map<F2, FN>(&self, f: FN) -> ParallaxOffset<F2>
+    where
+        F: Copy,
+        FN: Fn(F) -> F2,
+    {
+        ParallaxOffset {
+            source: self.source.clone(),
+            delta_varpi: f(self.delta_varpi),
+            delta_varpi_ep: f(self.delta_varpi_ep),
+            delta_varpi_em: f(self.delta_varpi_em),
+        }
+    }
+}
+
+impl<F> Params<F> {
+    /// Try to enable the fitting of the zero-point offsets of the
+    /// parallaxes, one for each of the sources of the objects
+    pub fn try_init_par_offsets(&mut self, objects: &[Object<F>]) -> Result<()>
+    where
+        F: Float,
+    {
+        let mut sources: Vec<&str> = Vec::new();
+        for source in objects.iter().filter_map(|object| object.source.as_deref()) {
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+        if sources.len() > PAR_OFFSET_NAMES.len() {
+            bail!(
+                "Expected at most {} sources of the data, found {}",
+                PAR_OFFSET_NAMES.len(),
+                sources.len()
+            );
+        }
+        self.par_offsets = sources
+            .into_iter()
+            .map(|source| ParallaxOffset {
+                source: source.to_owned(),
+                delta_varpi: F::zero(),
+                delta_varpi_ep: F::zero(),
+                delta_varpi_em: F::zero(),
+            })
+            .collect();
+        Ok(())
+    }
+    /// Format the names of the zero-point offsets with their sources
+    pub fn format_par_offsets(&self) -> String {
+        if self.par_offsets.is_empty() {
+            "none".to_owned()
+        } else {
+            PAR_OFFSET_NAMES
+                .iter()
+                .zip(&self.par_offsets)
+                .map(|(name, offset)| format!("{name} ({})", offset.source))
+                .collect::<Vec<String>>()
+                .join(", ")
+        }
+    }
+}
+
+/// Compute the zero-point offset of the parallaxes from the source
+///
+/// The sources without a fitted offset have a zero one.
+pub fn compute_par_offset<F>(source: Option<&str>, params: &Params<F>) -> F
+where
+    F: Float,
+{
+    params
+        .par_offsets
+        .iter()
+        .find(|offset| Some(offset.source.as_str()) == source)
+        .map_or_else(F::zero, |offset| offset.delta_varpi)
+}
+
+impl<F> Model<F> {
+    /// Serialize the fitted zero-point offsets of the parallaxes
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    pub fn serialize_to_fit_par_offsets(&self) -> Result<()>
+    where
+        F: Float + Debug + Display + Serialize,
+    {
+        let fit_params = self.fit_params.as_ref().unwrap();
+        if fit_params.par_offsets.is_empty() {
+            return Ok(());
+        }
+        let header = formatdoc!(
+            "
+            # Fit of the model (zero-point offsets of the parallaxes)
+            {sample_description}
+            # Descriptions:
+            #
+            # 01 source: Source of the data
+            # 02 delta_varpi: Offset, subtracted from the observed parallaxes [mas]
+            # 03 delta_varpi_ep: Plus uncertainty in `delta_varpi` [mas]
+            # 04 delta_varpi_em: Minus uncertainty in `delta_varpi` [mas]
+            #
+            ",
+            sample_description = self.format_sample_description(),
+        );
+        output::serialize_to(
+            &self.output_dir,
+            "fit_par_offsets",
+            &header,
+            &fit_params.par_offsets,
+        )
+    }
+}
+
+#[test]
+fn test() -> Result<()> {
+    let objects: Vec<Object<f64>> = ["Reid", "VERA", "Reid"]
+        .into_iter()
+        .map(|source| Object {
+            source: Some(source.to_owned()),
+            ..Default::default()
+        })
+        .collect();
+    let mut params = Params::<f64>::default();
+    params.try_init_par_offsets(&objects)?;
+    ensure!(params.extra_names() == ["delta_varpi_1", "delta_varpi_2"]);
+    // The offsets are updated from the point in the parameter space
+    let mut p = params.to_vec(1);
+    if let Some(last) = p.last_mut() {
+        *last = 0.01;
+    }
+    params.update_with(&p);
+    ensure!((compute_par_offset(Some("VERA"), &params) - 0.01).abs() < f64::EPSILON);
+    ensure!(compute_par_offset(Some("Reid"), &params).abs() < f64::EPSILON);
+    ensure!(compute_par_offset(Some("New"), &params).abs() < f64::EPSILON);
+    Ok(())
+}
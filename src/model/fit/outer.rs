@@ -2,8 +2,8 @@
 
 extern crate alloc;
 
-use super::{prepare_inner_problem, InnerOptimizationProblem, Triples};
-use super::{Objects, Params};
+use super::{prepare_inner_problem, prepare_inner_problem_with, InnerOptimizationProblem, Triples};
+use super::{Object, Objects, ObservedCovariance, Params, Priors, RotationCurveModel};
 use crate::utils::{self, FiniteDiff};
 
 use alloc::rc::Rc;
@@ -20,6 +20,7 @@ use argmin_math::{
     ArgminAdd, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax, ArgminMul, ArgminSignum,
     ArgminSub, ArgminZeroLike,
 };
+use autodiff::FT;
 use num::Float;
 use numeric_literals::replace_float_literals;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
@@ -31,6 +32,7 @@ pub struct OuterOptimizationProblem<'a, F> {
     pub disable_inner: bool,
     pub objects: &'a Objects<F>,
     pub params: &'a Params<F>,
+    pub priors: &'a Priors<F>,
     pub triples: &'a Rc<RefCell<Vec<Triples<F>>>>,
     pub output_dir: &'a PathBuf,
 }
@@ -51,8 +53,11 @@ impl<'a, F> OuterOptimizationProblem<'a, F> {
     #[allow(clippy::unwrap_in_result)]
     #[allow(clippy::unwrap_used)]
     #[replace_float_literals(F::from(literal).unwrap())]
-    /// Compute the parameterized part of the negative log likelihood function of the model
-    pub fn inner_cost(&self, p: &Param<F>, update_triples: bool) -> Result<Output<F>>
+    /// Compute the per-object contributions to the parameterized
+    /// part of the negative log likelihood function of the model
+    ///
+    /// The contributions of the outliers are zeros.
+    pub fn inner_costs(&self, p: &Param<F>, update_triples: bool) -> Result<Vec<F>>
     where
         F: Float
             + Debug
@@ -87,6 +92,7 @@ impl<'a, F> OuterOptimizationProblem<'a, F> {
         let disable_inner = self.disable_inner;
         // Update the parameters
         fit_params.update_with(p);
+        fit_params.rotcurve.check_shape(&fit_params)?;
         // Prepare storage for the costs
         let mut costs = vec![F::zero(); self.objects.borrow().len()];
         // Compute the new value of the function
@@ -103,75 +109,306 @@ impl<'a, F> OuterOptimizationProblem<'a, F> {
 
                 // Compute some values
                 object.compute_r_g(&fit_params);
-                // Unpack the data
-                let par = object.par.unwrap();
-                let par_e = object.par_e.unwrap();
                 // Define a problem of the inner optimization
                 let problem = prepare_inner_problem(object, &fit_params);
 
-                let (best_par_r, best_sum) = if disable_inner {
-                    let sum = problem.cost(&par)?;
-                    (par, sum)
-                } else {
-                    // Scan the vicinity of the observed parallax via subintervals
-
-                    let mut pars = Vec::with_capacity(5);
-                    let mut sums = Vec::with_capacity(5);
-
-                    let n_subintervals = 50;
-                    for coeff in [1., 2., 3.] {
-                        find_minima(
-                            &problem,
-                            &mut pars,
-                            &mut sums,
-                            par + (coeff - 1.) * 3. * par_e,
-                            par + coeff * 3. * par_e,
-                            n_subintervals,
-                        )?;
-                        find_minima(
-                            &problem,
-                            &mut pars,
-                            &mut sums,
-                            F::max(F::epsilon(), par - coeff * 3. * par_e),
-                            par - (coeff - 1.) * 3. * par_e,
-                            n_subintervals,
-                        )?;
-                        if !pars.is_empty() {
-                            break;
-                        }
-                    }
-
-                    // Find the result closest to the observed parallax
-                    //
-                    // The default values are like this because of
-                    // the initialization stage of the executor
-                    let mut best_par_r = 0.;
-                    let mut best_sum = if pars.is_empty() { 0. } else { F::infinity() };
-                    for (par_r, sum) in izip!(&pars, &sums) {
-                        if *sum < best_sum {
-                            best_par_r = *par_r;
-                            best_sum = *sum;
-                        }
-                    }
-
-                    (best_par_r, best_sum)
-                };
+                let (best_par_r, best_sum) = find_best_par_r(&problem, disable_inner)?;
 
-                *cost = F::ln(problem.v_r_error)
-                    + F::ln(problem.mu_l_cos_b_error)
-                    + F::ln(problem.mu_b_error)
-                    + 0.5 * best_sum;
+                *cost = problem.compute_ln_norm() + 0.5 * best_sum;
 
                 if update_triples {
                     *triple = problem.compute_triples(best_par_r);
                 }
 
+                Ok(())
+            })?;
+        Ok(costs)
+    }
+    /// Compute the parameterized part of the negative log likelihood function
+    /// of the model, penalized by the priors of the parameters
+    ///
+    /// The cost is infinite outside of the domain of the family of the rotation
+    /// curve, so the backtracking line search steps back from there.
+    pub fn inner_cost(&self, p: &Param<F>, update_triples: bool) -> Result<Output<F>>
+    where
+        F: Float
+            + Debug
+            + Default
+            + Display
+            + Sum
+            + Sync
+            + Send
+            + ArgminFloat
+            + ArgminL2Norm<F>
+            + ArgminSub<F, F>
+            + ArgminAdd<F, F>
+            + ArgminDot<F, F>
+            + ArgminMul<F, F>
+            + ArgminZeroLike
+            + ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<F, Vec<F>>,
+        Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminAdd<F, Vec<F>>,
+        Vec<F>: ArgminMul<F, Vec<F>>,
+        Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminL1Norm<F>,
+        Vec<F>: ArgminSignum,
+        Vec<F>: ArgminMinMax,
+        Vec<F>: ArgminDot<Vec<F>, F>,
+        Vec<F>: ArgminL2Norm<F>,
+        Vec<F>: FiniteDiff<F>,
+    {
+        let mut fit_params = self.params.clone();
+        fit_params.update_with(p);
+        if fit_params.rotcurve.check_shape(&fit_params).is_err() {
+            return Ok(F::infinity());
+        }
+        let costs = self.inner_costs(p, update_triples)?;
+        // We do the summing sequentially because
+        // floating-point arithmetic is not associative
+        let cost: F = costs.iter().copied().sum();
+        let penalty = -self.priors.ln_density(&self.params.names_of(p.len()), p);
+        Ok(cost + penalty)
+    }
+    /// Compute the gradient of the parameterized part of the negative log
+    /// likelihood function of the model, penalized by the priors of the parameters
+    ///
+    /// By the envelope theorem, the reduced parallaxes can be held fixed
+    /// at their optimal values, so the partial derivatives of the per-object
+    /// contributions are found via the forward-mode automatic differentiation.
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    pub fn inner_gradient(&self, p: &Param<F>) -> Result<Vec<F>>
+    where
+        F: Float
+            + Debug
+            + Default
+            + Display
+            + Sum
+            + Sync
+            + Send
+            + ArgminFloat
+            + ArgminL2Norm<F>
+            + ArgminSub<F, F>
+            + ArgminAdd<F, F>
+            + ArgminDot<F, F>
+            + ArgminMul<F, F>
+            + ArgminZeroLike
+            + ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<F, Vec<F>>,
+        Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminAdd<F, Vec<F>>,
+        Vec<F>: ArgminMul<F, Vec<F>>,
+        Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminL1Norm<F>,
+        Vec<F>: ArgminSignum,
+        Vec<F>: ArgminMinMax,
+        Vec<F>: ArgminDot<Vec<F>, F>,
+        Vec<F>: ArgminL2Norm<F>,
+        Vec<F>: FiniteDiff<F>,
+    {
+        // Unpack the problem
+        let mut fit_params = self.params.clone();
+        let disable_inner = self.disable_inner;
+        // Update the parameters
+        fit_params.update_with(p);
+        fit_params.rotcurve.check_shape(&fit_params)?;
+        // Prepare storage for the per-object gradients
+        let m = p.len();
+        let mut gradients = vec![vec![F::zero(); m]; self.objects.borrow().len()];
+        // Compute the new value of the gradient
+        self.objects
+            .borrow_mut()
+            .par_iter_mut()
+            .zip(gradients.par_iter_mut())
+            .try_for_each(|(object, gradient)| -> Result<()> {
+                // Skip if the object is an outlier
+                if object.outlier {
+                    return Ok(());
+                };
+
+                // Find the reduced parallax
+                object.compute_r_g(&fit_params);
+                let problem = prepare_inner_problem(object, &fit_params);
+                let (best_par_r, _) = find_best_par_r(&problem, disable_inner)?;
+                // If the inner optimization is disabled, the reduced parallax
+                // follows the offset of the observed one, so it's not held fixed
+                let par_r = (!disable_inner).then_some(best_par_r);
+
+                // These don't depend on the parameters being optimized
+                let observed = object.compute_observed_covariance(&fit_params);
+
+                // Differentiate the contribution by each of the parameters
+                for (j, partial) in gradient.iter_mut().enumerate() {
+                    let dual_p: Vec<FT<F>> = p
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &x)| {
+                            if i == j {
+                                FT::<F>::var(x)
+                            } else {
+                                FT::<F>::cst(x)
+                            }
+                        })
+                        .collect();
+                    let mut dual_params = self.params.map(FT::<F>::cst);
+                    dual_params.update_with(&dual_p);
+                    let cost =
+                        compute_lifted_cost(object, &dual_params, observed, par_r, FT::<F>::cst)?;
+                    *partial = cost.deriv();
+                }
+
                 Ok(())
             })?;
         // We do the summing sequentially because
         // floating-point arithmetic is not associative
-        let cost = costs.iter().copied().sum();
-        Ok(cost)
+        let penalty_gradient = self.priors.penalty_gradient(&self.params.names_of(m), p);
+        let gradient = (0..m)
+            .map(|j| gradients.iter().map(|gradient| gradient[j]).sum::<F>() + penalty_gradient[j])
+            .collect();
+        Ok(gradient)
+    }
+}
+
+/// Compute the contribution of the object to the cost with
+/// the parameters in another (e.g., dual) number type
+///
+/// The covariances of the observed proper motions and the reduced parallax
+/// are held constant. If the reduced parallax isn't specified, the observed one
+/// (corrected for the zero-point offset) is used, as in the case of the disabled
+/// inner optimization.
+#[allow(clippy::unwrap_used)]
+#[allow(clippy::unwrap_in_result)]
+pub fn compute_lifted_cost<F, D, L>(
+    object: &Object<F>,
+    params: &Params<D>,
+    observed: ObservedCovariance<F>,
+    par_r: Option<F>,
+    lift: L,
+) -> Result<D>
+where
+    F: Float + Debug + Default,
+    D: Float + Debug + Default,
+    L: Fn(F) -> D,
+{
+    let mut lifted_object = Object {
+        source: object.source.clone(),
+        l: object.l.map(&lift),
+        b: object.b.map(&lift),
+        par: object.par.map(&lift),
+        par_e: object.par_e.map(&lift),
+        par_ep: object.par_ep.map(&lift),
+        par_em: object.par_em.map(&lift),
+        r_h: object.r_h.map(&lift),
+        v_r: object.v_r.map(&lift),
+        v_r_e: object.v_r_e.map(&lift),
+        v_r_ep: object.v_r_ep.map(&lift),
+        v_r_em: object.v_r_em.map(&lift),
+        mu_l_cos_b: object.mu_l_cos_b.map(&lift),
+        mu_b: object.mu_b.map(&lift),
+        ..Default::default()
+    };
+    lifted_object.compute_r_g_nominal(params);
+    let problem = prepare_inner_problem_with(&lifted_object, params, observed.map(&lift));
+    let lifted_par_r = par_r.map_or(problem.par, &lift);
+    let sum = problem.cost(&lifted_par_r)?;
+    Ok(problem.compute_ln_norm() + sum / D::from(2.).unwrap())
+}
+
+/// Find the reduced parallax that minimizes the inner target function
+/// (closest to the observed one), and the minimum itself
+///
+/// If the inner optimization is disabled, the observed parallax is used. Otherwise,
+/// the vicinity of the observed parallax is scanned in the units of its uncertainties.
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+pub fn find_best_par_r<F>(
+    problem: &InnerOptimizationProblem<'_, F>,
+    disable_inner: bool,
+) -> Result<(F, F)>
+where
+    F: Float
+        + Debug
+        + Default
+        + Display
+        + Sync
+        + Send
+        + ArgminFloat
+        + ArgminL2Norm<F>
+        + ArgminSub<F, F>
+        + ArgminAdd<F, F>
+        + ArgminDot<F, F>
+        + ArgminMul<F, F>
+        + ArgminZeroLike
+        + ArgminMul<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminSub<F, Vec<F>>,
+    Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminAdd<F, Vec<F>>,
+    Vec<F>: ArgminMul<F, Vec<F>>,
+    Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminL1Norm<F>,
+    Vec<F>: ArgminSignum,
+    Vec<F>: ArgminMinMax,
+    Vec<F>: ArgminDot<Vec<F>, F>,
+    Vec<F>: ArgminL2Norm<F>,
+    Vec<F>: FiniteDiff<F>,
+{
+    let InnerOptimizationProblem {
+        par,
+        par_ep,
+        par_em,
+        ..
+    } = *problem;
+    if disable_inner {
+        let sum = problem.cost(&par)?;
+        Ok((par, sum))
+    } else {
+        // Scan the vicinity of the observed parallax via subintervals
+
+        let mut pars = Vec::with_capacity(5);
+        let mut sums = Vec::with_capacity(5);
+
+        let n_subintervals = 50;
+        for coeff in [1., 2., 3.] {
+            find_minima(
+                problem,
+                &mut pars,
+                &mut sums,
+                par + (coeff - 1.) * 3. * par_ep,
+                par + coeff * 3. * par_ep,
+                n_subintervals,
+            )?;
+            find_minima(
+                problem,
+                &mut pars,
+                &mut sums,
+                F::max(F::epsilon(), par - coeff * 3. * par_em),
+                par - (coeff - 1.) * 3. * par_em,
+                n_subintervals,
+            )?;
+            if !pars.is_empty() {
+                break;
+            }
+        }
+
+        // Find the result closest to the observed parallax
+        //
+        // The default values are like this because of
+        // the initialization stage of the executor
+        let mut best_par_r = 0.;
+        let mut best_sum = if pars.is_empty() { 0. } else { F::infinity() };
+        for (par_r, sum) in izip!(&pars, &sums) {
+            if *sum < best_sum {
+                best_par_r = *par_r;
+                best_sum = *sum;
+            }
+        }
+
+        Ok((best_par_r, best_sum))
     }
 }
 
@@ -336,9 +573,109 @@ where
     #[allow(clippy::unwrap_used)]
     #[replace_float_literals(F::from(literal).unwrap())]
     fn gradient(&self, p: &Self::Param) -> Result<Self::Gradient> {
-        Ok((*p).central_diff(
-            &|x| self.inner_cost(x, false).unwrap(),
-            F::sqrt(F::epsilon()),
-        ))
+        self.inner_gradient(p)
     }
 }
+
+#[cfg(test)]
+use super::{ErrorFloor, ParallaxOffset, RadialFlow, SigmaProfile};
+#[cfg(test)]
+use crate::model::simulate::{simulate_test_objects, test_params};
+#[cfg(test)]
+use crate::model::{OptionalParam, Prior};
+#[cfg(test)]
+use anyhow::ensure;
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test() -> Result<()> {
+    let true_params = Params {
+        theta_2: -1.,
+        ..test_params()
+    };
+    let mut objects = simulate_test_objects(&true_params, 10, 0)?;
+    // Enable the extra parameters, fix some of the parameters,
+    // and move away from the truth a bit (to the point with a prior)
+    let params = Params {
+        omega_0: 29.,
+        fitted: vec![
+            OptionalParam::ZSun,
+            OptionalParam::ErrorFloors,
+            OptionalParam::VertexDeviation,
+            OptionalParam::TiltRz,
+        ],
+        z_sun: 20.,
+        radial_flow: RadialFlow::Constant,
+        v_flow: 2.,
+        par_offsets: vec![ParallaxOffset {
+            source: "Reid".to_owned(),
+            delta_varpi: 0.01,
+            ..Default::default()
+        }],
+        error_floors: vec![ErrorFloor {
+            source: "Reid".to_owned(),
+            v_r: 3.,
+            v_t: 2.,
+            ..Default::default()
+        }],
+        l_v: 10.,
+        alpha_rz: 5.,
+        sigma_profile: SigmaProfile::Exponential,
+        h_sigma_r: 10.,
+        h_sigma_theta: 12.,
+        h_sigma_z: 8.,
+        fixed: vec!["A", "z_sun"],
+        ..true_params
+    };
+    let priors = Priors {
+        vec: vec![(
+            "omega_0",
+            Prior::Normal {
+                mean: 28.,
+                std_dev: 1.,
+            },
+        )],
+    };
+    for object in &mut objects {
+        object.compute(&params);
+    }
+    let triples = Rc::new(RefCell::new(vec![Vec::new(); objects.len()]));
+    let shared_objects = Rc::new(RefCell::new(objects));
+    let output_dir = PathBuf::new();
+    // The gradient should match the central differences of the cost
+    // (with the reduced parallaxes optimized anew at each point)
+    let n = 2;
+    let free_p = params.to_free_vec(n, false);
+    let names: Vec<&str> = izip!(params.names(n), params.frozen_mask(n, false))
+        .filter_map(|(name, frozen)| (!frozen).then_some(name))
+        .collect();
+    for disable_inner in [true, false] {
+        let problem = OuterOptimizationProblem {
+            disable_inner,
+            objects: &shared_objects,
+            params: &params,
+            priors: &priors,
+            triples: &triples,
+            output_dir: &output_dir,
+        };
+        let mut gradient = problem.inner_gradient(&params.insert_frozen(n, &free_p, false))?;
+        params.remove_frozen(n, &mut gradient, false);
+        let expected = free_p.central_diff(
+            &|p| {
+                problem
+                    .inner_cost(&params.insert_frozen(n, p, false), false)
+                    .unwrap()
+            },
+            1e-6,
+        );
+        ensure!(gradient.len() == expected.len());
+        for (name, x, y) in izip!(names.iter(), gradient, expected) {
+            ensure!(
+                (x - y).abs() < 1e-6 * (1. + y.abs()),
+                "The partial derivatives by `{name}` should match \
+                (disable_inner = {disable_inner}): {x} vs. {y}"
+            );
+        }
+    }
+    Ok(())
+}
@@ -3,15 +3,12 @@
 extern crate alloc;
 
 use super::Model;
-use super::{FitLogger, OuterOptimizationProblem, SigmaOuterOptimizationProblem};
+use super::{FitLogger, LogWriter, SigmaOuterOptimizationProblem};
 use crate::utils::FiniteDiff;
 
 use alloc::rc::Rc;
-use core::cell::RefCell;
 use core::fmt::{Debug, Display};
 use core::iter::Sum;
-use std::fs::File;
-use std::io::BufWriter;
 
 use anyhow::{Context, Result};
 use argmin::core::observers::ObserverMode;
@@ -41,9 +38,6 @@ pub const LBFGS_TOLERANCE_ERRORS: f64 = 1e-11;
 /// Maximum number of iterations
 pub const MAX_ITERS: u64 = 500;
 
-/// A term to add to the velocities of non-from-Reid objects
-pub const VEL_TERM: f64 = 10.;
-
 impl<F> Model<F> {
     /// Try to fit the model of the Galaxy to the data
     #[allow(clippy::as_conversions)]
@@ -62,7 +56,7 @@ impl<F> Model<F> {
         n: usize,
         sample_iteration: usize,
         l_stroke: usize,
-        fit_log_writer: &Rc<RefCell<BufWriter<File>>>,
+        fit_log_writer: &LogWriter,
     ) -> Result<()>
     where
         F: Float
@@ -103,16 +97,21 @@ impl<F> Model<F> {
         });
 
         let (best_cost, mut fit_params) = if l_stroke == 3 {
+            // The error floors are held at their initial values in this run
+            let params = self.params.with_fixed_error_floors();
             // Define the problem of the outer optimization
-            let problem = OuterOptimizationProblem {
+            let problem = SigmaOuterOptimizationProblem {
                 disable_inner: self.disable_inner,
+                l_stroke,
+                n,
                 objects: &self.objects,
-                params: &self.params,
+                fit_params: &params,
+                priors: &self.priors,
                 triples: &Rc::clone(&self.triples),
                 output_dir: &self.output_dir,
             };
             // Find the local minimum in the outer optimization
-            let init_param = self.params.to_vec(n, false);
+            let init_param = params.to_free_vec(n, false);
             let cond = ArmijoCondition::new(F::from(ARMIJO_PARAM).unwrap())?;
             let linesearch =
                 BacktrackingLineSearch::new(cond).rho(F::from(BACKTRACKING_PARAM).unwrap())?;
@@ -124,9 +123,10 @@ impl<F> Model<F> {
                 .add_observer(
                     FitLogger {
                         l_stroke,
+                        n,
                         sample_iteration,
                         objects: Rc::clone(&self.objects),
-                        params: self.params.clone(),
+                        params: params.clone(),
                         triples: Rc::clone(&self.triples),
                         writer: Rc::clone(fit_log_writer),
                     },
@@ -136,7 +136,7 @@ impl<F> Model<F> {
                 .with_context(|| "Couldn't solve the outer optimization problem")?;
 
             let best_cost = res.state().get_best_cost();
-            let best_point = res.state().get_best_param().unwrap().clone();
+            let best_point = params.insert_frozen(n, res.state().get_best_param().unwrap(), false);
 
             // Prepare storage for the new parameters
             let mut fit_params = self.params.clone();
@@ -148,13 +148,16 @@ impl<F> Model<F> {
             // Define the problem of the outer optimization
             let problem = SigmaOuterOptimizationProblem {
                 disable_inner: self.disable_inner,
+                l_stroke,
+                n,
                 objects: &self.objects,
                 fit_params: &fit_params,
+                priors: &self.priors,
                 triples: &Rc::clone(&self.triples),
                 output_dir: &self.output_dir,
             };
             // Find the local minimum in the outer optimization
-            let init_param = self.params.to_vec(n, true);
+            let init_param = self.params.to_free_vec(n, true);
             let cond = ArmijoCondition::new(F::from(ARMIJO_PARAM).unwrap())?;
             let linesearch =
                 BacktrackingLineSearch::new(cond).rho(F::from(BACKTRACKING_PARAM).unwrap())?;
@@ -166,6 +169,7 @@ impl<F> Model<F> {
                 .add_observer(
                     FitLogger {
                         l_stroke,
+                        n,
                         sample_iteration,
                         objects: Rc::clone(&self.objects),
                         params: self.params.clone(),
@@ -178,11 +182,8 @@ impl<F> Model<F> {
                 .with_context(|| "Couldn't solve the outer optimization problem")?;
 
             let best_cost = res.state().get_best_cost();
-            let mut best_point = res.state().get_best_param().unwrap().clone();
-
-            best_point.insert(6, fit_params.sigma_r_g);
-            best_point.insert(7, fit_params.sigma_theta);
-            best_point.insert(8, fit_params.sigma_z);
+            let best_point =
+                fit_params.insert_frozen(n, res.state().get_best_param().unwrap(), true);
 
             // Update the previous parameters
             fit_params.update_with(&best_point);
@@ -201,6 +202,13 @@ impl<F> Model<F> {
         // Save the results
         self.n = Some(n);
         self.best_cost = Some(best_cost);
+        // Subtracting from zero avoids a negative zero without the priors
+        self.best_prior_cost = Some(
+            F::zero()
+                - self
+                    .priors
+                    .ln_density(&fit_params.names(n), &fit_params.to_vec(n)),
+        );
         self.fit_params = Some(fit_params);
         Ok(())
     }
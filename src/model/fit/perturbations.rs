@@ -0,0 +1,225 @@
+//! Non-axisymmetric perturbations of the velocity field
+
+use super::Params;
+
+use core::fmt::{Debug, Display};
+use core::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use num::Float;
+use numeric_literals::replace_float_literals;
+
+/// Global radial velocity field (an expansion or a contraction of the disk)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RadialFlow {
+    /// No radial flow
+    #[default]
+    None,
+    /// Constant radial velocity, `V_R = V_flow`
+    Constant,
+    /// Radial velocity linear in R (the K term), `V_R = K_flow * R`
+    KTerm,
+}
+
+impl FromStr for RadialFlow {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "constant" => Ok(Self::Constant),
+            "k-term" => Ok(Self::KTerm),
+            _ => Err(anyhow!("Expected `none`, `constant`, or `k-term`")),
+        }
+    }
+}
+
+impl Display for RadialFlow {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let string = match *self {
+            Self::None => "none",
+            Self::Constant => "constant",
+            Self::KTerm => "k-term",
+        };
+        write!(f, "{string}")
+    }
+}
+
+/// Compute the global radial velocity (km/s, positive outward)
+/// at the Galactocentric distance `r_g`
+///
+/// The radial motion of the Sun itself is absorbed by its residual motion.
+pub fn compute_radial_flow_velocity<F>(r_g: F, params: &Params<F>) -> F
+where
+    F: Float,
+{
+    match params.radial_flow {
+        RadialFlow::None => F::zero(),
+        RadialFlow::Constant => params.v_flow,
+        RadialFlow::KTerm => params.k_flow * r_g,
+    }
+}
+
+/// Compute the perturbation by the spiral density wave (Lin, Shu 1964)
+/// of the velocity at the Galactocentric distance `r_g` and the
+/// Galactocentric longitude `lambda` (counted in the direction of rotation)
+///
+/// The phase of the wave is `chi = m (cot(i) ln(R / R_0) - lambda) + chi_0`,
+/// the perturbations are `V_R = -f_R cos(chi)` (positive outward) and
+/// `V_theta = f_theta sin(chi)` (positive in the direction of rotation).
+///
+/// The perturbation of the Sun itself is absorbed by its residual motion.
+///
+/// Returns a pair of the radial and azimuthal perturbations (km/s).
+#[allow(clippy::unwrap_used)]
+pub fn compute_spiral_velocity<F>(r_g: F, lambda: F, params: &Params<F>) -> (F, F)
+where
+    F: Float + Debug,
+{
+    let Some(n_arms) = params.spiral_arms else {
+        return (F::zero(), F::zero());
+    };
+    let Params {
+        r_0,
+        f_r,
+        f_theta,
+        i,
+        chi_0,
+        ..
+    } = *params;
+    let m = F::from(n_arms).unwrap();
+    let chi = m * ((r_g / r_0).ln() / i.to_radians().tan() - lambda) + chi_0.to_radians();
+    (-f_r * chi.cos(), f_theta * chi.sin())
+}
+
+/// Compute the perturbation by the central bar (m = 2) of the velocity
+/// at the Galactocentric distance `r_g` and the Galactocentric
+/// longitude `lambda` (counted in the direction of rotation)
+///
+/// The streaming is `V_R = V_bar g(R) sin(2 (lambda - phi_bar))` (positive
+/// outward) and `V_theta = V_bar g(R) cos(2 (lambda - phi_bar))` (positive in
+/// the direction of rotation), where `g(R) = x^2 exp(2 (1 - x))`, `x = R / R_bar`,
+/// vanishes at the center and far away from the bar and peaks at `R = R_bar`.
+///
+/// Returns a pair of the radial and azimuthal perturbations (km/s).
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+pub fn compute_bar_velocity<F>(r_g: F, lambda: F, params: &Params<F>) -> (F, F)
+where
+    F: Float + Debug,
+{
+    if !params.bar {
+        return (0., 0.);
+    }
+    let Params {
+        v_bar,
+        phi_bar,
+        r_bar,
+        ..
+    } = *params;
+    let x = r_g / r_bar;
+    let v = v_bar * x.powi(2) * (2. * (1. - x)).exp();
+    let angle = 2. * (lambda - phi_bar.to_radians());
+    (v * angle.sin(), v * angle.cos())
+}
+
+/// Compute the sum of the enabled perturbations of the velocity (including
+/// the global radial flow) in the Galactocentric frame and project it onto
+/// the heliocentric directions of the line of sight, the longitude and the latitude
+///
+/// The longitude and latitude are expected in the frame
+/// aligned with the Galactocentric one (see `Params::compute_tilt`).
+///
+/// Returns a triple of the velocities (km/s).
+#[allow(clippy::similar_names)]
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+pub fn compute_perturbation_velocities<F>(
+    l: F,
+    b: F,
+    r_h: F,
+    r_g: F,
+    params: &Params<F>,
+) -> (F, F, F)
+where
+    F: Float + Debug,
+{
+    if params.spiral_arms.is_none() && !params.bar && params.radial_flow == RadialFlow::None {
+        return (0., 0., 0.);
+    }
+    // Compute the sines and cosines of the longitude and latitude
+    let sin_l = l.sin();
+    let sin_b = b.sin();
+    let cos_l = l.cos();
+    let cos_b = b.cos();
+    // Compute the sines and cosines of the Galactocentric longitude
+    let sin_lambda = (r_h * cos_b) / r_g * sin_l;
+    let (_, cos_tilt) = params.compute_tilt();
+    let cos_lambda = (params.r_0 * cos_tilt - r_h * cos_b * cos_l) / r_g;
+    let lambda = sin_lambda.atan2(cos_lambda);
+    // Sum the perturbations
+    let (v_r_g_spiral, v_theta_spiral) = compute_spiral_velocity(r_g, lambda, params);
+    let (v_r_g_bar, v_theta_bar) = compute_bar_velocity(r_g, lambda, params);
+    let v_r_g = v_r_g_spiral + v_r_g_bar + compute_radial_flow_velocity(r_g, params);
+    let v_theta = v_theta_spiral + v_theta_bar;
+    // Compute the heliocentric Cartesian components (toward
+    // the Galactic center and toward l = 90 degrees)
+    let v_x = -v_r_g * cos_lambda + v_theta * sin_lambda;
+    let v_y = v_r_g * sin_lambda + v_theta * cos_lambda;
+    // Project onto the directions
+    let v_r = (v_x * cos_l + v_y * sin_l) * cos_b;
+    let v_l = -v_x * sin_l + v_y * cos_l;
+    let v_b = -(v_x * cos_l + v_y * sin_l) * sin_b;
+    (v_r, v_l, v_b)
+}
+
+#[cfg(test)]
+use anyhow::ensure;
+
+#[test]
+fn test() -> Result<()> {
+    // A purely azimuthal perturbation should be
+    // projected the same way as the rotation is
+    let params = Params {
+        r_0: 8.15,
+        f_theta: 4.,
+        i: -13.,
+        chi_0: -140.,
+        spiral_arms: Some(4),
+        ..Default::default()
+    };
+    let (l, b, r_h) = (0.7_f64, 0.1_f64, 3.);
+    let r_g = (params.r_0.powi(2) + (r_h * b.cos()).powi(2)
+        - 2. * params.r_0 * r_h * b.cos() * l.cos())
+    .sqrt();
+    let sin_lambda = r_h * b.cos() / r_g * l.sin();
+    let cos_lambda = (params.r_0 - r_h * b.cos() * l.cos()) / r_g;
+    let (v_r_g, v_theta) = compute_spiral_velocity(r_g, sin_lambda.atan2(cos_lambda), &params);
+    ensure!(v_r_g == 0., "There should be no radial perturbation");
+    let (v_r, v_l, v_b) = compute_perturbation_velocities(l, b, r_h, r_g, &params);
+    let projections = [
+        v_theta * params.r_0 / r_g * l.sin() * b.cos(),
+        v_theta * (params.r_0 * l.cos() - r_h * b.cos()) / r_g,
+        -v_theta * params.r_0 / r_g * l.sin() * b.sin(),
+    ];
+    for (value, expected) in [v_r, v_l, v_b].into_iter().zip(projections) {
+        ensure!(
+            (value - expected).abs() < 1e-12,
+            "The projections should match: {value} vs. {expected}"
+        );
+    }
+    // The projections of the radial flow should keep its magnitude
+    let flow_params = Params {
+        r_0: 8.15,
+        v_flow: 5.,
+        radial_flow: RadialFlow::Constant,
+        ..Default::default()
+    };
+    let (flow_r, flow_l, flow_b) = compute_perturbation_velocities(l, b, r_h, r_g, &flow_params);
+    let v = (flow_r.powi(2) + flow_l.powi(2) + flow_b.powi(2)).sqrt();
+    ensure!(
+        (v - 5.).abs() < 1e-12,
+        "The magnitude of the radial flow should be kept: {v}"
+    );
+    Ok(())
+}
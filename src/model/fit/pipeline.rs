@@ -0,0 +1,402 @@
+//! Fitting pipeline
+
+extern crate alloc;
+
+use super::rotcurve::RotationCurveModel;
+use super::selection::{self, Criterion};
+use super::{LogWriter, Model, Object, Triple};
+use crate::model::N_MAX;
+use crate::utils::FiniteDiff;
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt::{Debug, Display};
+use core::iter::{self, Sum};
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{ensure, Context, Result};
+use argmin::core::ArgminFloat;
+use argmin_math::{
+    ArgminAdd, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax, ArgminMul, ArgminSignum,
+    ArgminSub, ArgminZeroLike,
+};
+use indoc::indoc;
+use nalgebra::ComplexField;
+use num::Float;
+
+/// Settings of the fitting pipeline
+#[derive(Debug, Clone)]
+pub struct FitSettings {
+    /// Optimal degree of the polynomial of the rotation curve
+    pub n_best: usize,
+    /// Information criterion to choose the optimal degree with
+    ///
+    /// If specified, `n_best` is ignored, and the degree is chosen
+    /// after the first fit, before the checks for outliers.
+    pub n_best_criterion: Option<Criterion>,
+    /// Maximum degree of the polynomial of the rotation curve
+    pub n_max: usize,
+    /// Try to define the confidence intervals?
+    pub with_errors: bool,
+    /// Compute the frozen profiles (before the confidence intervals)?
+    pub with_frozen_profiles: bool,
+    /// Disable the checks for outliers?
+    pub disable_outliers: bool,
+}
+
+impl Default for FitSettings {
+    fn default() -> Self {
+        Self {
+            n_best: 1,
+            n_best_criterion: None,
+            n_max: 6,
+            with_errors: false,
+            with_frozen_profiles: false,
+            disable_outliers: false,
+        }
+    }
+}
+
+/// Log writers of the fitting pipeline
+pub struct FitLogs {
+    /// Fit logs (one per degree, starting from 1)
+    pub fit: Vec<LogWriter>,
+    /// Outliers log
+    pub outliers: LogWriter,
+    /// Errors log
+    pub errors: LogWriter,
+}
+
+impl FitLogs {
+    /// Create logs which discard everything written to them
+    pub fn sink(n_max: usize) -> Self {
+        let sink = || -> LogWriter { Rc::new(RefCell::new(io::sink())) };
+        Self {
+            fit: iter::repeat_with(sink).take(n_max).collect(),
+            outliers: sink(),
+            errors: sink(),
+        }
+    }
+}
+
+/// Results of the fitting pipeline
+#[derive(Debug, Clone)]
+pub struct FitResults<F> {
+    /// Fitted models (one per degree, starting from 1)
+    pub models: Vec<Model<F>>,
+    /// Optimal degree of the polynomial of the rotation curve
+    pub n_best: usize,
+    /// Progression of the number of the objects (total,
+    /// after the L' = 3 run, after the L' = 1 run)
+    pub n_objects: Vec<usize>,
+}
+
+impl<F> FitResults<F> {
+    /// Get the model with the optimal degree
+    #[allow(clippy::indexing_slicing)]
+    pub fn best_model(&self) -> &Model<F> {
+        &self.models[self.n_best - 1]
+    }
+}
+
+impl<F> Model<F>
+where
+    F: Float
+        + Debug
+        + Default
+        + Display
+        + Sync
+        + Send
+        + Sum
+        + ArgminFloat
+        + ArgminL2Norm<F>
+        + ArgminSub<F, F>
+        + ArgminAdd<F, F>
+        + ArgminDot<F, F>
+        + ArgminMul<F, F>
+        + ArgminZeroLike
+        + ArgminMul<Vec<F>, Vec<F>>
+        + ComplexField,
+    Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminSub<F, Vec<F>>,
+    Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminAdd<F, Vec<F>>,
+    Vec<F>: ArgminMul<F, Vec<F>>,
+    Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminL1Norm<F>,
+    Vec<F>: ArgminSignum,
+    Vec<F>: ArgminMinMax,
+    Vec<F>: ArgminDot<Vec<F>, F>,
+    Vec<F>: ArgminL2Norm<F>,
+    Vec<F>: FiniteDiff<F>,
+{
+    /// Fit the models with the degrees from 1 to `n_max` to the
+    /// data, checking for the outliers via the optimal one
+    pub fn fit(&self, settings: &FitSettings) -> Result<FitResults<F>> {
+        let logs = FitLogs::sink(settings.n_max);
+        self.fit_with(settings, &logs, |_, _, _| Ok(()))
+    }
+    /// Fit the models the same way as `fit` does, but also write the
+    /// logs and call `on_stage` after both the L' = 3 and L' = 1 runs
+    /// (with the stage, the optimal degree, and the models)
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::print_stderr)]
+    #[allow(clippy::too_many_lines)]
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    #[allow(clippy::use_debug)]
+    pub fn fit_with<FN>(
+        &self,
+        settings: &FitSettings,
+        logs: &FitLogs,
+        mut on_stage: FN,
+    ) -> Result<FitResults<F>>
+    where
+        FN: FnMut(usize, usize, &mut [Self]) -> Result<()>,
+    {
+        ensure!(
+            1 <= settings.n_best && settings.n_best <= settings.n_max && settings.n_max <= N_MAX,
+            "The degrees should satisfy 1 <= n_best <= n_max <= {N_MAX}"
+        );
+        ensure!(
+            settings.n_max <= self.params.rotcurve.n_max(),
+            "The family of the rotation curve `{}` supports the degrees up to {}",
+            self.params.rotcurve,
+            self.params.rotcurve.n_max(),
+        );
+        ensure!(
+            logs.fit.len() == settings.n_max,
+            "There should be a fit log for each degree"
+        );
+
+        // Prepare several models
+        let mut models: Vec<Self> = (1..=settings.n_max)
+            .map(|n| self.clone_with_degree(n))
+            .collect();
+
+        writeln!(
+            logs.outliers.borrow_mut(),
+            "{}",
+            indoc!(
+                "
+                Outliers
+
+                `m` is the index of the discrepancy (starting from 1), as in the array [V_r, mu_l', mu_b, par_r]."
+            ),
+        )?;
+
+        let mut best_i = settings.n_best - 1;
+        let mut best_n = settings.n_best;
+
+        let mut n_objects = vec![models[best_i].objects.borrow().len()];
+
+        let mut sample_iteration = 0;
+        for l_stroke in [3, 1] {
+            'samples: loop {
+                // Fit the parameters for each model
+                for (i, (model, fit_log_writer)) in models.iter_mut().zip(&logs.fit).enumerate() {
+                    let n = i + 1;
+
+                    // Try to fit a model with the specified degree
+                    model
+                        .try_fit_params(n, sample_iteration, l_stroke, fit_log_writer)
+                        .with_context(|| "Couldn't fit the model")?;
+                }
+
+                // Choose the optimal degree after the first fit
+                if let (Some(criterion), 3, 0) =
+                    (settings.n_best_criterion, l_stroke, sample_iteration)
+                {
+                    let n_selection = selection::compute_n_selection(&models);
+                    best_n = selection::select_n(&n_selection, criterion)
+                        .with_context(|| "Couldn't choose the optimal degree")?;
+                    best_i = best_n - 1;
+                    writeln!(
+                        logs.outliers.borrow_mut(),
+                        "\nbest_n chosen via {criterion}: {best_n}"
+                    )?;
+                }
+
+                // Check for the outliers via the best model
+                {
+                    let best_model = &mut models[best_i];
+                    let before_nonoutliers_count = best_model.count_non_outliers();
+
+                    let mut outliers_log_writer = logs.outliers.borrow_mut();
+
+                    writeln!(
+                        outliers_log_writer,
+                        "\nsample_iteration: {sample_iteration}",
+                    )?;
+                    writeln!(
+                        outliers_log_writer,
+                        "before_nonoutliers_count: {before_nonoutliers_count}"
+                    )?;
+                    writeln!(outliers_log_writer, "best_n: {best_n}")?;
+                    writeln!(outliers_log_writer, "l_stroke: {l_stroke}")?;
+
+                    if settings.disable_outliers {
+                        break 'samples;
+                    }
+
+                    let (one_dimensional_outliers, multi_dimensional_outliers) = best_model
+                        .find_outliers(l_stroke)
+                        .with_context(|| "Couldn't check for outliers")?;
+
+                    if one_dimensional_outliers.vec.is_empty()
+                        && multi_dimensional_outliers.vec.is_empty()
+                    {
+                        break 'samples;
+                    }
+
+                    let objects = best_model.objects.borrow();
+                    if !one_dimensional_outliers.vec.is_empty() {
+                        writeln!(
+                            outliers_log_writer,
+                            "\none-dimensional:\nm{s:4}rel_discrepancy{s:1}kappa{s:13}k_005{s:13}i{s:3}source name",
+                            s = " "
+                        )?;
+                        for &(m, i, rel_discrepancy) in &one_dimensional_outliers.vec {
+                            let object = &objects[i];
+                            writeln!(
+                                outliers_log_writer,
+                                "{} {rel_discrepancy:>18.15} {:<17.15} {:<17.15} {:<3} {:6} {}",
+                                m + 1,
+                                one_dimensional_outliers.kappa,
+                                one_dimensional_outliers.k_005,
+                                i + 1,
+                                object.source.as_ref().unwrap(),
+                                object.name.as_ref().unwrap(),
+                            )?;
+                        }
+                    }
+                    if !multi_dimensional_outliers.vec.is_empty() {
+                        writeln!(
+                            outliers_log_writer,
+                            "\n{}:\n{s:18}z{s:1}kappa{s:14}k_005{s:14}i{s:3}source name",
+                            if best_model.disable_inner {
+                                "three-dimensional"
+                            } else {
+                                "four-dimensional"
+                            },
+                            s = " ",
+                        )?;
+                        for &(i, rel_discrepancy) in &multi_dimensional_outliers.vec {
+                            let object = &objects[i];
+                            writeln!(
+                                outliers_log_writer,
+                                "{rel_discrepancy:>19.15} {:<18.15} {:<18.15} {:<3} {:6} {}",
+                                multi_dimensional_outliers.kappa,
+                                multi_dimensional_outliers.k_005,
+                                i + 1,
+                                object.source.as_ref().unwrap(),
+                                object.name.as_ref().unwrap(),
+                            )?;
+                        }
+                    }
+
+                    outliers_log_writer.flush()?;
+                }
+
+                // Update the outliers
+                let outliers_mask = models[best_i].get_outliers_mask();
+                for (i, model) in models.iter_mut().enumerate() {
+                    if i != best_i {
+                        model.apply_outliers_mask(&outliers_mask);
+                    }
+                }
+
+                sample_iteration += 1;
+            }
+
+            logs.outliers.borrow_mut().flush()?;
+            for fit_log_writer in &logs.fit {
+                fit_log_writer.borrow_mut().flush()?;
+            }
+
+            let l_stroke_n = models[best_i].count_non_outliers();
+            n_objects.push(l_stroke_n);
+
+            for (i, model) in models.iter_mut().enumerate() {
+                let n = i + 1;
+
+                if model.fit_params.is_none() {
+                    continue;
+                }
+
+                if l_stroke == 1 {
+                    model.l_stroke_1_n = Some(l_stroke_n);
+                } else {
+                    model.l_stroke_3_n = Some(l_stroke_n);
+                }
+
+                if settings.with_frozen_profiles {
+                    model
+                        .try_compute_frozen_profiles(l_stroke)
+                        .with_context(|| "Couldn't compute frozen profiles")?;
+                }
+
+                model.errors_fitted = false;
+                if settings.with_errors {
+                    writeln!(logs.errors.borrow_mut(), "n: {n}\n")?;
+                    let res = model
+                        .try_fit_errors(&logs.errors, l_stroke)
+                        .with_context(|| "Couldn't compute the errors");
+                    match res {
+                        Ok(()) => model.errors_fitted = true,
+                        Err(ref err) => eprintln!("{err:?}"),
+                    }
+                }
+
+                logs.errors.borrow_mut().flush()?;
+
+                if l_stroke == 1 {
+                    model.compute_covariance_matrix()?;
+                    model.post_fit();
+                }
+            }
+
+            on_stage(l_stroke, best_n, &mut models)?;
+        }
+
+        Ok(FitResults {
+            models,
+            n_best: best_n,
+            n_objects,
+        })
+    }
+}
+
+impl<F> Model<F> {
+    /// Get the output directory of the model with the
+    /// specified degree of the polynomial of the rotation curve
+    pub fn degree_output_dir(&self, n: usize) -> PathBuf {
+        self.output_dir.join(format!("n = {n}"))
+    }
+    /// Clone the model, replacing the objects
+    pub(in crate::model) fn with_objects(&self, objects: Vec<Object<F>>) -> Self
+    where
+        F: Clone + Default,
+    {
+        let triples = vec![vec![Triple::default(); 4]; objects.len()];
+        Self {
+            objects: Rc::new(RefCell::new(objects)),
+            triples: Rc::new(RefCell::new(triples)),
+            ..self.clone()
+        }
+    }
+    /// Clone the model for the specified degree of the polynomial
+    /// of the rotation curve, not sharing the objects and the triples
+    fn clone_with_degree(&self, n: usize) -> Self
+    where
+        F: Clone,
+    {
+        Self {
+            objects: Rc::new(RefCell::new(self.objects.borrow().clone())),
+            triples: Rc::new(RefCell::new(self.triples.borrow().clone())),
+            output_dir: self.degree_output_dir(n),
+            ..self.clone()
+        }
+    }
+}
@@ -3,8 +3,8 @@
 extern crate alloc;
 
 use super::io::output;
+use super::Model;
 use super::{ConfidenceIntervalProblem, OuterOptimizationProblem, Triple, Triples};
-use super::{Model, Params, PARAMS_N, PARAMS_NAMES};
 use crate::utils::FiniteDiff;
 
 use alloc::rc::Rc;
@@ -86,7 +86,7 @@ impl<F> Model<F> {
     {
         // Get the optimized parameters as arrays
         let n = self.n.unwrap();
-        let fit_params = self.fit_params.as_ref().unwrap().to_vec(n, false);
+        let fit_params = self.fit_params.as_ref().unwrap().to_vec(n);
         let fit_params_ep = self.fit_params.as_ref().unwrap().to_ep_vec(n);
         let fit_params_em = self.fit_params.as_ref().unwrap().to_em_vec(n);
         // Prepare storage
@@ -98,7 +98,7 @@ impl<F> Model<F> {
         let len = fit_params.len();
         for index in 0..len {
             // Don't compute for the sigmas or compute for the sigmas only
-            if Params::<F>::compute_with_l_stroke(index, l_stroke) {
+            if self.params.compute_with_l_stroke(n, index, l_stroke) {
                 continue;
             }
 
@@ -118,103 +118,131 @@ impl<F> Model<F> {
                 &triples,
             )?;
 
-            self.serialize_to_profile(&ProfileType::Conditional, &profile, PARAMS_NAMES[index])
-                .with_context(|| "Couldn't write a conditional profile to a file")?;
+            self.serialize_to_profile(
+                &ProfileType::Conditional,
+                &profile,
+                self.params.names(n)[index],
+            )
+            .with_context(|| "Couldn't write a conditional profile to a file")?;
         }
 
-        // Compute the conditional profile for derived values,
-        // too, by temporarily changing the parametrization
+        // Compute the conditional profiles for the derived values, too
         if l_stroke == 1 {
-            {
-                let fit_param = self.fit_params.as_ref().unwrap().theta_0;
-                let fit_param_ep = self.fit_params.as_ref().unwrap().theta_0_ep;
-                let fit_param_em = self.fit_params.as_ref().unwrap().theta_0_em;
+            self.try_compute_derived_profiles(l_stroke, &triples)?;
+        }
 
+        Ok(())
+    }
+    /// Try to compute the conditional profiles for the derived values
+    /// by temporarily changing the parametrization (unless the
+    /// mimicked parameter is fixed)
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::type_complexity)]
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    #[replace_float_literals(F::from(literal).unwrap())]
+    fn try_compute_derived_profiles(
+        &mut self,
+        l_stroke: usize,
+        triples: &Rc<RefCell<Vec<Triples<F>>>>,
+    ) -> Result<()>
+    where
+        F: Float
+            + Debug
+            + Default
+            + Display
+            + Sync
+            + Send
+            + Sum
+            + ArgminFloat
+            + ArgminL2Norm<F>
+            + ArgminSub<F, F>
+            + ArgminAdd<F, F>
+            + ArgminDot<F, F>
+            + ArgminMul<F, F>
+            + ArgminZeroLike
+            + ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminSub<F, Vec<F>>,
+        Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminAdd<F, Vec<F>>,
+        Vec<F>: ArgminMul<F, Vec<F>>,
+        Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+        Vec<F>: ArgminL1Norm<F>,
+        Vec<F>: ArgminSignum,
+        Vec<F>: ArgminMinMax,
+        Vec<F>: ArgminDot<Vec<F>, F>,
+        Vec<F>: ArgminL2Norm<F>,
+        Vec<F>: FiniteDiff<F>,
+    {
+        let n = self.n.unwrap();
+        let fit_params = self.fit_params.as_ref().unwrap();
+        // Names of the derived values, their values with the plus and minus
+        // uncertainties, indices of the mimicked parameters, and the functions
+        // computing the mimicked parameters from the derived values
+        let derived: [(&str, [F; 3], usize, fn(F, &[F]) -> F); 4] = [
+            (
+                "theta_0",
+                [
+                    fit_params.theta_0,
+                    fit_params.theta_0_ep,
+                    fit_params.theta_0_em,
+                ],
+                1,
                 // `omega_0` = `theta_0` / `R_0`
-                let compute_param = |theta_0: F, p: &[F]| theta_0 / p[0];
-
-                let profile = self.try_compute_conditional_profile(
-                    l_stroke,
-                    1,
-                    compute_param,
-                    fit_param,
-                    fit_param_ep,
-                    fit_param_em,
-                    &triples,
-                )?;
-
-                self.serialize_to_profile(&ProfileType::Conditional, &profile, "theta_0")
-                    .with_context(|| "Couldn't write a conditional profile to a file")?;
-            }
-            {
-                let fit_param = self.fit_params.as_ref().unwrap().theta_1;
-                let fit_param_ep = self.fit_params.as_ref().unwrap().theta_1_ep;
-                let fit_param_em = self.fit_params.as_ref().unwrap().theta_1_em;
-
+                |theta_0, p| theta_0 / p[0],
+            ),
+            (
+                "theta_1",
+                [
+                    fit_params.theta_1,
+                    fit_params.theta_1_ep,
+                    fit_params.theta_1_em,
+                ],
+                1,
                 // `omega_0` = `theta_1` + 2 * `A`
-                //
-                // The index is 1 and not 2 because the
-                // frozen parameter (index 1) is removed
-                let compute_param = |theta_1: F, p: &[F]| theta_1 + 2. * p[1];
-
-                let profile = self.try_compute_conditional_profile(
-                    l_stroke,
-                    1,
-                    compute_param,
-                    fit_param,
-                    fit_param_ep,
-                    fit_param_em,
-                    &triples,
-                )?;
-
-                self.serialize_to_profile(&ProfileType::Conditional, &profile, "theta_1")
-                    .with_context(|| "Couldn't write a conditional profile to a file")?;
-            }
-            {
-                let fit_param = self.fit_params.as_ref().unwrap().theta_sun;
-                let fit_param_ep = self.fit_params.as_ref().unwrap().theta_sun_ep;
-                let fit_param_em = self.fit_params.as_ref().unwrap().theta_sun_em;
-
+                |theta_1, p| theta_1 + 2. * p[2],
+            ),
+            (
+                "theta_sun",
+                [
+                    fit_params.theta_sun,
+                    fit_params.theta_sun_ep,
+                    fit_params.theta_sun_em,
+                ],
+                4,
                 // `v_sun` = `theta_sun` - `R_0` * `omega_0`
-                let compute_param = |theta_sun: F, p: &[F]| theta_sun - p[0] * p[1];
-
-                let profile = self.try_compute_conditional_profile(
-                    l_stroke,
-                    4,
-                    compute_param,
-                    fit_param,
-                    fit_param_ep,
-                    fit_param_em,
-                    &triples,
-                )?;
-
-                self.serialize_to_profile(&ProfileType::Conditional, &profile, "theta_sun")
-                    .with_context(|| "Couldn't write a conditional profile to a file")?;
-            }
-            {
-                let fit_param = self.fit_params.as_ref().unwrap().omega_sun;
-                let fit_param_ep = self.fit_params.as_ref().unwrap().omega_sun_ep;
-                let fit_param_em = self.fit_params.as_ref().unwrap().omega_sun_em;
-
+                |theta_sun, p| theta_sun - p[0] * p[1],
+            ),
+            (
+                "omega_sun",
+                [
+                    fit_params.omega_sun,
+                    fit_params.omega_sun_ep,
+                    fit_params.omega_sun_em,
+                ],
+                1,
                 // Compute `omega_0` from `omega_sun` = `v_sun` / `R_0`
-                //
-                // The index is 3 and not 4 because the
-                // frozen parameter (index 1) is removed
-                let compute_param = |omega_sun: F, p: &[F]| omega_sun - p[3] / p[0];
-
-                let profile = self.try_compute_conditional_profile(
-                    l_stroke,
-                    1,
-                    compute_param,
-                    fit_param,
-                    fit_param_ep,
-                    fit_param_em,
-                    &triples,
-                )?;
-
-                self.serialize_to_profile(&ProfileType::Conditional, &profile, "omega_sun")
-                    .with_context(|| "Couldn't write a conditional profile to a file")?;
+                |omega_sun, p| omega_sun - p[4] / p[0],
+            ),
+        ];
+        for (name, [fit_param, fit_param_ep, fit_param_em], index, compute_param) in derived {
+            if self.params.is_fixed(n, index) {
+                continue;
             }
+
+            let profile = self.try_compute_conditional_profile(
+                l_stroke,
+                index,
+                compute_param,
+                fit_param,
+                fit_param_ep,
+                fit_param_em,
+                triples,
+            )?;
+
+            self.serialize_to_profile(&ProfileType::Conditional, &profile, name)
+                .with_context(|| "Couldn't write a conditional profile to a file")?;
         }
 
         Ok(())
@@ -289,6 +317,7 @@ impl<F> Model<F> {
                 params: &self.params,
                 compute_param: &compute_param,
                 fit_params: self.fit_params.as_ref().unwrap(),
+                priors: &self.priors,
                 triples: &Rc::clone(triples),
                 output_dir: &self.output_dir,
             };
@@ -339,9 +368,9 @@ impl<F> Model<F> {
     {
         // Get the optimized parameters as arrays
         let n = self.n.unwrap();
-        let fit_params = self.fit_params.as_ref().unwrap().to_vec(n, false);
-        let fit_params_ep = [1.0; PARAMS_N];
-        let fit_params_em = [1.0; PARAMS_N];
+        let fit_params = self.fit_params.as_ref().unwrap().to_vec(n);
+        let fit_params_ep = vec![1.0; fit_params.len()];
+        let fit_params_em = vec![1.0; fit_params.len()];
         // Prepare storage for the profiles and the reduced parallaxes
         let triple = vec![Triple::<F>::default(); 4];
         let triples = Rc::new(RefCell::new(vec![triple; self.objects.borrow().len()]));
@@ -358,7 +387,7 @@ impl<F> Model<F> {
         let len = fit_params.len();
         for index in 0..len {
             // Don't compute for the sigmas or compute for the sigmas only
-            if Params::<F>::compute_with_l_stroke(index, l_stroke) {
+            if self.params.compute_with_l_stroke(n, index, l_stroke) {
                 continue;
             }
 
@@ -381,6 +410,7 @@ impl<F> Model<F> {
                     disable_inner: self.disable_inner,
                     objects: &objects,
                     params: &self.params,
+                    priors: &self.priors,
                     triples: &Rc::clone(&triples),
                     output_dir: &self.output_dir,
                 };
@@ -391,7 +421,7 @@ impl<F> Model<F> {
                 profile.push(ProfilePoint { param, cost });
             }
 
-            self.serialize_to_profile(&ProfileType::Frozen, &profile, PARAMS_NAMES[index])
+            self.serialize_to_profile(&ProfileType::Frozen, &profile, self.params.names(n)[index])
                 .with_context(|| "Couldn't write a frozen profile to a file")?;
         }
 
@@ -1,5 +1,11 @@
 //! Fit of the model (rotation curve)
 
+mod models;
+
+pub use models::{
+    BrandBlitz, FlatLinear, PowerLaw, RotationCurveKind, RotationCurveModel, Taylor, Universal,
+};
+
 use super::io::output;
 use super::{Model, Params};
 
@@ -37,7 +43,7 @@ impl<F> Model<F> {
     {
         let fit_params = self.fit_params.as_ref().unwrap();
         let n = self.n.unwrap();
-        let m = fit_params.to_vec(n, false).len();
+        let m = fit_params.to_vec(n).len();
 
         let n_points_int = 1000;
         let n_points_float = F::from(n_points_int).unwrap();
@@ -89,6 +95,8 @@ impl<F> Model<F> {
             "
             # Fit of the model (rotation curve)
             {sample_description}
+            # Family of the rotation curve: {rotcurve}
+            #
             # Descriptions:
             #
             # 01 R: Galactocentric distance to the Sun [kpc]
@@ -108,6 +116,7 @@ impl<F> Model<F> {
             #
             ",
             sample_description = self.format_sample_description(),
+            rotcurve = fit_params.rotcurve,
             r_0 = fit_params.r_0,
             omega_0 = fit_params.omega_0,
             a = fit_params.a,
@@ -138,7 +147,7 @@ where
 {
     let h = 1e-8;
 
-    let best_p = fit_params.to_vec(n, false);
+    let best_p = fit_params.to_vec(n);
 
     let mut new_fit_params = fit_params.clone();
     let mut p = best_p.clone();
@@ -154,35 +163,11 @@ where
     (plus_f - minus_f) / (2. * h)
 }
 
-/// Compute the linear rotation curve approximation via Taylor series
-#[allow(clippy::as_conversions)]
-#[allow(clippy::cast_possible_truncation)]
-#[allow(clippy::cast_possible_wrap)]
-#[allow(clippy::indexing_slicing)]
-#[allow(clippy::unwrap_used)]
-#[replace_float_literals(F::from(literal).unwrap())]
+/// Compute the difference between the model rotation curve and the
+/// solid-body rotation with `omega_0` (in the family chosen in the parameters)
 pub fn compute_rot_curve_series<F>(delta_r_g: F, fit_params: &Params<F>) -> F
 where
     F: Float,
 {
-    let Params {
-        a,
-        theta_2,
-        theta_3,
-        theta_4,
-        theta_5,
-        theta_6,
-        theta_7,
-        theta_8,
-        ..
-    } = *fit_params;
-
-    -2. * a * delta_r_g
-        + theta_2 / 2. * (delta_r_g).powi(2)
-        + theta_3 / 6. * (delta_r_g).powi(3)
-        + theta_4 / 24. * (delta_r_g).powi(4)
-        + theta_5 / 120. * (delta_r_g).powi(5)
-        + theta_6 / 720. * (delta_r_g).powi(6)
-        + theta_7 / 5040. * (delta_r_g).powi(7)
-        + theta_8 / 40320. * (delta_r_g).powi(8)
+    fit_params.rotcurve.compute_series(delta_r_g, fit_params)
 }
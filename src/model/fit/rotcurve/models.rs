@@ -0,0 +1,381 @@
+//! Families of the rotation curve
+
+use crate::model::Params;
+
+use core::fmt::Display;
+use core::str::FromStr;
+
+use anyhow::{anyhow, ensure, Result};
+use num::Float;
+use numeric_literals::replace_float_literals;
+
+/// Model of the rotation curve
+///
+/// Each model passes through `theta_0 = omega_0 * R_0` at R = R_0 with the
+/// slope `theta_1 = omega_0 - 2 A` there. The rest of the shape is defined by
+/// the shape parameters, which take the places of `theta_2`, `theta_3`, etc.
+/// in the point in the parameter space (one per degree starting from 2).
+pub trait RotationCurveModel {
+    /// Names of the shape parameters
+    fn shape_names(&self) -> &'static [&'static str];
+    /// Default initial values of the shape parameters
+    fn shape_defaults(&self) -> &'static [f64];
+    /// Compute the difference between the azimuthal velocity and the
+    /// velocity of the solid-body rotation with `omega_0` at R = R_0 + `delta_r_g`
+    fn compute_series<F>(&self, delta_r_g: F, params: &Params<F>) -> F
+    where
+        F: Float;
+    /// Maximum degree supported
+    fn n_max(&self) -> usize {
+        self.shape_names().len() + 1
+    }
+    /// Check that the shape parameters are in the domain of the model
+    fn check_shape<F>(&self, _params: &Params<F>) -> Result<()>
+    where
+        F: Float,
+    {
+        Ok(())
+    }
+}
+
+/// Taylor series in (R - R_0)
+#[derive(Debug, Clone, Copy)]
+pub struct Taylor;
+
+impl RotationCurveModel for Taylor {
+    fn shape_names(&self) -> &'static [&'static str] {
+        &[
+            "theta_2", "theta_3", "theta_4", "theta_5", "theta_6", "theta_7", "theta_8",
+        ]
+    }
+    fn shape_defaults(&self) -> &'static [f64] {
+        &[0.; 7]
+    }
+    #[allow(clippy::unwrap_used)]
+    #[replace_float_literals(F::from(literal).unwrap())]
+    fn compute_series<F>(&self, delta_r_g: F, params: &Params<F>) -> F
+    where
+        F: Float,
+    {
+        let Params {
+            a,
+            theta_2,
+            theta_3,
+            theta_4,
+            theta_5,
+            theta_6,
+            theta_7,
+            theta_8,
+            ..
+        } = *params;
+
+        -2. * a * delta_r_g
+            + theta_2 / 2. * (delta_r_g).powi(2)
+            + theta_3 / 6. * (delta_r_g).powi(3)
+            + theta_4 / 24. * (delta_r_g).powi(4)
+            + theta_5 / 120. * (delta_r_g).powi(5)
+            + theta_6 / 720. * (delta_r_g).powi(6)
+            + theta_7 / 5040. * (delta_r_g).powi(7)
+            + theta_8 / 40320. * (delta_r_g).powi(8)
+    }
+}
+
+/// Power law, `theta_0 * (R / R_0)^alpha`
+///
+/// The exponent is fixed by the slope, `alpha = theta_1 / omega_0`.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerLaw;
+
+impl RotationCurveModel for PowerLaw {
+    fn shape_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn shape_defaults(&self) -> &'static [f64] {
+        &[]
+    }
+    #[allow(clippy::unwrap_used)]
+    #[replace_float_literals(F::from(literal).unwrap())]
+    fn compute_series<F>(&self, delta_r_g: F, params: &Params<F>) -> F
+    where
+        F: Float,
+    {
+        let Params {
+            r_0, omega_0, a, ..
+        } = *params;
+        let r_g = r_0 + delta_r_g;
+        let alpha = (omega_0 - 2. * a) / omega_0;
+        omega_0 * r_0 * (r_g / r_0).powf(alpha) - omega_0 * r_g
+    }
+}
+
+/// Brand--Blitz curve, `theta_0 * (a_1 * (R / R_0)^a_2 + a_3)`
+///
+/// The shape parameter is `a_2`, while `a_1` and `a_3`
+/// are fixed by the value and the slope at R = R_0.
+///
+/// Source: Brand, Blitz (1993)
+#[derive(Debug, Clone, Copy)]
+pub struct BrandBlitz;
+
+impl RotationCurveModel for BrandBlitz {
+    fn shape_names(&self) -> &'static [&'static str] {
+        &["a_2"]
+    }
+    fn shape_defaults(&self) -> &'static [f64] {
+        &[0.0394]
+    }
+    #[allow(clippy::unwrap_used)]
+    #[replace_float_literals(F::from(literal).unwrap())]
+    fn compute_series<F>(&self, delta_r_g: F, params: &Params<F>) -> F
+    where
+        F: Float,
+    {
+        let Params {
+            r_0,
+            omega_0,
+            a,
+            theta_2: a_2,
+            ..
+        } = *params;
+        let r_g = r_0 + delta_r_g;
+        // See `check_shape`
+        let a_1 = (omega_0 - 2. * a) / omega_0 / a_2;
+        let a_3 = 1. - a_1;
+        omega_0 * r_0 * (a_1 * (r_g / r_0).powf(a_2) + a_3) - omega_0 * r_g
+    }
+    fn check_shape<F>(&self, params: &Params<F>) -> Result<()>
+    where
+        F: Float,
+    {
+        // The amplitude `a_1` is inversely proportional to the exponent
+        ensure!(
+            params.theta_2 > F::zero(),
+            "The exponent `a_2` of the `brand-blitz` curve should be positive"
+        );
+        Ok(())
+    }
+}
+
+/// Universal rotation curve, `sqrt(c_d * D(x) + c_h * H(x))`, where
+/// `x = R / R_opt`, `D(x)` and `H(x)` are the profiles of the disk and the
+/// halo terms, `R_opt = a_2 * R_0`, and `a_3` is the core radius of the halo
+/// in the units of `R_opt`
+///
+/// The amplitudes `c_d` and `c_h` (fixed by the luminosity in the
+/// original form) are fixed by the value and the slope at R = R_0.
+///
+/// Sources: Persic, Salucci, Stel (1996); Reid et al. (2019)
+#[derive(Debug, Clone, Copy)]
+pub struct Universal;
+
+impl RotationCurveModel for Universal {
+    fn shape_names(&self) -> &'static [&'static str] {
+        &["a_2", "a_3"]
+    }
+    fn shape_defaults(&self) -> &'static [f64] {
+        &[0.90, 1.46]
+    }
+    #[allow(clippy::unwrap_used)]
+    #[allow(clippy::similar_names)]
+    #[replace_float_literals(F::from(literal).unwrap())]
+    fn compute_series<F>(&self, delta_r_g: F, params: &Params<F>) -> F
+    where
+        F: Float,
+    {
+        let Params {
+            r_0,
+            omega_0,
+            a,
+            theta_2: a_2,
+            theta_3: a_3,
+            ..
+        } = *params;
+        let r_g = r_0 + delta_r_g;
+        let r_opt = a_2 * r_0;
+        // Profiles of the disk and the halo terms and their derivatives
+        let disk = |x: F| 1.97 * x.powf(1.22) / (x.powi(2) + 0.78.powi(2)).powf(1.43);
+        let d_disk = |x: F| disk(x) * (1.22 / x - 2.86 * x / (x.powi(2) + 0.78.powi(2)));
+        let halo = |x: F| x.powi(2) / (x.powi(2) + a_3.powi(2));
+        let d_halo = |x: F| 2. * x * a_3.powi(2) / (x.powi(2) + a_3.powi(2)).powi(2);
+        // Match the square of the velocity and its derivative at R = R_0
+        let x_0 = r_0 / r_opt;
+        let theta_0 = omega_0 * r_0;
+        let theta_1 = omega_0 - 2. * a;
+        let v_sq = theta_0.powi(2);
+        let d_v_sq = 2. * theta_0 * theta_1 * r_opt;
+        let det = disk(x_0) * d_halo(x_0) - halo(x_0) * d_disk(x_0);
+        let c_d = (v_sq * d_halo(x_0) - halo(x_0) * d_v_sq) / det;
+        let c_h = (disk(x_0) * d_v_sq - v_sq * d_disk(x_0)) / det;
+        // Compute the velocity
+        let x = r_g / r_opt;
+        (c_d * disk(x) + c_h * halo(x)).sqrt() - omega_0 * r_g
+    }
+    fn check_shape<F>(&self, params: &Params<F>) -> Result<()>
+    where
+        F: Float,
+    {
+        ensure!(
+            params.theta_2 > F::zero(),
+            "The optical radius `a_2` of the `urc` curve should be positive"
+        );
+        Ok(())
+    }
+}
+
+/// Flat rotation curve with a linear term, `theta_0 + theta_1 * (R - R_0)`
+///
+/// This is the same curve as the Taylor series of the first degree. It's kept
+/// as a separate family, since it's the named model the results are compared
+/// with in the literature: choosing it pins the degree to 1 (there are no
+/// shape parameters), so the degree isn't selected, and the outputs are
+/// labelled with this family rather than with a truncated series.
+///
+/// Source: Reid et al. (2014)
+#[derive(Debug, Clone, Copy)]
+pub struct FlatLinear;
+
+impl RotationCurveModel for FlatLinear {
+    fn shape_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn shape_defaults(&self) -> &'static [f64] {
+        &[]
+    }
+    #[allow(clippy::unwrap_used)]
+    #[replace_float_literals(F::from(literal).unwrap())]
+    fn compute_series<F>(&self, delta_r_g: F, params: &Params<F>) -> F
+    where
+        F: Float,
+    {
+        -2. * params.a * delta_r_g
+    }
+}
+
+/// Family of the rotation curve
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RotationCurveKind {
+    /// Taylor series in (R - R_0)
+    #[default]
+    Taylor,
+    /// Power law
+    PowerLaw,
+    /// Brand--Blitz curve
+    BrandBlitz,
+    /// Universal rotation curve
+    Universal,
+    /// Flat rotation curve with a linear term
+    FlatLinear,
+}
+
+impl RotationCurveModel for RotationCurveKind {
+    fn shape_names(&self) -> &'static [&'static str] {
+        match *self {
+            Self::Taylor => Taylor.shape_names(),
+            Self::PowerLaw => PowerLaw.shape_names(),
+            Self::BrandBlitz => BrandBlitz.shape_names(),
+            Self::Universal => Universal.shape_names(),
+            Self::FlatLinear => FlatLinear.shape_names(),
+        }
+    }
+    fn shape_defaults(&self) -> &'static [f64] {
+        match *self {
+            Self::Taylor => Taylor.shape_defaults(),
+            Self::PowerLaw => PowerLaw.shape_defaults(),
+            Self::BrandBlitz => BrandBlitz.shape_defaults(),
+            Self::Universal => Universal.shape_defaults(),
+            Self::FlatLinear => FlatLinear.shape_defaults(),
+        }
+    }
+    fn compute_series<F>(&self, delta_r_g: F, params: &Params<F>) -> F
+    where
+        F: Float,
+    {
+        match *self {
+            Self::Taylor => Taylor.compute_series(delta_r_g, params),
+            Self::PowerLaw => PowerLaw.compute_series(delta_r_g, params),
+            Self::BrandBlitz => BrandBlitz.compute_series(delta_r_g, params),
+            Self::Universal => Universal.compute_series(delta_r_g, params),
+            Self::FlatLinear => FlatLinear.compute_series(delta_r_g, params),
+        }
+    }
+    fn check_shape<F>(&self, params: &Params<F>) -> Result<()>
+    where
+        F: Float,
+    {
+        match *self {
+            Self::Taylor => Taylor.check_shape(params),
+            Self::PowerLaw => PowerLaw.check_shape(params),
+            Self::BrandBlitz => BrandBlitz.check_shape(params),
+            Self::Universal => Universal.check_shape(params),
+            Self::FlatLinear => FlatLinear.check_shape(params),
+        }
+    }
+}
+
+impl FromStr for RotationCurveKind {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string.to_lowercase().as_str() {
+            "taylor" => Ok(Self::Taylor),
+            "power-law" => Ok(Self::PowerLaw),
+            "brand-blitz" => Ok(Self::BrandBlitz),
+            "urc" => Ok(Self::Universal),
+            "flat-linear" => Ok(Self::FlatLinear),
+            _ => Err(anyhow!(
+                "Expected `taylor`, `power-law`, `brand-blitz`, `urc`, or `flat-linear`"
+            )),
+        }
+    }
+}
+
+impl Display for RotationCurveKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let string = match *self {
+            Self::Taylor => "taylor",
+            Self::PowerLaw => "power-law",
+            Self::BrandBlitz => "brand-blitz",
+            Self::Universal => "urc",
+            Self::FlatLinear => "flat-linear",
+        };
+        write!(f, "{string}")
+    }
+}
+
+#[test]
+fn test() -> Result<()> {
+    let kinds = [
+        RotationCurveKind::Taylor,
+        RotationCurveKind::PowerLaw,
+        RotationCurveKind::BrandBlitz,
+        RotationCurveKind::Universal,
+        RotationCurveKind::FlatLinear,
+    ];
+    for kind in kinds {
+        let mut p = vec![8.15, 28., 17., 10.7, 19., 7.7, 12., 6., 3.];
+        p.extend(kind.shape_defaults());
+        let mut params = Params {
+            rotcurve: kind,
+            ..Default::default()
+        };
+        params.update_with(&p);
+        // The curve should pass through `theta_0` with the slope `theta_1`
+        let h = 1e-6;
+        let value = kind.compute_series(0., &params);
+        let slope = (kind.compute_series(h, &params) - kind.compute_series(-h, &params)) / (2. * h);
+        ensure!(
+            value.abs() < 1e-9 && (slope + 2. * params.a).abs() < 1e-6,
+            "The `{kind}` curve doesn't match the value or the slope at R = R_0"
+        );
+        ensure!(kind.to_string().parse::<RotationCurveKind>()? == kind);
+        kind.check_shape(&params)?;
+    }
+    // The exponent of the Brand--Blitz curve can't be zero
+    let params = Params {
+        rotcurve: RotationCurveKind::BrandBlitz,
+        theta_2: 0.,
+        ..Default::default()
+    };
+    ensure!(params.rotcurve.check_shape(&params).is_err());
+    Ok(())
+}
@@ -0,0 +1,242 @@
+//! Selection of the degree of the polynomial of the rotation curve
+
+extern crate alloc;
+
+use super::io::output;
+use super::{FitResults, Model};
+
+use core::fmt::{Debug, Display};
+use core::str::FromStr;
+
+#[cfg(test)]
+use super::{Object, Params};
+#[cfg(test)]
+use alloc::rc::Rc;
+#[cfg(test)]
+use anyhow::ensure;
+use anyhow::{anyhow, Context, Result};
+#[cfg(test)]
+use core::cell::RefCell;
+use indoc::formatdoc;
+use num::Float;
+use numeric_literals::replace_float_literals;
+use serde::Serialize;
+
+/// Information criterion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Criterion {
+    /// Akaike information criterion
+    Aic,
+    /// Akaike information criterion with the correction for small samples
+    Aicc,
+    /// Bayesian information criterion
+    Bic,
+}
+
+impl FromStr for Criterion {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string.to_lowercase().as_str() {
+            "aic" => Ok(Self::Aic),
+            "aicc" => Ok(Self::Aicc),
+            "bic" => Ok(Self::Bic),
+            _ => Err(anyhow!("Expected `aic`, `aicc`, or `bic`")),
+        }
+    }
+}
+
+impl Display for Criterion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let string = match *self {
+            Self::Aic => "aic",
+            Self::Aicc => "aicc",
+            Self::Bic => "bic",
+        };
+        write!(f, "{string}")
+    }
+}
+
+/// Information criteria of the model with the specified degree
+#[derive(Debug, Clone, Serialize)]
+pub struct NSelection<F> {
+    /// Degree of the polynomial of the rotation curve
+    pub n: usize,
+    /// Number of the parameters
+    pub k: usize,
+    /// Number of the observations
+    pub n_obs: usize,
+    /// Best cost without the priors (negative log likelihood)
+    #[serde(rename = "L_1")]
+    pub cost: F,
+    /// Akaike information criterion
+    #[serde(rename = "AIC")]
+    pub aic: F,
+    /// Akaike information criterion with the correction for small samples
+    #[serde(rename = "AICc")]
+    pub aicc: F,
+    /// Bayesian information criterion
+    #[serde(rename = "BIC")]
+    pub bic: F,
+    /// Statistic of the likelihood-ratio test against the previous degree
+    #[serde(rename = "LRT")]
+    pub lrt: F,
+    /// P-value of the likelihood-ratio test
+    pub p_value: F,
+}
+
+impl<F> NSelection<F> {
+    /// Get the value of the criterion
+    pub fn get(&self, criterion: Criterion) -> F
+    where
+        F: Copy,
+    {
+        match criterion {
+            Criterion::Aic => self.aic,
+            Criterion::Aicc => self.aicc,
+            Criterion::Bic => self.bic,
+        }
+    }
+}
+
+/// Compute the information criteria of the fitted models
+/// (one per degree, starting from 1)
+///
+/// Each non-outlier object provides three observations. If the inner
+/// optimization is enabled, the parallax is observed, too, but it's spent
+/// on the reduced parallax fitted for the object, so the count stays the
+/// same (and the reduced parallaxes aren't counted as the parameters).
+/// The likelihood-ratio tests assume one extra degree
+/// of freedom per step in the degree.
+///
+/// The criteria are computed from the likelihood, that is, with
+/// the contribution of the priors subtracted from the best cost.
+///
+/// Sources: Akaike (1974); Hurvich, Tsai (1989); Schwarz (1978); Wilks (1938)
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+pub fn compute_n_selection<F>(models: &[Model<F>]) -> Vec<NSelection<F>>
+where
+    F: Float + Debug,
+{
+    let mut selection: Vec<NSelection<F>> = Vec::with_capacity(models.len());
+    for (i, model) in models.iter().enumerate() {
+        let n = i + 1;
+        let (Some(best_cost), Some(fit_params)) = (model.best_cost, model.fit_params.as_ref())
+        else {
+            continue;
+        };
+        let cost = best_cost - model.best_prior_cost.unwrap_or_else(F::zero);
+        let k = fit_params.to_free_vec(n, false).len();
+        let n_obs = model.count_non_outliers() * 3;
+
+        let k_f = F::from(k).unwrap();
+        let n_obs_f = F::from(n_obs).unwrap();
+        let aic = 2. * k_f + 2. * cost;
+        let aicc = if n_obs_f - k_f - 1. > 0. {
+            aic + 2. * k_f * (k_f + 1.) / (n_obs_f - k_f - 1.)
+        } else {
+            F::infinity()
+        };
+        let bic = k_f * n_obs_f.ln() + 2. * cost;
+
+        // The statistic has the chi-squared distribution with one degree of freedom
+        let (lrt, p_value) = match selection.last() {
+            Some(previous) if previous.n + 1 == n => {
+                let lrt = 2. * (previous.cost - cost);
+                let p_value_f64 = libm::erfc((lrt.max(0.) / 2.).sqrt().to_f64().unwrap());
+                (lrt, F::from(p_value_f64).unwrap())
+            }
+            _ => (F::nan(), F::nan()),
+        };
+
+        selection.push(NSelection {
+            n,
+            k,
+            n_obs,
+            cost,
+            aic,
+            aicc,
+            bic,
+            lrt,
+            p_value,
+        });
+    }
+    selection
+}
+
+/// Choose the degree with the smallest value of the criterion
+#[allow(clippy::unwrap_used)]
+pub fn select_n<F>(selection: &[NSelection<F>], criterion: Criterion) -> Option<usize>
+where
+    F: Float,
+{
+    selection
+        .iter()
+        .min_by(|a, b| a.get(criterion).partial_cmp(&b.get(criterion)).unwrap())
+        .map(|record| record.n)
+}
+
+impl<F> Model<F> {
+    /// Serialize the information criteria of the fitted models
+    pub fn serialize_to_n_selection(&self, results: &FitResults<F>) -> Result<()>
+    where
+        F: Float + Debug + Serialize,
+    {
+        let n_best = results.n_best;
+        let selection = compute_n_selection(&results.models);
+        let header = formatdoc!(
+            "
+            # Information criteria as a dependency of the degree
+            # of the polynomial of the rotation curve
+            #
+            # Descriptions:
+            #
+            #  1 n: Degree of the polynomial of the rotation curve
+            #  2 k: Number of the parameters
+            #  3 n_obs: Number of the observations
+            #  4 L_1: Best cost (negative log likelihood)
+            #  5 AIC: Akaike information criterion
+            #  6 AICc: Akaike information criterion with the correction for small samples
+            #  7 BIC: Bayesian information criterion
+            #  8 LRT: Statistic of the likelihood-ratio test against the previous degree
+            #  9 p_value: P-value of the likelihood-ratio test
+            #
+            # Optimal degree: {n_best}
+            #
+            "
+        );
+        output::serialize_to(&self.output_dir, "n_selection", &header, &selection)
+            .with_context(|| "Couldn't write the information criteria")
+    }
+}
+
+#[test]
+#[allow(clippy::indexing_slicing)]
+fn test() -> Result<()> {
+    ensure!(
+        "BIC".parse::<Criterion>()? == Criterion::Bic,
+        "The names of the criteria should be case-insensitive"
+    );
+    ensure!(
+        "bogus".parse::<Criterion>().is_err(),
+        "Unknown criteria should be rejected"
+    );
+    // The criteria shouldn't depend on the priors
+    let models: Vec<Model<f64>> = [(10., 3.), (6., 0.)]
+        .into_iter()
+        .map(|(best_cost, best_prior_cost)| Model {
+            best_cost: Some(best_cost),
+            best_prior_cost: Some(best_prior_cost),
+            fit_params: Some(Params::default()),
+            objects: Rc::new(RefCell::new(vec![Object::default(); 5])),
+            ..Default::default()
+        })
+        .collect();
+    let selection = compute_n_selection(&models);
+    ensure!((selection[0].cost - 7.).abs() < 1e-12 && (selection[1].cost - 6.).abs() < 1e-12);
+    ensure!((selection[1].lrt - 2.).abs() < 1e-12);
+    // The reduced parallaxes are profiled out of the likelihood
+    ensure!(!models[0].disable_inner && selection[0].n_obs == 15);
+    Ok(())
+}
@@ -1,9 +1,10 @@
 //! Outer optimization problem with frozen sigmas (frozen natural dispersions)
+//! and the parameters fixed by the user
 
 extern crate alloc;
 
 use super::outer::{Output, Param};
-use super::{Objects, Params};
+use super::{Objects, Params, Priors};
 use super::{OuterOptimizationProblem, Triples};
 use crate::utils::FiniteDiff;
 use alloc::rc::Rc;
@@ -22,13 +23,18 @@ use argmin_math::{
 use num::Float;
 use numeric_literals::replace_float_literals;
 
-/// A problem for the outer optimization, but with frozen sigmas
+/// A problem for the outer optimization, but with frozen sigmas (in the L' = 1
+/// run only) and the parameters fixed by the user (their values are taken from
+/// `fit_params`). The point is of the degree `n` with the frozen parameters removed.
 #[allow(clippy::missing_docs_in_private_items)]
 #[allow(clippy::type_complexity)]
 pub struct SigmaOuterOptimizationProblem<'a, F> {
     pub disable_inner: bool,
+    pub l_stroke: usize,
+    pub n: usize,
     pub objects: &'a Objects<F>,
     pub fit_params: &'a Params<F>,
+    pub priors: &'a Priors<F>,
     pub triples: &'a Rc<RefCell<Vec<Triples<F>>>>,
     pub output_dir: &'a PathBuf,
 }
@@ -80,16 +86,14 @@ where
             disable_inner: self.disable_inner,
             objects: self.objects,
             params: self.fit_params,
+            priors: self.priors,
             triples: self.triples,
             output_dir: self.output_dir,
         };
         // Prepare the parameter vector
-        let mut new_p = p.clone();
-        new_p.insert(6, self.fit_params.sigma_r_g);
-        new_p.insert(7, self.fit_params.sigma_theta);
-        new_p.insert(8, self.fit_params.sigma_z);
-        // Compute the cost
-        outer_problem.inner_cost(&new_p, false)
+        let new_p = self.fit_params.insert_frozen(self.n, p, self.l_stroke == 1);
+        // Compute the cost (the triples are updated in the L' = 3 run only)
+        outer_problem.inner_cost(&new_p, self.l_stroke == 3)
     }
 }
 
@@ -130,6 +134,22 @@ where
     #[allow(clippy::unwrap_used)]
     #[replace_float_literals(F::from(literal).unwrap())]
     fn gradient(&self, p: &Self::Param) -> Result<Self::Gradient> {
-        Ok((*p).central_diff(&|x| self.cost(x).unwrap(), F::sqrt(F::epsilon())))
+        // Create an outer problem
+        let outer_problem = OuterOptimizationProblem {
+            disable_inner: self.disable_inner,
+            objects: self.objects,
+            params: self.fit_params,
+            priors: self.priors,
+            triples: self.triples,
+            output_dir: self.output_dir,
+        };
+        // Prepare the parameter vector
+        let new_p = self.fit_params.insert_frozen(self.n, p, self.l_stroke == 1);
+        // Compute the gradient, skipping the partial
+        // derivatives by the frozen parameters
+        let mut gradient = outer_problem.inner_gradient(&new_p)?;
+        self.fit_params
+            .remove_frozen(self.n, &mut gradient, self.l_stroke == 1);
+        Ok(gradient)
     }
 }
@@ -1,38 +1,110 @@
 //! Input related
 
-use serde::Deserialize;
+use crate::utils::AngleUnit;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 
 /// Input data record
-#[derive(Deserialize)]
-pub(in crate::model) struct Record<F> {
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Record<F> {
     /// Name
-    pub(in crate::model) name: String,
-    /// Right ascension (string in the HMS form)
-    pub(in crate::model) alpha: String,
-    /// Declination (string in the DMS form)
-    pub(in crate::model) delta: String,
+    pub name: String,
+    /// Right ascension (string in one of the forms of `AngleUnit`)
+    pub alpha: String,
+    /// Declination (string in one of the forms of `AngleUnit`)
+    pub delta: String,
     /// Parallax
-    pub(in crate::model) par: F,
+    pub par: F,
     /// Uncertainty in `par`
-    pub(in crate::model) par_e: F,
+    pub par_e: F,
     /// Local Standard of Rest velocity
-    pub(in crate::model) v_lsr: F,
+    pub v_lsr: F,
     /// Uncertainty in `v_lsr`
-    pub(in crate::model) v_lsr_e: F,
+    pub v_lsr_e: F,
     /// Eastward proper motion
-    pub(in crate::model) mu_x: F,
+    pub mu_x: F,
     /// Uncertainty in `mu_x`
-    pub(in crate::model) mu_x_e: F,
+    pub mu_x_e: F,
     /// Northward proper motion
-    pub(in crate::model) mu_y: F,
+    pub mu_y: F,
     /// Uncertainty in `mu_y`
-    pub(in crate::model) mu_y_e: F,
+    pub mu_y_e: F,
     /// Type of the object
     #[serde(rename = "type")]
-    pub(in crate::model) obj_type: String,
+    pub obj_type: String,
     /// Sources of the data
-    pub(in crate::model) source: String,
+    pub source: String,
     /// Reference(s)
-    #[allow(dead_code)]
-    pub(in crate::model) reference: String,
+    pub reference: String,
+    /// Correlation coefficient of `mu_x` and `mu_y` (optional)
+    #[serde(default)]
+    pub mu_x_mu_y_corr: Option<F>,
+    /// Correlation coefficient of `par` and `mu_x` (optional)
+    #[serde(default)]
+    pub par_mu_x_corr: Option<F>,
+    /// Correlation coefficient of `par` and `mu_y` (optional)
+    #[serde(default)]
+    pub par_mu_y_corr: Option<F>,
+    /// Plus uncertainty in `par` (optional, overrides `par_e`)
+    #[serde(default)]
+    pub par_ep: Option<F>,
+    /// Minus uncertainty in `par` (optional, overrides `par_e`)
+    #[serde(default)]
+    pub par_em: Option<F>,
+    /// Plus uncertainty in `v_lsr` (optional, overrides `v_lsr_e`)
+    #[serde(default)]
+    pub v_lsr_ep: Option<F>,
+    /// Minus uncertainty in `v_lsr` (optional, overrides `v_lsr_e`)
+    #[serde(default)]
+    pub v_lsr_em: Option<F>,
+}
+
+/// Units of the equatorial coordinates in an input file
+///
+/// The units not declared in the file are detected from the values.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CoordinateUnits {
+    /// Unit of the right ascension
+    pub alpha: Option<AngleUnit>,
+    /// Unit of the declination
+    pub delta: Option<AngleUnit>,
+}
+
+/// Try to read the units of the equatorial coordinates declared in the
+/// descriptions of the columns in the header of the input file, e.g.
+/// `#  2 alpha: Right ascension [degrees]`
+pub fn try_read_coordinate_units_from(path: &Path) -> Result<CoordinateUnits> {
+    let file = File::open(path).with_context(|| format!("Couldn't read from the file {path:?}"))?;
+    let mut units = CoordinateUnits::default();
+    for read in BufReader::new(file).lines() {
+        let line = read.with_context(|| format!("Couldn't read from the file {path:?}"))?;
+        // The descriptions end with the header
+        let Some(comment) = line.strip_prefix('#') else {
+            break;
+        };
+        // Skip the index of the column
+        let description = comment
+            .trim_start()
+            .trim_start_matches(|c: char| c.is_ascii_digit());
+        for (column, unit) in [("alpha", &mut units.alpha), ("delta", &mut units.delta)] {
+            if let Some(rest) = description
+                .trim_start()
+                .strip_prefix(column)
+                .and_then(|rest| rest.strip_prefix(':'))
+            {
+                if let Some((_, bracket)) = rest.trim_end().rsplit_once('[') {
+                    let string = bracket.trim_end_matches(']');
+                    *unit = Some(string.parse().with_context(|| {
+                        format!("Couldn't parse the unit `{string}` of the column `{column}`")
+                    })?);
+                }
+            }
+        }
+    }
+    Ok(units)
 }
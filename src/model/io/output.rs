@@ -49,3 +49,63 @@ pub fn serialize_to(
     }
     Ok(())
 }
+
+/// Serialize records to the files, renaming the columns of the text file
+/// via `rename` (the columns it returns `None` for are dropped)
+///
+/// The binary file is the same as the one written by `serialize_to`.
+pub fn serialize_to_renamed<R>(
+    output_dir: &Path,
+    name: &str,
+    header: &str,
+    records: &[impl Serialize],
+    rename: R,
+) -> Result<()>
+where
+    R: Fn(&str) -> Option<String>,
+{
+    // Write both files as usual
+    serialize_to(output_dir, name, header, records)?;
+    // Serialize the records in the text format in memory
+    let mut buffer_wtr = csv::WriterBuilder::default()
+        .delimiter(b' ')
+        .from_writer(Vec::new());
+    for record in records {
+        buffer_wtr
+            .serialize(record)
+            .with_context(|| "Couldn't write a record to the buffer")?;
+    }
+    let buffer = buffer_wtr
+        .into_inner()
+        .with_context(|| "Couldn't flush the buffer")?;
+    // Read them back, keeping only the renamed columns
+    let mut buffer_rdr = csv::ReaderBuilder::default()
+        .delimiter(b' ')
+        .from_reader(buffer.as_slice());
+    let columns: Vec<(usize, String)> = buffer_rdr
+        .headers()
+        .with_context(|| "Couldn't read the columns back from the buffer")?
+        .iter()
+        .enumerate()
+        .filter_map(|(i, column)| rename(column).map(|new_column| (i, new_column)))
+        .collect();
+    // Rewrite the text file
+    let dat_path = &output_dir.join(format!("{name}.dat"));
+    let mut dat_file = File::create(dat_path)
+        .with_context(|| format!("Couldn't open the file {dat_path:?} in write-only mode"))?;
+    write!(&mut dat_file, "{header}")
+        .with_context(|| format!("Couldn't write the header to {dat_path:?}"))?;
+    let mut dat_wtr = csv::WriterBuilder::default()
+        .delimiter(b' ')
+        .from_writer(dat_file);
+    dat_wtr
+        .write_record(columns.iter().map(|pair| &pair.1))
+        .with_context(|| format!("Couldn't write the columns to {dat_path:?}"))?;
+    for result in buffer_rdr.records() {
+        let record = result.with_context(|| "Couldn't read a record back from the buffer")?;
+        dat_wtr
+            .write_record(columns.iter().filter_map(|pair| record.get(pair.0)))
+            .with_context(|| format!("Couldn't write a record to {dat_path:?}"))?;
+    }
+    Ok(())
+}
@@ -9,7 +9,10 @@ mod mu;
 mod r_g;
 mod theta;
 
-use super::io::{input, output};
+pub use mu::ObservedCovariance;
+
+use super::io::input::{self, CoordinateUnits};
+use super::io::output;
 use super::{Model, Params};
 
 use alloc::rc::Rc;
@@ -19,7 +22,7 @@ use core::str::FromStr;
 use std::error::Error;
 use std::path::Path;
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use csv::ReaderBuilder;
 use indoc::formatdoc;
 use itertools::izip;
@@ -50,9 +53,6 @@ pub struct Object<F> {
     /// Is this object an outlier? (one of the
     /// discrepancies turned out to be too big)
     pub outlier: bool,
-    /// Is this object from a Reid catalogue?
-    #[serde(skip)]
-    pub from_reid: Option<bool>,
     /// Name of the object
     #[serde(serialize_with = "serialize_option")]
     pub name: Option<String>,
@@ -75,6 +75,12 @@ pub struct Object<F> {
     /// Uncertainty in `par` (mas)
     #[serde(serialize_with = "serialize_option")]
     pub par_e: Option<F>,
+    /// Plus uncertainty in `par` (mas)
+    #[serde(skip)]
+    pub par_ep: Option<F>,
+    /// Minus uncertainty in `par` (mas)
+    #[serde(skip)]
+    pub par_em: Option<F>,
     /// Plus value of `par`
     #[serde(serialize_with = "serialize_option")]
     pub par_p: Option<F>,
@@ -89,6 +95,12 @@ pub struct Object<F> {
     #[serde(serialize_with = "serialize_option")]
     #[serde(rename = "V_lsr_e")]
     pub v_lsr_e: Option<F>,
+    /// Plus uncertainty in `v_lsr` (km/s)
+    #[serde(skip)]
+    pub v_lsr_ep: Option<F>,
+    /// Minus uncertainty in `v_lsr` (km/s)
+    #[serde(skip)]
+    pub v_lsr_em: Option<F>,
     /// Eastward proper motion (mas/yr)
     #[serde(serialize_with = "serialize_option")]
     pub mu_x: Option<F>,
@@ -101,6 +113,15 @@ pub struct Object<F> {
     /// Uncertainty in `mu_y` (mas/yr)
     #[serde(serialize_with = "serialize_option")]
     pub mu_y_e: Option<F>,
+    /// Correlation coefficient of `mu_x` and `mu_y`
+    #[serde(skip)]
+    pub mu_x_mu_y_corr: Option<F>,
+    /// Correlation coefficient of `par` and `mu_x`
+    #[serde(skip)]
+    pub par_mu_x_corr: Option<F>,
+    /// Correlation coefficient of `par` and `mu_y`
+    #[serde(skip)]
+    pub par_mu_y_corr: Option<F>,
     /// Heliocentric distance (kpc)
     #[serde(rename = "r")]
     #[serde(serialize_with = "serialize_option")]
@@ -244,6 +265,12 @@ pub struct Object<F> {
     #[serde(rename = "V_r_e")]
     #[serde(serialize_with = "serialize_option")]
     pub v_r_e: Option<F>,
+    /// Plus uncertainty in `v_r` (km/s)
+    #[serde(skip)]
+    pub v_r_ep: Option<F>,
+    /// Minus uncertainty in `v_r` (km/s)
+    #[serde(skip)]
+    pub v_r_em: Option<F>,
     /// Heliocentric velocity in longitude (km/s)
     #[serde(rename = "V_l")]
     #[serde(serialize_with = "serialize_option")]
@@ -372,27 +399,52 @@ where
 {
     type Error = anyhow::Error;
 
+    fn try_from(record: input::Record<F>) -> Result<Self> {
+        Self::try_from_record(record, CoordinateUnits::default())
+    }
+}
+
+impl<F> Object<F> {
+    /// Try to parse the record into an object, with the
+    /// equatorial coordinates in the specified units
     #[allow(clippy::unwrap_in_result)]
     #[allow(clippy::unwrap_used)]
     #[replace_float_literals(F::from(literal).unwrap())]
-    fn try_from(record: input::Record<F>) -> Result<Self> {
+    fn try_from_record(record: input::Record<F>, units: CoordinateUnits) -> Result<Self>
+    where
+        F: Float + Default + Debug + FromStr,
+        <F as FromStr>::Err: Error + Send + Sync + 'static,
+    {
         let mut object = Self::default();
-        object.try_parse_alpha(&record)?;
-        object.try_parse_delta(&record)?;
+        object.try_parse_alpha(&record, units.alpha)?;
+        object.try_parse_delta(&record, units.delta)?;
         object.name = Some(record.name);
         object.obj_type = Some(record.obj_type);
-        object.from_reid = Some(record.source == "Reid");
         object.source = Some(record.source);
+        // The asymmetric uncertainties override the symmetric ones, which
+        // are then replaced by the means of the plus and minus uncertainties
+        let par_err_plus = record.par_ep.unwrap_or(record.par_e);
+        let par_err_minus = record.par_em.unwrap_or(record.par_e);
+        let v_lsr_err_plus = record.v_lsr_ep.unwrap_or(record.v_lsr_e);
+        let v_lsr_err_minus = record.v_lsr_em.unwrap_or(record.v_lsr_e);
+        ensure!(
+            [par_err_plus, par_err_minus, v_lsr_err_plus, v_lsr_err_minus]
+                .iter()
+                .all(|e| *e >= 0.),
+            "The uncertainties should be non-negative"
+        );
         object.par = Some(record.par);
-        object.par_e = Some(record.par_e);
-        object.par_p = Some(record.par + record.par_e);
+        object.par_e = Some((par_err_plus + par_err_minus) / 2.);
+        object.par_ep = Some(par_err_plus);
+        object.par_em = Some(par_err_minus);
+        object.par_p = Some(record.par + par_err_plus);
         // In some cases the uncertainty of the value can be greater than
         // the nominal value, hence leading to non-positive results in this
         // subtraction. We avoid this here since there is no such thing
         // as a non-positive parallax. Instead, we assume the distance to be
         // a finite, but sufficiently big value.
         object.par_m = Some({
-            let v_l = record.par - record.par_e;
+            let v_l = record.par - par_err_minus;
             if v_l > 0.0 {
                 v_l
             } else {
@@ -400,18 +452,43 @@ where
             }
         });
         object.v_lsr = Some(record.v_lsr);
-        object.v_lsr_e = Some(record.v_lsr_e);
-        object.v_r_e = Some(record.v_lsr_e);
+        object.v_lsr_e = Some((v_lsr_err_plus + v_lsr_err_minus) / 2.);
+        object.v_lsr_ep = Some(v_lsr_err_plus);
+        object.v_lsr_em = Some(v_lsr_err_minus);
+        // The heliocentric velocity differs from the LSR one by a constant
+        object.v_r_e = object.v_lsr_e;
+        object.v_r_ep = object.v_lsr_ep;
+        object.v_r_em = object.v_lsr_em;
         object.mu_x = Some(record.mu_x);
         object.mu_x_e = Some(record.mu_x_e);
         object.mu_y = Some(record.mu_y);
         object.mu_y_e = Some(record.mu_y_e);
+        // The correlation matrix of the parallax and
+        // the proper motions should be positive definite
+        let corr_mu = record.mu_x_mu_y_corr.unwrap_or(0.);
+        let corr_par_east = record.par_mu_x_corr.unwrap_or(0.);
+        let corr_par_north = record.par_mu_y_corr.unwrap_or(0.);
+        let det = 1. - corr_mu.powi(2) - corr_par_east.powi(2) - corr_par_north.powi(2)
+            + 2. * corr_mu * corr_par_east * corr_par_north;
+        ensure!(
+            [corr_mu, corr_par_east, corr_par_north]
+                .iter()
+                .all(|rho| rho.abs() < 1.)
+                && det > 0.,
+            "The correlation coefficients should form a positive definite matrix"
+        );
+        object.mu_x_mu_y_corr = record.mu_x_mu_y_corr;
+        object.par_mu_x_corr = record.par_mu_x_corr;
+        object.par_mu_y_corr = record.par_mu_y_corr;
         Ok(object)
     }
 }
 
 /// Parse a record into an object
-fn deserialize<F>(result: Result<input::Record<F>, csv::Error>) -> Result<Object<F>>
+fn deserialize<F>(
+    result: Result<input::Record<F>, csv::Error>,
+    units: CoordinateUnits,
+) -> Result<Object<F>>
 where
     F: Float + Default + Debug + FromStr,
     <F as FromStr>::Err: Error + Send + Sync + 'static,
@@ -419,11 +496,32 @@ where
     // Try to deserialize the record
     let record: input::Record<F> = result.with_context(|| "Couldn't deserialize a record")?;
     // Parse an object from the record
-    let object =
-        Object::try_from(record).with_context(|| "Couldn't parse a record into an object")?;
+    let object = Object::try_from_record(record, units)
+        .with_context(|| "Couldn't parse a record into an object")?;
     Ok(object)
 }
 
+/// Try to read objects from the input file
+pub fn try_read_objects_from<F>(path: &Path) -> Result<Vec<Object<F>>>
+where
+    F: Float + Default + Debug + DeserializeOwned + FromStr,
+    <F as FromStr>::Err: Error + Send + Sync + 'static,
+{
+    // Read the units of the coordinates declared in the header
+    let units = input::try_read_coordinate_units_from(path)?;
+    // Create a CSV reader
+    let mut rdr = ReaderBuilder::default()
+        .delimiter(b' ')
+        .comment(Some(b'#'))
+        .from_path(path)
+        .with_context(|| format!("Couldn't read from the file {path:?}"))?;
+    // Try to collect objects
+    rdr.deserialize()
+        .map(|result| deserialize(result, units))
+        .collect::<Result<Vec<Object<F>>>>()
+        .with_context(|| format!("Couldn't get objects from the file {path:?}"))
+}
+
 impl<F> Model<F> {
     /// Count the number of non-outliers
     pub fn count_non_outliers(&self) -> usize {
@@ -452,27 +550,6 @@ impl<F> Model<F> {
             object.outlier = bool;
         }
     }
-    /// Try to load data from the path
-    pub fn try_load_data_from(&mut self, path: &Path) -> Result<()>
-    where
-        F: Float + Default + Debug + DeserializeOwned + FromStr,
-        <F as FromStr>::Err: Error + Send + Sync + 'static,
-    {
-        // Create a CSV reader
-        let mut rdr = ReaderBuilder::default()
-            .delimiter(b' ')
-            .comment(Some(b'#'))
-            .from_path(path)
-            .with_context(|| format!("Couldn't read from the file {path:?}"))?;
-        // Try to collect objects
-        let objects = rdr
-            .deserialize()
-            .map(deserialize)
-            .collect::<Result<Vec<Object<F>>>>()
-            .with_context(|| format!("Couldn't get objects from the file {path:?}"))?;
-        self.objects = Rc::new(RefCell::new(objects));
-        Ok(())
-    }
     /// Serialize the per-object data
     #[allow(clippy::too_many_lines)]
     pub fn serialize_to_objects(&self, name: &str, params: &Params<F>) -> Result<()>
@@ -493,11 +570,11 @@ impl<F> Model<F> {
             # 05 alpha: Right ascension [radians]
             # 06 delta: Declination [radians]
             # 07 par: Parallax [mas]
-            # 08 par_e: Uncertainty in `par` [mas]
+            # 08 par_e: Uncertainty in `par` (mean of the plus and minus ones) [mas]
             # 09 par_p: Plus value of `par` [mas]
             # 10 par_m: Minus value of `par` [mas]
             # 11 V_lsr: Local Standard of Rest velocity [km/s]
-            # 12 V_lsr_e: Uncertainty in `V_lsr` [km/s]
+            # 12 V_lsr_e: Uncertainty in `V_lsr` (mean of the plus and minus ones) [km/s]
             # 13 mu_x: Eastward proper motion [mas/yr]
             # 14 mu_x_e: Uncertainty in `mu_x` [mas/yr]
             # 15 mu_y: Northward proper motion [mas/yr]
@@ -1,47 +1,92 @@
 //! Equatorial heliocentric spherical coordinates
 
 use crate::model::io::input;
-use crate::model::Object;
-use crate::utils;
+use crate::model::{Object, Params};
+use crate::utils::{self, AngleUnit};
 
 use core::fmt::Debug;
 use core::str::FromStr;
 use std::error::Error;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use num::Float;
 
 impl<F> Object<F> {
     /// Parse the right ascension string and convert the angle to radians
-    pub(in crate::model) fn try_parse_alpha(&mut self, record: &input::Record<F>) -> Result<()>
+    ///
+    /// If the unit isn't specified, sexagesimal strings are
+    /// taken as HMS angles and bare numbers as decimal degrees.
+    pub(in crate::model) fn try_parse_alpha(
+        &mut self,
+        record: &input::Record<F>,
+        unit: Option<AngleUnit>,
+    ) -> Result<()>
     where
         F: Float + Debug + FromStr,
         <F as FromStr>::Err: Error + Send + Sync + 'static,
     {
+        let alpha_unit = unit.unwrap_or_else(|| AngleUnit::detect(&record.alpha, AngleUnit::Hms));
         self.alpha = Some(
-            match utils::str2vec(&record.alpha)
-                .with_context(|| format!("Couldn't parse the string {:?}", &record.alpha))?[..]
-            {
-                [hours, minutes, seconds] => utils::hms2rad(hours, minutes, seconds),
-                _ => bail!("Three values were expected"),
-            },
+            utils::parse_angle(&record.alpha, alpha_unit).with_context(|| {
+                format!(
+                    "Couldn't parse the string {:?} ({alpha_unit})",
+                    &record.alpha
+                )
+            })?,
         );
         Ok(())
     }
     /// Parse the declination string and convert the angle to radians
-    pub(in crate::model) fn try_parse_delta(&mut self, record: &input::Record<F>) -> Result<()>
+    ///
+    /// If the unit isn't specified, sexagesimal strings are
+    /// taken as DMS angles and bare numbers as decimal degrees.
+    pub(in crate::model) fn try_parse_delta(
+        &mut self,
+        record: &input::Record<F>,
+        unit: Option<AngleUnit>,
+    ) -> Result<()>
     where
         F: Float + Debug + FromStr,
         <F as FromStr>::Err: Error + Send + Sync + 'static,
     {
+        let delta_unit = unit.unwrap_or_else(|| AngleUnit::detect(&record.delta, AngleUnit::Dms));
         self.delta = Some(
-            match utils::str2vec(&record.delta)
-                .with_context(|| format!("Couldn't parse the string {:?}", &record.delta))?[..]
-            {
-                [degrees, minutes, seconds] => utils::dms2rad(degrees, minutes, seconds),
-                _ => bail!("Three values were expected"),
-            },
+            utils::parse_angle(&record.delta, delta_unit).with_context(|| {
+                format!(
+                    "Couldn't parse the string {:?} ({delta_unit})",
+                    &record.delta
+                )
+            })?,
         );
         Ok(())
     }
+    /// Convert the spherical heliocentric Galactic coordinates to the
+    /// equatorial ones (the inverse of `compute_l_b`)
+    ///
+    /// Angles must be in radians, then radians are returned.
+    #[allow(clippy::unwrap_used)]
+    pub fn compute_alpha_delta<F2>(&mut self, params: &Params<F2>)
+    where
+        F: Float + Debug,
+        F2: Float + Debug + Into<F>,
+    {
+        // Unpack the data
+        let l = self.l.unwrap();
+        let b = self.b.unwrap();
+        // Get the parameters
+        let alpha_ngp: F = params.alpha_ngp.into();
+        let delta_ngp: F = params.delta_ngp.into();
+        let l_ncp: F = params.l_ncp.into();
+        // Compute the angles
+        let phi = F::atan2(
+            F::cos(b) * F::sin(l_ncp - l),
+            F::cos(delta_ngp) * F::sin(b) - F::sin(delta_ngp) * F::cos(b) * F::cos(l_ncp - l),
+        );
+        let alpha = alpha_ngp + phi;
+        let two_pi = F::from(core::f64::consts::TAU).unwrap();
+        self.alpha = Some(alpha - two_pi * F::floor(alpha / two_pi));
+        self.delta = Some(F::asin(
+            F::sin(delta_ngp) * F::sin(b) + F::cos(delta_ngp) * F::cos(b) * F::cos(l_ncp - l),
+        ));
+    }
 }
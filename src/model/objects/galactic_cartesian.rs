@@ -1,6 +1,6 @@
 //! Galactic heliocentric Cartesian coordinates
 
-use super::Object;
+use super::{Object, Params};
 
 use core::fmt::Debug;
 
@@ -23,6 +23,45 @@ impl<F> Object<F> {
         let z = r_h * b.sin();
         (x, y, z)
     }
+    /// Compute the coordinates in the frame aligned with the Galactocentric
+    /// one (the heliocentric frame rotated by the tilt, see `Params::compute_tilt`)
+    pub fn compute_x_y_z_tilted_with<F2>(&self, r_h: F, params: &Params<F2>) -> (F, F, F)
+    where
+        F: Float + Debug,
+        F2: Float + Debug + Into<F>,
+    {
+        let (x, y, z) = self.compute_x_y_z_with(r_h);
+        let tilt = params.compute_tilt();
+        let (sin_tilt, cos_tilt): (F, F) = (tilt.0.into(), tilt.1.into());
+        (x * cos_tilt + z * sin_tilt, y, z * cos_tilt - x * sin_tilt)
+    }
+    /// Compute the longitude and the latitude in the frame aligned with the
+    /// Galactocentric one, and the sine and cosine of the angle `psi` of the
+    /// rotation from its local basis to the Galactic one on the sky
+    ///
+    /// Components of a tangential velocity are converted as
+    /// `V_l = cos(psi) V_l' + sin(psi) V_b'` and
+    /// `V_b = -sin(psi) V_l' + cos(psi) V_b'`.
+    #[allow(clippy::similar_names)]
+    pub fn compute_l_b_tilted<F2>(&self, params: &Params<F2>) -> (F, F, F, F)
+    where
+        F: Float + Debug,
+        F2: Float + Debug + Into<F>,
+    {
+        let l = self.l.unwrap();
+        let (x, y, z) = self.compute_x_y_z_tilted_with(F::one(), params);
+        let l_tilted = y.atan2(x);
+        let b_tilted = z.asin();
+        let tilt = params.compute_tilt();
+        let (sin_tilt, cos_tilt): (F, F) = (tilt.0.into(), tilt.1.into());
+        let (sin_l, cos_l) = (l.sin(), l.cos());
+        let (sin_l_tilted, cos_l_tilted) = (l_tilted.sin(), l_tilted.cos());
+        let (sin_b_tilted, cos_b_tilted) = (b_tilted.sin(), b_tilted.cos());
+        let cos_psi = sin_l * sin_l_tilted * cos_tilt + cos_l * cos_l_tilted;
+        let sin_psi = sin_l * (sin_b_tilted * cos_l_tilted * cos_tilt + cos_b_tilted * sin_tilt)
+            - cos_l * sin_b_tilted * sin_l_tilted;
+        (l_tilted, b_tilted, sin_psi, cos_psi)
+    }
     /// Convert the galactic heliocentric spherical coordinates
     /// to Galactic heliocentric Cartesian coordinates (nominal
     /// values only)
@@ -141,8 +180,6 @@ impl<F> Object<F> {
 
 cfg_if::cfg_if! {
     if #[cfg(test)] {
-        use crate::model::{Params};
-
         use std::path::Path;
 
         use anyhow::{ensure, Context, Result};
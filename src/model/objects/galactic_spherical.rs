@@ -88,6 +88,27 @@ impl<F> Object<F> {
                 - w_sun_standard * b.sin(),
         );
     }
+    /// Compute the Local Standard of Rest velocity from the
+    /// heliocentric velocity in distance (the inverse of `compute_v_r`)
+    pub fn compute_v_lsr<F2>(&mut self, params: &Params<F2>)
+    where
+        F: Float + Debug,
+        F2: Float + Debug + Into<F>,
+    {
+        // Unpack the data
+        let l = self.l.unwrap();
+        let b = self.b.unwrap();
+        let v_r = self.v_r.unwrap();
+        // Get the parameters
+        let u_sun_standard: F = params.u_sun_standard.into();
+        let v_sun_standard: F = params.v_sun_standard.into();
+        let w_sun_standard: F = params.w_sun_standard.into();
+        // Compute the Local Standard of Rest velocity
+        self.v_lsr = Some(
+            v_r + (u_sun_standard * l.cos() + v_sun_standard * l.sin()) * b.cos()
+                + w_sun_standard * b.sin(),
+        );
+    }
     /// Compute the velocities in longitude and
     /// latitude with the specific values
     fn compute_v_l_v_b_with<F2>(&self, r_h: F, params: &Params<F2>) -> (F, F)
@@ -1,7 +1,5 @@
 //! Proper motions in Galactic coordinates
 
-use crate::model::fit::params::VEL_TERM;
-
 use super::{Object, Params};
 
 use core::fmt::Debug;
@@ -10,6 +8,39 @@ use autodiff::FT;
 use num::Float;
 use numeric_literals::replace_float_literals;
 
+/// Covariances of the observed proper motions in Galactic
+/// coordinates and of their correlations with the parallax
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ObservedCovariance<F> {
+    /// Dispersion of `mu_l * cos(b)`
+    pub d_mu_l_cos_b: F,
+    /// Dispersion of `mu_b`
+    pub d_mu_b: F,
+    /// Covariance of `mu_l * cos(b)` and `mu_b`
+    pub c_mu_l_cos_b_mu_b: F,
+    /// Covariance of the parallax and `mu_l * cos(b)`
+    pub c_par_mu_l_cos_b: F,
+    /// Covariance of the parallax and `mu_b`
+    pub c_par_mu_b: F,
+}
+
+impl<F> ObservedCovariance<F> {
+    /// Apply the function to each of the values
+    pub fn map<F2, FN>(&self, f: FN) -> ObservedCovariance<F2>
+    where
+        F: Copy,
+        FN: Fn(F) -> F2,
+    {
+        ObservedCovariance {
+            d_mu_l_cos_b: f(self.d_mu_l_cos_b),
+            d_mu_b: f(self.d_mu_b),
+            c_mu_l_cos_b_mu_b: f(self.c_mu_l_cos_b_mu_b),
+            c_par_mu_l_cos_b: f(self.c_par_mu_l_cos_b),
+            c_par_mu_b: f(self.c_par_mu_b),
+        }
+    }
+}
+
 #[allow(clippy::similar_names)]
 #[allow(clippy::unwrap_in_result)]
 #[allow(clippy::unwrap_used)]
@@ -49,13 +80,61 @@ impl<F> Object<F> {
         self.mu_l_cos_b = Some(mu_l_rad.to_degrees() * 3600. * 1000. * b.cos());
         self.mu_b = Some(mu_b_rad.to_degrees() * 3600. * 1000.);
     }
-    /// Compute the dispersions of `mu_l * cos(b)` and `mu_b`
+    /// Compute the proper motions in equatorial coordinates
+    /// (the inverse of `compute_mu_l_cos_b_mu_b`)
+    pub fn compute_mu_x_mu_y<F2>(&mut self, params: &Params<F2>)
+    where
+        F: Float + Debug + Default,
+        F2: Float + Debug + Into<F>,
+    {
+        // Unpack the data
+        let alpha = self.alpha.unwrap();
+        let delta = self.delta.unwrap();
+        let l = self.l.unwrap();
+        let b = self.b.unwrap();
+        let mu_l_cos_b = self.mu_l_cos_b.unwrap();
+        let mu_b = self.mu_b.unwrap();
+        // Define the forward transformation
+        let forward = |mu_x: F, mu_y: F| -> (F, F) {
+            let mut object = Object {
+                alpha: Some(alpha),
+                delta: Some(delta),
+                l: Some(l),
+                b: Some(b),
+                mu_x: Some(mu_x),
+                mu_y: Some(mu_y),
+                ..Default::default()
+            };
+            object.compute_mu_l_cos_b_mu_b(params);
+            (object.mu_l_cos_b.unwrap(), object.mu_b.unwrap())
+        };
+        // The transformation is a rotation up to the terms of
+        // the second order, so the columns of its matrix are
+        // found by transforming the unit vectors
+        let (a_11, a_21) = forward(1., 0.);
+        let (a_12, a_22) = forward(0., 1.);
+        let det = a_11 * a_22 - a_12 * a_21;
+        // Refine the solution via the fixed-point iterations
+        let mut mu_x = 0.;
+        let mut mu_y = 0.;
+        for _ in 0..3 {
+            let (mu_l_cos_b_i, mu_b_i) = forward(mu_x, mu_y);
+            let r_1 = mu_l_cos_b - mu_l_cos_b_i;
+            let r_2 = mu_b - mu_b_i;
+            mu_x = mu_x + (a_22 * r_1 - a_12 * r_2) / det;
+            mu_y = mu_y + (a_11 * r_2 - a_21 * r_1) / det;
+        }
+        self.mu_x = Some(mu_x);
+        self.mu_y = Some(mu_y);
+    }
+    /// Compute the covariances of `mu_l * cos(b)` and `mu_b`
+    /// (and of their correlations with the parallax)
     ///
-    /// Note that only values with independent
-    /// errors (from the catalog) are unpacked.
+    /// Note that only values with errors from the catalog are unpacked. The
+    /// correlation coefficients missing from the catalog are assumed to be zero.
     #[allow(clippy::shadow_unrelated)]
     #[allow(clippy::similar_names)]
-    pub fn compute_d_mu_l_cos_b_mu_b(&self, params: &Params<F>) -> (F, F)
+    pub fn compute_observed_covariance(&self, params: &Params<F>) -> ObservedCovariance<F>
     where
         F: Float + Debug + Default,
     {
@@ -66,9 +145,6 @@ impl<F> Object<F> {
         let mu_y = self.mu_y.unwrap();
         let mu_x_e = self.mu_x_e.unwrap();
         let mu_y_e = self.mu_y_e.unwrap();
-        let r_h = self.r_h.unwrap();
-        // Get the parameters
-        let k = params.k;
         // Compute the partial derivatives of
         // `mu_l * cos(b)` by `mu_alpha * cos(delta)`
         // and `mu_b` by `mu_alpha * cos(delta)`
@@ -81,8 +157,8 @@ impl<F> Object<F> {
         };
         object.compute_l_b(params);
         object.compute_mu_l_cos_b_mu_b(params);
-        let deriv_mu_l_cos_b_mu_x_sq = object.mu_l_cos_b.unwrap().deriv().powi(2);
-        let deriv_mu_b_mu_x_sq = object.mu_b.unwrap().deriv().powi(2);
+        let a_11 = object.mu_l_cos_b.unwrap().deriv();
+        let a_21 = object.mu_b.unwrap().deriv();
         // Compute the partial derivatives of
         // `mu_l * cos(b)` by `mu_delta`
         // and `mu_b` by `mu_delta`
@@ -90,27 +166,29 @@ impl<F> Object<F> {
         object.mu_y = Some(FT::var(mu_y));
         object.compute_l_b(params);
         object.compute_mu_l_cos_b_mu_b(params);
-        let deriv_mu_l_cos_b_mu_y_sq = object.mu_l_cos_b.unwrap().deriv().powi(2);
-        let deriv_mu_b_mu_y_sq = object.mu_b.unwrap().deriv().powi(2);
-        // Compute the observed dispersions
-        let mut d_mu_x = mu_x_e.powi(2);
-        let mut d_mu_y = mu_y_e.powi(2);
-        // We account for the uncertainty in transferring the
-        // maser motions to that of the central star by adding
-        // an error term here for non-Reid objects.
-        //
-        // See Reid et al. (2019)
-        if !self.from_reid.as_ref().unwrap() {
-            let extra_term_v = F::from(VEL_TERM).unwrap().powi(2);
-            let extra_term_mu = extra_term_v / k.powi(2) / r_h.powi(2);
-            d_mu_x = d_mu_x + extra_term_mu;
-            d_mu_y = d_mu_y + extra_term_mu;
+        let a_12 = object.mu_l_cos_b.unwrap().deriv();
+        let a_22 = object.mu_b.unwrap().deriv();
+        // Compute the observed covariances
+        let d_mu_x = mu_x_e.powi(2);
+        let d_mu_y = mu_y_e.powi(2);
+        let c_mu_x_mu_y = self.mu_x_mu_y_corr.map_or(0., |rho| rho * mu_x_e * mu_y_e);
+        let c_par_mu_x = self
+            .par_mu_x_corr
+            .map_or(0., |rho| rho * self.par_e.unwrap() * mu_x_e);
+        let c_par_mu_y = self
+            .par_mu_y_corr
+            .map_or(0., |rho| rho * self.par_e.unwrap() * mu_y_e);
+        // Transform them to the Galactic frame
+        ObservedCovariance {
+            d_mu_l_cos_b: a_11.powi(2) * d_mu_x
+                + a_12.powi(2) * d_mu_y
+                + 2. * a_11 * a_12 * c_mu_x_mu_y,
+            d_mu_b: a_21.powi(2) * d_mu_x + a_22.powi(2) * d_mu_y + 2. * a_21 * a_22 * c_mu_x_mu_y,
+            c_mu_l_cos_b_mu_b: a_11 * a_21 * d_mu_x
+                + a_12 * a_22 * d_mu_y
+                + (a_11 * a_22 + a_12 * a_21) * c_mu_x_mu_y,
+            c_par_mu_l_cos_b: a_11 * c_par_mu_x + a_12 * c_par_mu_y,
+            c_par_mu_b: a_21 * c_par_mu_x + a_22 * c_par_mu_y,
         }
-        // Compute the dispersion of `mu_l * cos(b)`
-        let d_mu_l_cos_b = deriv_mu_l_cos_b_mu_x_sq * d_mu_x + deriv_mu_l_cos_b_mu_y_sq * d_mu_y;
-        // Compute the dispersion of `mu_b`
-        let d_mu_b = deriv_mu_b_mu_x_sq * d_mu_x + deriv_mu_b_mu_y_sq * d_mu_y;
-        // Return the results
-        (d_mu_l_cos_b, d_mu_b)
     }
 }
@@ -5,12 +5,10 @@ use super::{Object, Params};
 use core::fmt::Debug;
 
 use num::Float;
-use numeric_literals::replace_float_literals;
 
 #[allow(clippy::similar_names)]
 #[allow(clippy::unwrap_in_result)]
 #[allow(clippy::unwrap_used)]
-#[replace_float_literals(F::from(literal).unwrap())]
 impl<F> Object<F> {
     /// Compute the galactocentric distance with the specific values
     fn compute_r_g_with<F2>(&self, r_h: F, params: &Params<F2>) -> F
@@ -18,15 +16,14 @@ impl<F> Object<F> {
         F: Float + Debug,
         F2: Float + Debug + Into<F>,
     {
-        // Unpack the data
-        let l = self.l.unwrap();
-        let b = self.b.unwrap();
-        // Prepare the Galactocentric distance to the Sun
-        let r_0: F = params.r_0.into();
-        // Compute the projection of the heliocentric distance in the XY plane
-        let d = r_h * b.cos();
+        // Compute the coordinates in the frame aligned with the Galactocentric one
+        let (x, y, _) = self.compute_x_y_z_tilted_with(r_h, params);
+        // Prepare the projection of the Galactocentric
+        // distance to the Sun on the Galactic plane
+        let (_, cos_tilt) = params.compute_tilt();
+        let r_0: F = (params.r_0 * cos_tilt).into();
         // Compute the Galactocentric distance
-        F::sqrt(r_0.powi(2) + d.powi(2) - 2. * r_0 * d * l.cos())
+        F::sqrt((r_0 - x).powi(2) + y.powi(2))
     }
     /// Compute the galactocentric distance (nominal value only)
     pub fn compute_r_g_nominal<F2>(&mut self, params: &Params<F2>)
@@ -58,3 +55,50 @@ impl<F> Object<F> {
         self.r_g_em = Some(r_g - r_g_m);
     }
 }
+
+#[cfg(test)]
+use anyhow::{ensure, Context, Result};
+
+#[test]
+fn test() -> Result<()> {
+    // The Galactic center is on the X axis even if the Sun is above the plane
+    let params = Params {
+        r_0: 8.15,
+        z_sun: 20.8,
+        ..Default::default()
+    };
+    let mut center = Object {
+        l: Some(0.),
+        b: Some(0.),
+        r_h: Some(params.r_0),
+        ..Default::default()
+    };
+    center.compute_r_g_nominal(&params);
+    let r_g_center = center.r_g.context("The distance should be computed")?;
+    ensure!(
+        r_g_center.abs() < 1e-12,
+        "The Galactic center should be at R = 0: {r_g_center}"
+    );
+    // Without the tilt, the distance follows the law of cosines
+    let flat_params = Params {
+        r_0: 8.15,
+        ..Default::default()
+    };
+    let (l, b, r_h) = (0.7_f64, 0.1_f64, 3.);
+    let mut object = Object {
+        l: Some(l),
+        b: Some(b),
+        r_h: Some(r_h),
+        ..Default::default()
+    };
+    object.compute_r_g_nominal(&flat_params);
+    let r_g = object.r_g.context("The distance should be computed")?;
+    let d = r_h * b.cos();
+    let r_0 = flat_params.r_0;
+    let expected = (r_0.powi(2) + d.powi(2) - 2. * r_0 * d * l.cos()).sqrt();
+    ensure!(
+        (r_g - expected).abs() < 1e-12,
+        "The distances should match: {r_g} vs. {expected}"
+    );
+    Ok(())
+}
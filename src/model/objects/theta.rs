@@ -1,6 +1,6 @@
 //! Azimuthal velocity
 
-use crate::model::fit::params::VEL_TERM;
+use crate::model::fit::compute_error_floors;
 
 use super::{Object, Params};
 
@@ -16,27 +16,28 @@ use numeric_literals::replace_float_literals;
 impl<F> Object<F> {
     /// Compute the azimuthal velocity with the specific values
     #[allow(clippy::many_single_char_names)]
-    fn compute_theta_with<F2>(&self, r_h: F, r_g: F, u: F, v: F, params: &Params<F2>) -> F
+    fn compute_theta_with<F2>(&self, r_h: F, r_g: F, u: F, v: F, w: F, params: &Params<F2>) -> F
     where
         F: Float + Debug,
         F2: Float + Debug + Into<F>,
     {
-        // Unpack the data
-        let l = self.l.unwrap();
-        let b = self.b.unwrap();
         // Get the parameters
         let u_sun: F = params.u_sun.into();
         let theta_sun: F = params.theta_sun.into();
-        let r_0: F = params.r_0.into();
+        let tilt = params.compute_tilt();
+        let (sin_tilt, cos_tilt): (F, F) = (tilt.0.into(), tilt.1.into());
+        // Compute the projection of the Galactocentric
+        // distance to the Sun on the Galactic plane
+        let r_0 = cos_tilt * params.r_0.into();
         // Convert to the Galactocentric coordinate
         // system associated with the Sun
-        let u_g = u + u_sun;
+        let u_g = u * cos_tilt + w * sin_tilt + u_sun;
         let v_g = v + theta_sun;
-        // Compute the projection of the heliocentric distance in the XY plane
-        let d = r_h * b.cos();
+        // Compute the coordinates in the frame aligned with the Galactocentric one
+        let (x, y, _) = self.compute_x_y_z_tilted_with(r_h, params);
         // Compute the azimuthal velocity
-        let sin_lambda = d / r_g * l.sin();
-        let cos_lambda = (r_0 - d * l.cos()) / r_g;
+        let sin_lambda = y / r_g;
+        let cos_lambda = (r_0 - x) / r_g;
         u_g * sin_lambda + v_g * cos_lambda
     }
     /// Compute the azimuthal velocity (nominal value only)
@@ -49,7 +50,8 @@ impl<F> Object<F> {
         let r_g = self.r_g.unwrap();
         let u = self.u.unwrap();
         let v = self.v.unwrap();
-        self.theta = Some(self.compute_theta_with(r_h, r_g, u, v, params));
+        let w = self.w.unwrap();
+        self.theta = Some(self.compute_theta_with(r_h, r_g, u, v, w, params));
     }
     /// Compute the azimuthal velocity
     #[allow(clippy::similar_names)]
@@ -71,10 +73,13 @@ impl<F> Object<F> {
         let v = self.v.unwrap();
         let v_p = self.v_p.unwrap();
         let v_m = self.v_m.unwrap();
+        let w = self.w.unwrap();
+        let w_p = self.w_p.unwrap();
+        let w_m = self.w_m.unwrap();
         // Compute the azimuthal velocity
-        let theta = self.compute_theta_with(r_h, r_g, u, v, params);
-        let theta_p = self.compute_theta_with(r_h_p, r_g_p, u_p, v_p, params);
-        let theta_m = self.compute_theta_with(r_h_m, r_g_m, u_m, v_m, params);
+        let theta = self.compute_theta_with(r_h, r_g, u, v, w, params);
+        let theta_p = self.compute_theta_with(r_h_p, r_g_p, u_p, v_p, w_p, params);
+        let theta_m = self.compute_theta_with(r_h_m, r_g_m, u_m, v_m, w_m, params);
         self.theta = Some(theta);
         self.theta_p = Some(theta_p);
         self.theta_m = Some(theta_m);
@@ -83,7 +88,7 @@ impl<F> Object<F> {
     }
     /// Compute the uncertainty in the azimuthal velocity inherited from velocities
     ///
-    /// Note that we compute all values again, starting from the independent errors
+    /// Note that we compute all values again, starting from the errors from the catalog
     ///
     /// Sources: Gromov, Nikiforov, Ossipkov (2016)
     #[allow(clippy::similar_names)]
@@ -154,29 +159,32 @@ impl<F> Object<F> {
         let mut d_v_lsr = v_lsr_e.powi(2);
         let mut d_mu_x = mu_x_e.powi(2);
         let mut d_mu_y = mu_y_e.powi(2);
+        // The proper motions may be correlated
+        let c_mu_x_mu_y = self.mu_x_mu_y_corr.unwrap_or(0.) * mu_x_e * mu_y_e;
+        let term_c = 2. * deriv_theta_mu_x * deriv_theta_mu_y * c_mu_x_mu_y;
         // Compute the uncertainty
         self.theta_evel = Some(F::sqrt(
             deriv_theta_v_lsr.powi(2) * d_v_lsr
                 + deriv_theta_mu_x.powi(2) * d_mu_x
-                + deriv_theta_mu_y.powi(2) * d_mu_y,
+                + deriv_theta_mu_y.powi(2) * d_mu_y
+                + term_c,
         ));
         // We account for the uncertainty in transferring the
         // maser motions to that of the central star by adding
-        // an error term here for non-Reid objects.
+        // the error floors of the source here.
         //
         // See Reid et al. (2019)
-        if !self.from_reid.as_ref().unwrap() {
-            let extra_term_v = F::from(VEL_TERM).unwrap().powi(2);
-            let extra_term_mu = extra_term_v / k.powi(2) / r_h.powi(2);
-            d_v_lsr = d_v_lsr + extra_term_v;
-            d_mu_x = d_mu_x + extra_term_mu;
-            d_mu_y = d_mu_y + extra_term_mu;
-        }
+        let (floor_v_r, floor_v_t) = compute_error_floors(self.source.as_deref(), params);
+        let extra_term_mu = floor_v_t.powi(2) / k.powi(2) / r_h.powi(2);
+        d_v_lsr = d_v_lsr + floor_v_r.powi(2);
+        d_mu_x = d_mu_x + extra_term_mu;
+        d_mu_y = d_mu_y + extra_term_mu;
         // Compute the corrected uncertainty
         self.theta_evel_corrected = Some(F::sqrt(
             deriv_theta_v_lsr.powi(2) * d_v_lsr
                 + deriv_theta_mu_x.powi(2) * d_mu_x
-                + deriv_theta_mu_y.powi(2) * d_mu_y,
+                + deriv_theta_mu_y.powi(2) * d_mu_y
+                + term_c,
         ));
     }
 }
@@ -1,5 +1,9 @@
 //! Model parameters
 
+use super::fit::rotcurve::{RotationCurveKind, RotationCurveModel};
+use super::fit::{
+    ErrorFloor, ParallaxOffset, RadialFlow, SigmaProfile, ERROR_FLOOR_NAMES, PAR_OFFSET_NAMES,
+};
 use super::io::output;
 use super::Model;
 
@@ -7,7 +11,9 @@ use core::fmt::{Debug, Display};
 use std::io::Write;
 use std::{fs::File, io::BufWriter};
 
-use anyhow::Result;
+#[cfg(test)]
+use anyhow::ensure;
+use anyhow::{anyhow, Result};
 use indoc::{formatdoc, indoc};
 use num::Float;
 use serde::Serialize;
@@ -38,6 +44,72 @@ pub const PARAMS_NAMES: [&str; PARAMS_N] = [
     "theta_8",
 ];
 
+/// Names of the parameters of the spiral perturbation
+pub const SPIRAL_NAMES: [&str; 4] = ["f_R", "f_theta", "i", "chi_0"];
+
+/// Names of the parameters of the bar perturbation
+pub const BAR_NAMES: [&str; 3] = ["V_bar", "phi_bar", "R_bar"];
+
+/// Name of the height of the Sun above the Galactic plane
+pub const Z_SUN_NAME: &str = "z_sun";
+
+/// Name of the constant radial velocity of the global radial flow
+pub const V_FLOW_NAME: &str = "V_flow";
+
+/// Name of the K term of the global radial flow
+pub const K_FLOW_NAME: &str = "K_flow";
+
+/// Name of the vertex deviation of the ellipsoid of natural standard deviations
+pub const L_V_NAME: &str = "l_v";
+
+/// Name of the tilt of the ellipsoid of natural standard deviations in the R-Z plane
+pub const ALPHA_RZ_NAME: &str = "alpha_RZ";
+
+/// Names of the scale lengths of the radial profile of the natural standard deviations
+pub const SIGMA_SCALE_NAMES: [&str; 3] = ["h_sigma_R", "h_sigma_theta", "h_sigma_Z"];
+
+/// Names of the parameters of the ellipsoid of natural standard deviations
+/// (the sigmas, its orientation and the scale lengths of its radial profile)
+pub const SIGMA_NAMES: [&str; 8] = [
+    "sigma_R",
+    "sigma_theta",
+    "sigma_Z",
+    L_V_NAME,
+    ALPHA_RZ_NAME,
+    SIGMA_SCALE_NAMES[0],
+    SIGMA_SCALE_NAMES[1],
+    SIGMA_SCALE_NAMES[2],
+];
+
+/// Names of the derived parameters
+pub const DERIVED_NAMES: [&str; 3] = ["theta_0", "theta_sun", "omega_sun"];
+
+/// Compute the derived parameters at the point in the parameter space
+#[allow(clippy::indexing_slicing)]
+pub fn compute_derived<F>(p: &[F]) -> [F; 3]
+where
+    F: Float,
+{
+    let (r_0, omega_0, v_sun) = (p[0], p[1], p[4]);
+    let theta_0 = r_0 * omega_0;
+    let theta_sun = theta_0 + v_sun;
+    let omega_sun = omega_0 + v_sun / r_0;
+    [theta_0, theta_sun, omega_sun]
+}
+
+/// Optional parameters of the model, fitted only if enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionalParam {
+    /// Height of the Sun above the Galactic plane
+    ZSun,
+    /// Vertex deviation of the ellipsoid of natural standard deviations
+    VertexDeviation,
+    /// Tilt of the ellipsoid of natural standard deviations in the R-Z plane
+    TiltRz,
+    /// Error floors of the velocities
+    ErrorFloors,
+}
+
 /// Model parameters
 #[derive(Default, Debug, Clone, Serialize)]
 pub struct Params<F> {
@@ -173,6 +245,120 @@ pub struct Params<F> {
     pub omega_sun_ep: F,
     /// Minus uncertainty in `omega_sun`
     pub omega_sun_em: F,
+    /// Amplitude of the radial perturbation by the spiral density wave (km/s)
+    #[serde(rename = "f_R")]
+    pub f_r: F,
+    /// Plus uncertainty in `f_r`
+    #[serde(rename = "f_R_ep")]
+    pub f_r_ep: F,
+    /// Minus uncertainty in `f_r`
+    #[serde(rename = "f_R_em")]
+    pub f_r_em: F,
+    /// Amplitude of the azimuthal perturbation by the spiral density wave (km/s)
+    pub f_theta: F,
+    /// Plus uncertainty in `f_theta`
+    pub f_theta_ep: F,
+    /// Minus uncertainty in `f_theta`
+    pub f_theta_em: F,
+    /// Pitch angle of the spiral pattern (degrees)
+    pub i: F,
+    /// Plus uncertainty in `i`
+    pub i_ep: F,
+    /// Minus uncertainty in `i`
+    pub i_em: F,
+    /// Phase of the spiral density wave at the Sun (degrees)
+    pub chi_0: F,
+    /// Plus uncertainty in `chi_0`
+    pub chi_0_ep: F,
+    /// Minus uncertainty in `chi_0`
+    pub chi_0_em: F,
+    /// Amplitude of the perturbation by the bar (km/s)
+    #[serde(rename = "V_bar")]
+    pub v_bar: F,
+    /// Plus uncertainty in `v_bar`
+    #[serde(rename = "V_bar_ep")]
+    pub v_bar_ep: F,
+    /// Minus uncertainty in `v_bar`
+    #[serde(rename = "V_bar_em")]
+    pub v_bar_em: F,
+    /// Angle between the major axis of the bar and the Sun-GC line (degrees)
+    pub phi_bar: F,
+    /// Plus uncertainty in `phi_bar`
+    pub phi_bar_ep: F,
+    /// Minus uncertainty in `phi_bar`
+    pub phi_bar_em: F,
+    /// Radial extent of the perturbation by the bar (kpc)
+    #[serde(rename = "R_bar")]
+    pub r_bar: F,
+    /// Plus uncertainty in `r_bar`
+    #[serde(rename = "R_bar_ep")]
+    pub r_bar_ep: F,
+    /// Minus uncertainty in `r_bar`
+    #[serde(rename = "R_bar_em")]
+    pub r_bar_em: F,
+    /// Height of the Sun above the Galactic plane (pc)
+    pub z_sun: F,
+    /// Plus uncertainty in `z_sun`
+    pub z_sun_ep: F,
+    /// Minus uncertainty in `z_sun`
+    pub z_sun_em: F,
+    /// Vertex deviation of the ellipsoid of natural standard deviations (degrees)
+    pub l_v: F,
+    /// Plus uncertainty in `l_v`
+    pub l_v_ep: F,
+    /// Minus uncertainty in `l_v`
+    pub l_v_em: F,
+    /// Tilt of the ellipsoid of natural standard deviations in the R-Z plane (degrees)
+    #[serde(rename = "alpha_RZ")]
+    pub alpha_rz: F,
+    /// Plus uncertainty in `alpha_rz`
+    #[serde(rename = "alpha_RZ_ep")]
+    pub alpha_rz_ep: F,
+    /// Minus uncertainty in `alpha_rz`
+    #[serde(rename = "alpha_RZ_em")]
+    pub alpha_rz_em: F,
+    /// Scale length of the radial profile of `sigma_R` (kpc)
+    #[serde(rename = "h_sigma_R")]
+    pub h_sigma_r: F,
+    /// Plus uncertainty in `h_sigma_r`
+    #[serde(rename = "h_sigma_R_ep")]
+    pub h_sigma_r_ep: F,
+    /// Minus uncertainty in `h_sigma_r`
+    #[serde(rename = "h_sigma_R_em")]
+    pub h_sigma_r_em: F,
+    /// Scale length of the radial profile of `sigma_theta` (kpc)
+    pub h_sigma_theta: F,
+    /// Plus uncertainty in `h_sigma_theta`
+    pub h_sigma_theta_ep: F,
+    /// Minus uncertainty in `h_sigma_theta`
+    pub h_sigma_theta_em: F,
+    /// Scale length of the radial profile of `sigma_Z` (kpc)
+    #[serde(rename = "h_sigma_Z")]
+    pub h_sigma_z: F,
+    /// Plus uncertainty in `h_sigma_z`
+    #[serde(rename = "h_sigma_Z_ep")]
+    pub h_sigma_z_ep: F,
+    /// Minus uncertainty in `h_sigma_z`
+    #[serde(rename = "h_sigma_Z_em")]
+    pub h_sigma_z_em: F,
+    /// Constant radial velocity of the global radial flow (km/s)
+    #[serde(rename = "V_flow")]
+    pub v_flow: F,
+    /// Plus uncertainty in `v_flow`
+    #[serde(rename = "V_flow_ep")]
+    pub v_flow_ep: F,
+    /// Minus uncertainty in `v_flow`
+    #[serde(rename = "V_flow_em")]
+    pub v_flow_em: F,
+    /// K term of the global radial flow (km/s/kpc)
+    #[serde(rename = "K_flow")]
+    pub k_flow: F,
+    /// Plus uncertainty in `k_flow`
+    #[serde(rename = "K_flow_ep")]
+    pub k_flow_ep: F,
+    /// Minus uncertainty in `k_flow`
+    #[serde(rename = "K_flow_em")]
+    pub k_flow_em: F,
     /// The right ascension of the north galactic pole (radians)
     #[serde(skip)]
     pub alpha_ngp: F,
@@ -194,20 +380,514 @@ pub struct Params<F> {
     /// Standard Solar Motion toward NGP (km/s)
     #[serde(skip)]
     pub w_sun_standard: F,
+    /// Family of the rotation curve
+    #[serde(skip)]
+    pub rotcurve: RotationCurveKind,
+    /// Number of the spiral arms (if the spiral perturbation is enabled)
+    #[serde(skip)]
+    pub spiral_arms: Option<u32>,
+    /// Whether the bar perturbation is enabled
+    #[serde(skip)]
+    pub bar: bool,
+    /// Global radial velocity field
+    #[serde(skip)]
+    pub radial_flow: RadialFlow,
+    /// Radial profile of the natural standard deviations
+    #[serde(skip)]
+    pub sigma_profile: SigmaProfile,
+    /// Zero-point offsets of the parallaxes from the sources of the data (if fitted)
+    #[serde(skip)]
+    pub par_offsets: Vec<ParallaxOffset<F>>,
+    /// Error floors of the velocities of the objects from the sources of the data
+    #[serde(skip)]
+    pub error_floors: Vec<ErrorFloor<F>>,
+    /// Optional parameters that are fitted
+    #[serde(skip)]
+    pub fitted: Vec<OptionalParam>,
+    /// Names of the parameters fixed by the user
+    #[serde(skip)]
+    pub fixed: Vec<&'static str>,
 }
 
 impl<F> Params<F> {
+    /// Check whether the optional parameter is fitted
+    pub fn fits(&self, optional: OptionalParam) -> bool {
+        self.fitted.contains(&optional)
+    }
+    /// Apply the function to each of the parameters
+    pub fn map<F2, FN>(&self, f: FN) -> Params<F2>
+    where
+        F: Copy,
+        F2: Default,
+        FN: Fn(F) -> F2,
+    {
+        Params {
+            f_r: f(self.f_r),
+            f_r_ep: f(self.f_r_ep),
+            f_r_em: f(self.f_r_em),
+            f_theta: f(self.f_theta),
+            f_theta_ep: f(self.f_theta_ep),
+            f_theta_em: f(self.f_theta_em),
+            i: f(self.i),
+            i_ep: f(self.i_ep),
+            i_em: f(self.i_em),
+            chi_0: f(self.chi_0),
+            chi_0_ep: f(self.chi_0_ep),
+            chi_0_em: f(self.chi_0_em),
+            v_bar: f(self.v_bar),
+            v_bar_ep: f(self.v_bar_ep),
+            v_bar_em: f(self.v_bar_em),
+            phi_bar: f(self.phi_bar),
+            phi_bar_ep: f(self.phi_bar_ep),
+            phi_bar_em: f(self.phi_bar_em),
+            r_bar: f(self.r_bar),
+            r_bar_ep: f(self.r_bar_ep),
+            r_bar_em: f(self.r_bar_em),
+            z_sun: f(self.z_sun),
+            z_sun_ep: f(self.z_sun_ep),
+            z_sun_em: f(self.z_sun_em),
+            l_v: f(self.l_v),
+            l_v_ep: f(self.l_v_ep),
+            l_v_em: f(self.l_v_em),
+            alpha_rz: f(self.alpha_rz),
+            alpha_rz_ep: f(self.alpha_rz_ep),
+            alpha_rz_em: f(self.alpha_rz_em),
+            h_sigma_r: f(self.h_sigma_r),
+            h_sigma_r_ep: f(self.h_sigma_r_ep),
+            h_sigma_r_em: f(self.h_sigma_r_em),
+            h_sigma_theta: f(self.h_sigma_theta),
+            h_sigma_theta_ep: f(self.h_sigma_theta_ep),
+            h_sigma_theta_em: f(self.h_sigma_theta_em),
+            h_sigma_z: f(self.h_sigma_z),
+            h_sigma_z_ep: f(self.h_sigma_z_ep),
+            h_sigma_z_em: f(self.h_sigma_z_em),
+            v_flow: f(self.v_flow),
+            v_flow_ep: f(self.v_flow_ep),
+            v_flow_em: f(self.v_flow_em),
+            k_flow: f(self.k_flow),
+            k_flow_ep: f(self.k_flow_ep),
+            k_flow_em: f(self.k_flow_em),
+            rotcurve: self.rotcurve,
+            spiral_arms: self.spiral_arms,
+            bar: self.bar,
+            radial_flow: self.radial_flow,
+            sigma_profile: self.sigma_profile,
+            par_offsets: self
+                .par_offsets
+                .iter()
+                .map(|offset| offset.map(&f))
+                .collect(),
+            error_floors: self
+                .error_floors
+                .iter()
+                .map(|floor| floor.map(&f))
+                .collect(),
+            fitted: self.fitted.clone(),
+            fixed: self.fixed.clone(),
+            ..self.map_main(&f)
+        }
+    }
+    /// Apply the function to the main parameters and the constants
+    /// (the rest are left default, see `map`)
+    fn map_main<F2, FN>(&self, f: FN) -> Params<F2>
+    where
+        F: Copy,
+        F2: Default,
+        FN: Fn(F) -> F2,
+    {
+        Params {
+            r_0: f(self.r_0),
+            r_0_ep: f(self.r_0_ep),
+            r_0_em: f(self.r_0_em),
+            omega_0: f(self.omega_0),
+            omega_0_ep: f(self.omega_0_ep),
+            omega_0_em: f(self.omega_0_em),
+            a: f(self.a),
+            a_ep: f(self.a_ep),
+            a_em: f(self.a_em),
+            u_sun: f(self.u_sun),
+            u_sun_ep: f(self.u_sun_ep),
+            u_sun_em: f(self.u_sun_em),
+            v_sun: f(self.v_sun),
+            v_sun_ep: f(self.v_sun_ep),
+            v_sun_em: f(self.v_sun_em),
+            w_sun: f(self.w_sun),
+            w_sun_ep: f(self.w_sun_ep),
+            w_sun_em: f(self.w_sun_em),
+            sigma_r_g: f(self.sigma_r_g),
+            sigma_r_g_ep: f(self.sigma_r_g_ep),
+            sigma_r_g_em: f(self.sigma_r_g_em),
+            sigma_theta: f(self.sigma_theta),
+            sigma_theta_ep: f(self.sigma_theta_ep),
+            sigma_theta_em: f(self.sigma_theta_em),
+            sigma_z: f(self.sigma_z),
+            sigma_z_ep: f(self.sigma_z_ep),
+            sigma_z_em: f(self.sigma_z_em),
+            theta_2: f(self.theta_2),
+            theta_2_ep: f(self.theta_2_ep),
+            theta_2_em: f(self.theta_2_em),
+            theta_3: f(self.theta_3),
+            theta_3_ep: f(self.theta_3_ep),
+            theta_3_em: f(self.theta_3_em),
+            theta_4: f(self.theta_4),
+            theta_4_ep: f(self.theta_4_ep),
+            theta_4_em: f(self.theta_4_em),
+            theta_5: f(self.theta_5),
+            theta_5_ep: f(self.theta_5_ep),
+            theta_5_em: f(self.theta_5_em),
+            theta_6: f(self.theta_6),
+            theta_6_ep: f(self.theta_6_ep),
+            theta_6_em: f(self.theta_6_em),
+            theta_7: f(self.theta_7),
+            theta_7_ep: f(self.theta_7_ep),
+            theta_7_em: f(self.theta_7_em),
+            theta_8: f(self.theta_8),
+            theta_8_ep: f(self.theta_8_ep),
+            theta_8_em: f(self.theta_8_em),
+            theta_0: f(self.theta_0),
+            theta_0_ep: f(self.theta_0_ep),
+            theta_0_em: f(self.theta_0_em),
+            theta_1: f(self.theta_1),
+            theta_1_ep: f(self.theta_1_ep),
+            theta_1_em: f(self.theta_1_em),
+            theta_sun: f(self.theta_sun),
+            theta_sun_ep: f(self.theta_sun_ep),
+            theta_sun_em: f(self.theta_sun_em),
+            omega_sun: f(self.omega_sun),
+            omega_sun_ep: f(self.omega_sun_ep),
+            omega_sun_em: f(self.omega_sun_em),
+            alpha_ngp: f(self.alpha_ngp),
+            delta_ngp: f(self.delta_ngp),
+            l_ncp: f(self.l_ncp),
+            k: f(self.k),
+            u_sun_standard: f(self.u_sun_standard),
+            v_sun_standard: f(self.v_sun_standard),
+            w_sun_standard: f(self.w_sun_standard),
+            ..Default::default()
+        }
+    }
+    /// Names of the optimized parameters in the point of the degree `n` (with the
+    /// shape parameters of the family of the rotation curve and the extra parameters)
+    #[allow(clippy::indexing_slicing)]
+    pub fn names(&self, n: usize) -> Vec<&'static str> {
+        let mut names = PARAMS_NAMES[..=PARAMS_N - N_MAX].to_vec();
+        names.extend(self.rotcurve.shape_names().iter().take(n - 1));
+        names.extend(self.extra_names());
+        names
+    }
+    /// Names of the parameters in the point of the length `len`
+    pub fn names_of(&self, len: usize) -> Vec<&'static str> {
+        self.names(len + N_MAX - PARAMS_N - self.extra_names().len())
+    }
+    /// Get the name of the column of the output files for this column of the
+    /// serialized parameters (or `None` if the column is not used)
+    ///
+    /// The shape parameters of the families of the rotation curve other than
+    /// the Taylor series are stored in place of `theta_2`, `theta_3`, etc.,
+    /// so these columns are renamed after them (the rest are dropped).
+    fn shape_column(&self, column: &str) -> Option<String> {
+        if self.rotcurve == RotationCurveKind::Taylor {
+            return Some(column.to_owned());
+        }
+        let index = column
+            .strip_prefix("theta_")
+            .map(|rest| rest.split_once('_').unwrap_or((rest, "")))
+            .and_then(|(index, suffix)| index.parse::<usize>().ok().map(|i| (i, suffix)));
+        match index {
+            Some((i, suffix)) if i >= 2 => {
+                self.rotcurve
+                    .shape_names()
+                    .get(i - 2)
+                    .map(|name| match suffix {
+                        "" => (*name).to_owned(),
+                        _ => format!("{name}_{suffix}"),
+                    })
+            }
+            _ => Some(column.to_owned()),
+        }
+    }
+    /// Format the descriptions of the columns of the output files
+    /// of the parameters (see `shape_column`) and a note on the derived ones
+    fn format_descriptions(&self) -> String {
+        let mut lines = Vec::new();
+        let mut derived = (0, 0);
+        for line in DESCRIPTIONS.lines() {
+            let Some((column, description)) = line
+                .strip_prefix("# ")
+                .and_then(|rest| rest.split_once(' '))
+                .and_then(|(_, rest)| rest.split_once(": "))
+            else {
+                continue;
+            };
+            let Some(new_column) = self.shape_column(column) else {
+                continue;
+            };
+            let number = lines.len() + 1;
+            match column {
+                "theta_0" => derived.0 = number,
+                "omega_sun_em" => derived.1 = number,
+                _ => {}
+            }
+            let new_description = if new_column == column {
+                description.to_owned()
+            } else if let Some(name) = new_column.strip_suffix("_ep") {
+                format!("Plus uncertainty in `{name}`")
+            } else if let Some(name) = new_column.strip_suffix("_em") {
+                format!("Minus uncertainty in `{name}`")
+            } else {
+                format!("Shape parameter of the `{}` rotation curve", self.rotcurve)
+            };
+            lines.push(format!("# {number:02} {new_column}: {new_description}"));
+        }
+        formatdoc!(
+            "
+            {descriptions}
+            #
+            # Note that the values {start}-{end} are derived from the other parameters.",
+            descriptions = lines.join("\n"),
+            start = derived.0,
+            end = derived.1,
+        )
+    }
+    /// Names of the enabled extra parameters (the perturbations of
+    /// the velocity field, the height of the Sun, etc.)
+    ///
+    /// These are placed at the end of the point in the parameter space. The
+    /// parameters of the ellipsoid of natural standard deviations (its orientation
+    /// and the scale lengths of its radial profile) go last,
+    /// since they are frozen together with the sigmas (see `frozen_mask`).
+    pub fn extra_names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.spiral_arms.is_some() {
+            names.extend(SPIRAL_NAMES);
+        }
+        if self.bar {
+            names.extend(BAR_NAMES);
+        }
+        if self.fits(OptionalParam::ZSun) {
+            names.push(Z_SUN_NAME);
+        }
+        match self.radial_flow {
+            RadialFlow::None => {}
+            RadialFlow::Constant => names.push(V_FLOW_NAME),
+            RadialFlow::KTerm => names.push(K_FLOW_NAME),
+        }
+        names.extend(PAR_OFFSET_NAMES.iter().take(self.par_offsets.len()));
+        if self.fits(OptionalParam::ErrorFloors) {
+            names.extend(ERROR_FLOOR_NAMES.iter().take(2 * self.error_floors.len()));
+        }
+        if self.fits(OptionalParam::VertexDeviation) {
+            names.push(L_V_NAME);
+        }
+        if self.fits(OptionalParam::TiltRz) {
+            names.push(ALPHA_RZ_NAME);
+        }
+        if self.sigma_profile != SigmaProfile::Constant {
+            names.extend(SIGMA_SCALE_NAMES);
+        }
+        names
+    }
+    /// Get the enabled extra parameters with their plus and minus uncertainties
+    fn extras(&self) -> Vec<[F; 3]>
+    where
+        F: Copy,
+    {
+        let mut vec = Vec::new();
+        if self.spiral_arms.is_some() {
+            vec.extend([
+                [self.f_r, self.f_r_ep, self.f_r_em],
+                [self.f_theta, self.f_theta_ep, self.f_theta_em],
+                [self.i, self.i_ep, self.i_em],
+                [self.chi_0, self.chi_0_ep, self.chi_0_em],
+            ]);
+        }
+        if self.bar {
+            vec.extend([
+                [self.v_bar, self.v_bar_ep, self.v_bar_em],
+                [self.phi_bar, self.phi_bar_ep, self.phi_bar_em],
+                [self.r_bar, self.r_bar_ep, self.r_bar_em],
+            ]);
+        }
+        if self.fits(OptionalParam::ZSun) {
+            vec.push([self.z_sun, self.z_sun_ep, self.z_sun_em]);
+        }
+        match self.radial_flow {
+            RadialFlow::None => {}
+            RadialFlow::Constant => vec.push([self.v_flow, self.v_flow_ep, self.v_flow_em]),
+            RadialFlow::KTerm => vec.push([self.k_flow, self.k_flow_ep, self.k_flow_em]),
+        }
+        vec.extend(self.par_offsets.iter().map(|offset| {
+            [
+                offset.delta_varpi,
+                offset.delta_varpi_ep,
+                offset.delta_varpi_em,
+            ]
+        }));
+        if self.fits(OptionalParam::ErrorFloors) {
+            vec.extend(self.error_floors.iter().flat_map(|floor| {
+                [
+                    [floor.v_r, floor.v_r_ep, floor.v_r_em],
+                    [floor.v_t, floor.v_t_ep, floor.v_t_em],
+                ]
+            }));
+        }
+        if self.fits(OptionalParam::VertexDeviation) {
+            vec.push([self.l_v, self.l_v_ep, self.l_v_em]);
+        }
+        if self.fits(OptionalParam::TiltRz) {
+            vec.push([self.alpha_rz, self.alpha_rz_ep, self.alpha_rz_em]);
+        }
+        if self.sigma_profile != SigmaProfile::Constant {
+            vec.extend([
+                [self.h_sigma_r, self.h_sigma_r_ep, self.h_sigma_r_em],
+                [
+                    self.h_sigma_theta,
+                    self.h_sigma_theta_ep,
+                    self.h_sigma_theta_em,
+                ],
+                [self.h_sigma_z, self.h_sigma_z_ep, self.h_sigma_z_em],
+            ]);
+        }
+        vec
+    }
+    /// Get mutable references to the enabled extra
+    /// parameters and their plus and minus uncertainties
+    fn extras_mut(&mut self) -> Vec<[&mut F; 3]> {
+        // The references below borrow the fields, so check the optional parameters first
+        let [fits_z_sun, fits_error_floors, fits_l_v, fits_alpha_rz] = [
+            OptionalParam::ZSun,
+            OptionalParam::ErrorFloors,
+            OptionalParam::VertexDeviation,
+            OptionalParam::TiltRz,
+        ]
+        .map(|optional| self.fits(optional));
+        let mut vec = Vec::new();
+        if self.spiral_arms.is_some() {
+            vec.extend([
+                [&mut self.f_r, &mut self.f_r_ep, &mut self.f_r_em],
+                [
+                    &mut self.f_theta,
+                    &mut self.f_theta_ep,
+                    &mut self.f_theta_em,
+                ],
+                [&mut self.i, &mut self.i_ep, &mut self.i_em],
+                [&mut self.chi_0, &mut self.chi_0_ep, &mut self.chi_0_em],
+            ]);
+        }
+        if self.bar {
+            vec.extend([
+                [&mut self.v_bar, &mut self.v_bar_ep, &mut self.v_bar_em],
+                [
+                    &mut self.phi_bar,
+                    &mut self.phi_bar_ep,
+                    &mut self.phi_bar_em,
+                ],
+                [&mut self.r_bar, &mut self.r_bar_ep, &mut self.r_bar_em],
+            ]);
+        }
+        if fits_z_sun {
+            vec.push([&mut self.z_sun, &mut self.z_sun_ep, &mut self.z_sun_em]);
+        }
+        match self.radial_flow {
+            RadialFlow::None => {}
+            RadialFlow::Constant => {
+                vec.push([&mut self.v_flow, &mut self.v_flow_ep, &mut self.v_flow_em]);
+            }
+            RadialFlow::KTerm => {
+                vec.push([&mut self.k_flow, &mut self.k_flow_ep, &mut self.k_flow_em]);
+            }
+        }
+        vec.extend(self.par_offsets.iter_mut().map(|offset| {
+            [
+                &mut offset.delta_varpi,
+                &mut offset.delta_varpi_ep,
+                &mut offset.delta_varpi_em,
+            ]
+        }));
+        if fits_error_floors {
+            vec.extend(self.error_floors.iter_mut().flat_map(|floor| {
+                [
+                    [&mut floor.v_r, &mut floor.v_r_ep, &mut floor.v_r_em],
+                    [&mut floor.v_t, &mut floor.v_t_ep, &mut floor.v_t_em],
+                ]
+            }));
+        }
+        if fits_l_v {
+            vec.push([&mut self.l_v, &mut self.l_v_ep, &mut self.l_v_em]);
+        }
+        if fits_alpha_rz {
+            vec.push([
+                &mut self.alpha_rz,
+                &mut self.alpha_rz_ep,
+                &mut self.alpha_rz_em,
+            ]);
+        }
+        if self.sigma_profile != SigmaProfile::Constant {
+            vec.extend([
+                [
+                    &mut self.h_sigma_r,
+                    &mut self.h_sigma_r_ep,
+                    &mut self.h_sigma_r_em,
+                ],
+                [
+                    &mut self.h_sigma_theta,
+                    &mut self.h_sigma_theta_ep,
+                    &mut self.h_sigma_theta_em,
+                ],
+                [
+                    &mut self.h_sigma_z,
+                    &mut self.h_sigma_z_ep,
+                    &mut self.h_sigma_z_em,
+                ],
+            ]);
+        }
+        vec
+    }
+    /// Compute the sine and cosine of the angle of the rotation around the Y axis
+    /// from the heliocentric Galactic frame to the frame aligned with the
+    /// Galactocentric one
+    ///
+    /// The Galactic center lies on the X axis, while the Sun is at the height
+    /// `z_sun` above the Galactic plane, so the plane is tilted with respect
+    /// to the XY plane by the angle `asin(z_sun / R_0)` (as in Astropy).
+    #[allow(clippy::unwrap_used)]
+    pub fn compute_tilt(&self) -> (F, F)
+    where
+        F: Float,
+    {
+        let sin_tilt = self.z_sun / F::from(1000.).unwrap() / self.r_0;
+        let cos_tilt = (F::one() - sin_tilt.powi(2)).sqrt();
+        (sin_tilt, cos_tilt)
+    }
+    /// Split the point in the parameter space into the
+    /// main part and the part of the extra parameters
+    fn split_extras<'b>(&self, p: &'b [F]) -> (&'b [F], &'b [F]) {
+        p.split_at(p.len() - self.extra_names().len())
+    }
     /// Update the parameters with the point in the parameter space
     ///
-    /// Note that not all fields are updated, but only those needed for fitting
+    /// Note that not all fields are updated, but only those needed for fitting.
+    /// The shape parameters missing from the point get their default values.
     #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::unwrap_used)]
     pub fn update_with(&mut self, p: &[F])
     where
         F: Float + Debug,
     {
-        let len = p.len();
+        let (main_p, extra_p) = self.split_extras(p);
+        for ([x, _, _], &value) in self.extras_mut().into_iter().zip(extra_p) {
+            *x = value;
+        }
+
+        let len = main_p.len();
         let mut new_p = [F::zero(); PARAMS_N];
-        new_p[0..len].copy_from_slice(&p[0..len]);
+        for (x, &default) in new_p[PARAMS_N - N_MAX + 1..]
+            .iter_mut()
+            .zip(self.rotcurve.shape_defaults())
+        {
+            *x = F::from(default).unwrap();
+        }
+        new_p[0..len].copy_from_slice(&main_p[0..len]);
 
         self.r_0 = new_p[0];
         self.omega_0 = new_p[1];
@@ -230,7 +910,7 @@ impl<F> Params<F> {
     ///
     /// Note that not all fields are used, but only those needed for fitting
     #[allow(clippy::indexing_slicing)]
-    pub fn to_vec(&self, n: usize, remove_sigmas: bool) -> Vec<F>
+    pub fn to_vec(&self, n: usize) -> Vec<F>
     where
         F: Float + Debug,
     {
@@ -254,13 +934,103 @@ impl<F> Params<F> {
         ];
         let slice = &array[0..(PARAMS_N - N_MAX) + n];
         let mut vec = slice.to_vec();
-        if remove_sigmas {
-            for i in (6..9).rev() {
-                vec.remove(i);
-            }
-        }
+        vec.extend(self.extras().iter().map(|&[x, _, _]| x));
         vec
     }
+    /// Is the parameter at this index in the point of the degree `n` one of the
+    /// sigmas (or the other fitted parameters of the ellipsoid of natural
+    /// standard deviations)?
+    #[allow(clippy::indexing_slicing)]
+    fn is_sigma(&self, n: usize, index: usize) -> bool {
+        SIGMA_NAMES.contains(&self.names(n)[index])
+    }
+    /// Is the parameter at this index in the point of the degree `n` fixed by the user?
+    #[allow(clippy::indexing_slicing)]
+    pub fn is_fixed(&self, n: usize, index: usize) -> bool {
+        self.fixed.contains(&self.names(n)[index])
+    }
+    /// Get the mask of the frozen parameters in the point of the degree `n`:
+    /// those fixed by the user and, if requested, the sigmas
+    pub fn frozen_mask(&self, n: usize, remove_sigmas: bool) -> Vec<bool> {
+        (0..self.names(n).len())
+            .map(|index| self.is_fixed(n, index) || (remove_sigmas && self.is_sigma(n, index)))
+            .collect()
+    }
+    /// Construct a point in the parameter space from the free parameters only
+    pub fn to_free_vec(&self, n: usize, remove_sigmas: bool) -> Vec<F>
+    where
+        F: Float + Debug,
+    {
+        let mut vec = self.to_vec(n);
+        self.remove_frozen(n, &mut vec, remove_sigmas);
+        vec
+    }
+    /// Remove the frozen parameters from the point of the degree `n`
+    pub fn remove_frozen(&self, n: usize, p: &mut Vec<F>, remove_sigmas: bool)
+    where
+        F: Copy,
+    {
+        *p = p
+            .iter()
+            .zip(self.frozen_mask(n, remove_sigmas))
+            .filter_map(|(&x, frozen)| (!frozen).then_some(x))
+            .collect();
+    }
+    /// Insert the frozen parameters from these parameters
+    /// into the point of the degree `n` they were removed from
+    #[allow(clippy::unwrap_used)]
+    pub fn insert_frozen(&self, n: usize, p: &[F], remove_sigmas: bool) -> Vec<F>
+    where
+        F: Float + Debug,
+    {
+        let mut free = p.iter();
+        self.to_vec(n)
+            .into_iter()
+            .zip(self.frozen_mask(n, remove_sigmas))
+            .map(|(x, frozen)| if frozen { x } else { *free.next().unwrap() })
+            .collect()
+    }
+    /// Get the index of the parameter in the point
+    /// of the degree `n` with the frozen parameters removed
+    #[allow(clippy::indexing_slicing)]
+    pub fn free_index(&self, n: usize, index: usize, remove_sigmas: bool) -> usize {
+        self.frozen_mask(n, remove_sigmas)[..index]
+            .iter()
+            .filter(|&&frozen| !frozen)
+            .count()
+    }
+    /// Fix the parameter (optionally, at the specified value)
+    #[allow(clippy::indexing_slicing)]
+    pub fn try_fix(&mut self, name: &str, value: Option<F>) -> Result<()>
+    where
+        F: Float + Debug,
+    {
+        let n = self.rotcurve.n_max();
+        let names = self.names(n);
+        let index = names.iter().position(|&x| x == name).ok_or_else(|| {
+            anyhow!(
+                "Unknown parameter `{name}` (expected one of: {})",
+                names.join(", ")
+            )
+        })?;
+        if let Some(fixed_value) = value {
+            let mut p = self.to_vec(n);
+            p[index] = fixed_value;
+            self.update_with(&p);
+        }
+        if !self.fixed.contains(&names[index]) {
+            self.fixed.push(names[index]);
+        }
+        Ok(())
+    }
+    /// Format the names of the fixed parameters
+    pub fn format_fixed(&self) -> String {
+        if self.fixed.is_empty() {
+            "none".to_owned()
+        } else {
+            self.fixed.join(", ")
+        }
+    }
     /// Update the plus uncertainties of the parameters
     /// with the values in the provided vector
     ///
@@ -270,9 +1040,14 @@ impl<F> Params<F> {
     where
         F: Float + Debug,
     {
-        let len = p.len();
+        let (main_p, extra_p) = self.split_extras(p);
+        for ([_, x, _], &value) in self.extras_mut().into_iter().zip(extra_p) {
+            *x = value;
+        }
+
+        let len = main_p.len();
         let mut new_p = [F::zero(); PARAMS_N];
-        new_p[0..len].copy_from_slice(&p[0..len]);
+        new_p[0..len].copy_from_slice(&main_p[0..len]);
 
         self.r_0_ep = new_p[0];
         self.omega_0_ep = new_p[1];
@@ -316,7 +1091,9 @@ impl<F> Params<F> {
             self.theta_8_ep,
         ];
         let slice = &array[0..(PARAMS_N - N_MAX) + n];
-        slice.to_vec()
+        let mut vec = slice.to_vec();
+        vec.extend(self.extras().iter().map(|&[_, x, _]| x));
+        vec
     }
     /// Update the minus uncertainties of the parameters
     /// with the values in the provided vector
@@ -327,9 +1104,14 @@ impl<F> Params<F> {
     where
         F: Float + Debug,
     {
-        let len = p.len();
+        let (main_p, extra_p) = self.split_extras(p);
+        for ([_, _, x], &value) in self.extras_mut().into_iter().zip(extra_p) {
+            *x = value;
+        }
+
+        let len = main_p.len();
         let mut new_p = [F::zero(); PARAMS_N];
-        new_p[0..len].copy_from_slice(&p[0..len]);
+        new_p[0..len].copy_from_slice(&main_p[0..len]);
 
         self.r_0_em = new_p[0];
         self.omega_0_em = new_p[1];
@@ -373,12 +1155,15 @@ impl<F> Params<F> {
             self.theta_8_em,
         ];
         let slice = &array[0..(PARAMS_N - N_MAX) + n];
-        slice.to_vec()
+        let mut vec = slice.to_vec();
+        vec.extend(self.extras().iter().map(|&[_, _, x]| x));
+        vec
     }
-    /// Should you run computations for this parameter with this L'?
-    pub fn compute_with_l_stroke(index: usize, l_stroke: usize) -> bool {
-        (l_stroke == 1 && (6..9).contains(&index))
-            || (l_stroke == 3 && ((0..6).contains(&index) || (9..).contains(&index)))
+    /// Should you skip computations for this parameter (at this index
+    /// in the point of the degree `n`) with this L'?
+    pub fn compute_with_l_stroke(&self, n: usize, index: usize, l_stroke: usize) -> bool {
+        let is_sigma = self.is_sigma(n, index);
+        self.is_fixed(n, index) || (l_stroke == 1 && is_sigma) || (l_stroke == 3 && !is_sigma)
     }
 }
 
@@ -444,7 +1229,52 @@ const DESCRIPTIONS: &str = indoc!(
     # 57 theta_sun_em: Minus uncertainty in `theta_sun` [km/s]
     # 58 omega_sun: Circular rotation velocity of the Sun [km/s/kpc]
     # 59 omega_sun_ep: Plus uncertainty in `omega_sun` [km/s/kpc]
-    # 60 omega_sun_em: Minus uncertainty in `omega_sun` [km/s/kpc]"
+    # 60 omega_sun_em: Minus uncertainty in `omega_sun` [km/s/kpc]
+    # 61 f_R: Amplitude of the radial perturbation by the spiral density wave [km/s]
+    # 62 f_R_ep: Plus uncertainty in `f_R` [km/s]
+    # 63 f_R_em: Minus uncertainty in `f_R` [km/s]
+    # 64 f_theta: Amplitude of the azimuthal perturbation by the spiral density wave [km/s]
+    # 65 f_theta_ep: Plus uncertainty in `f_theta` [km/s]
+    # 66 f_theta_em: Minus uncertainty in `f_theta` [km/s]
+    # 67 i: Pitch angle of the spiral pattern [degrees]
+    # 68 i_ep: Plus uncertainty in `i` [degrees]
+    # 69 i_em: Minus uncertainty in `i` [degrees]
+    # 70 chi_0: Phase of the spiral density wave at the Sun [degrees]
+    # 71 chi_0_ep: Plus uncertainty in `chi_0` [degrees]
+    # 72 chi_0_em: Minus uncertainty in `chi_0` [degrees]
+    # 73 V_bar: Amplitude of the perturbation by the bar [km/s]
+    # 74 V_bar_ep: Plus uncertainty in `V_bar` [km/s]
+    # 75 V_bar_em: Minus uncertainty in `V_bar` [km/s]
+    # 76 phi_bar: Angle between the major axis of the bar and the Sun-GC line [degrees]
+    # 77 phi_bar_ep: Plus uncertainty in `phi_bar` [degrees]
+    # 78 phi_bar_em: Minus uncertainty in `phi_bar` [degrees]
+    # 79 R_bar: Radial extent of the perturbation by the bar [kpc]
+    # 80 R_bar_ep: Plus uncertainty in `R_bar` [kpc]
+    # 81 R_bar_em: Minus uncertainty in `R_bar` [kpc]
+    # 82 z_sun: Height of the Sun above the Galactic plane [pc]
+    # 83 z_sun_ep: Plus uncertainty in `z_sun` [pc]
+    # 84 z_sun_em: Minus uncertainty in `z_sun` [pc]
+    # 85 l_v: Vertex deviation of the ellipsoid of natural standard deviations [degrees]
+    # 86 l_v_ep: Plus uncertainty in `l_v` [degrees]
+    # 87 l_v_em: Minus uncertainty in `l_v` [degrees]
+    # 88 alpha_RZ: Tilt of the ellipsoid of natural standard deviations in the R-Z plane [degrees]
+    # 89 alpha_RZ_ep: Plus uncertainty in `alpha_RZ` [degrees]
+    # 90 alpha_RZ_em: Minus uncertainty in `alpha_RZ` [degrees]
+    # 91 h_sigma_R: Scale length of the radial profile of `sigma_R` [kpc]
+    # 92 h_sigma_R_ep: Plus uncertainty in `h_sigma_R` [kpc]
+    # 93 h_sigma_R_em: Minus uncertainty in `h_sigma_R` [kpc]
+    # 94 h_sigma_theta: Scale length of the radial profile of `sigma_theta` [kpc]
+    # 95 h_sigma_theta_ep: Plus uncertainty in `h_sigma_theta` [kpc]
+    # 96 h_sigma_theta_em: Minus uncertainty in `h_sigma_theta` [kpc]
+    # 97 h_sigma_Z: Scale length of the radial profile of `sigma_Z` [kpc]
+    # 98 h_sigma_Z_ep: Plus uncertainty in `h_sigma_Z` [kpc]
+    # 99 h_sigma_Z_em: Minus uncertainty in `h_sigma_Z` [kpc]
+    # 100 V_flow: Constant radial velocity of the global radial flow [km/s]
+    # 101 V_flow_ep: Plus uncertainty in `V_flow` [km/s]
+    # 102 V_flow_em: Minus uncertainty in `V_flow` [km/s]
+    # 103 K_flow: K term of the global radial flow [km/s/kpc]
+    # 104 K_flow_ep: Plus uncertainty in `K_flow` [km/s/kpc]
+    # 105 K_flow_em: Minus uncertainty in `K_flow` [km/s/kpc]"
 );
 
 impl<F> Model<F> {
@@ -464,9 +1294,7 @@ impl<F> Model<F> {
             {sample_description}
             # Descriptions:
             #
-            {DESCRIPTIONS}
-            #
-            # Note that the last 3 values are derived from the other parameters.
+            {descriptions}
             #
             # Constant parameters used:
             #
@@ -493,6 +1321,7 @@ impl<F> Model<F> {
             #
             ",
             sample_description = self.format_sample_description(),
+            descriptions = params.format_descriptions(),
             alpha_ngp = params.alpha_ngp,
             delta_ngp = params.delta_ngp,
             l_ncp = params.l_ncp,
@@ -502,7 +1331,9 @@ impl<F> Model<F> {
             w_sun_standard = params.w_sun_standard,
         );
         let records = vec![params];
-        output::serialize_to(&self.output_dir, "params", &header, &records)
+        output::serialize_to_renamed(&self.output_dir, "params", &header, &records, |column| {
+            params.shape_column(column)
+        })
     }
     /// Serialize the fitted parameters
     #[allow(clippy::too_many_lines)]
@@ -521,9 +1352,22 @@ impl<F> Model<F> {
             {sample_description}
             # Descriptions:
             #
-            {DESCRIPTIONS}
+            {descriptions}
+            #
+            # Family of the rotation curve: {rotcurve}
+            # Its shape parameters: {shape_names}
             #
-            # Note that the last 3 values are derived from the other parameters.
+            # Number of the spiral arms: {spiral_arms}
+            # Perturbation by the bar: {bar}
+            # Global radial flow: {radial_flow}
+            # Zero-point offsets of the parallaxes: {par_offsets}
+            # Error floors of the velocities: {error_floors}
+            #
+            # Fixed parameters (zero uncertainties): {fixed}
+            # Priors of the parameters: {priors}
+            #
+            # Radial profile of the natural standard deviations: {sigma_profile}
+            # (the sigmas are given at R = R_0)
             #
             # Initial parameters used:
             #
@@ -545,6 +1389,9 @@ impl<F> Model<F> {
             # Residual motion of the Sun toward NGP [km/s]
             # W_SUN: {w_sun}
             #
+            # Height of the Sun above the Galactic plane [pc]
+            # Z_SUN: {z_sun}{z_sun_fixed}
+            #
             # Radial component of the ellipsoid of natural standard deviations [km/s]
             # SIGMA_R: {sigma_r_g}
             #
@@ -554,6 +1401,17 @@ impl<F> Model<F> {
             # Vertical component of the ellipsoid of natural standard deviations [km/s]
             # SIGMA_Z: {sigma_z}
             #
+            # Vertex deviation of the ellipsoid of natural standard deviations [degrees]
+            # L_V: {l_v}{l_v_fixed}
+            #
+            # Tilt of the ellipsoid of natural standard deviations in the R-Z plane [degrees]
+            # ALPHA_RZ: {alpha_rz}{alpha_rz_fixed}
+            #
+            # Scale lengths of the radial profile of the natural standard deviations [kpc]
+            # H_SIGMA_R: {h_sigma_r}
+            # H_SIGMA_THETA: {h_sigma_theta}
+            # H_SIGMA_Z: {h_sigma_z}
+            #
             # Constant parameters used:
             #
             # The right ascension of the north galactic pole [radians]
@@ -579,15 +1437,50 @@ impl<F> Model<F> {
             #
             ",
             sample_description = self.format_sample_description(),
+            descriptions = params.format_descriptions(),
+            rotcurve = params.rotcurve,
+            shape_names = params.rotcurve.shape_names().join(", "),
+            spiral_arms = params.spiral_arms.map_or_else(
+                || "none (no spiral perturbation)".to_owned(),
+                |m| m.to_string()
+            ),
+            bar = if params.bar { "enabled" } else { "disabled" },
+            radial_flow = params.radial_flow,
+            par_offsets = params.format_par_offsets(),
+            error_floors = params.format_error_floors(),
+            fixed = params.format_fixed(),
+            priors = self.priors.format(),
+            sigma_profile = params.sigma_profile,
             r_0 = params.r_0,
             omega_0 = params.omega_0,
             a = params.a,
             u_sun = params.u_sun,
             v_sun = params.v_sun,
             w_sun = params.w_sun,
+            z_sun = params.z_sun,
+            z_sun_fixed = if params.fits(OptionalParam::ZSun) {
+                ""
+            } else {
+                " (fixed)"
+            },
             sigma_r_g = params.sigma_r_g,
             sigma_theta = params.sigma_theta,
             sigma_z = params.sigma_z,
+            l_v = params.l_v,
+            l_v_fixed = if params.fits(OptionalParam::VertexDeviation) {
+                ""
+            } else {
+                " (fixed)"
+            },
+            alpha_rz = params.alpha_rz,
+            alpha_rz_fixed = if params.fits(OptionalParam::TiltRz) {
+                ""
+            } else {
+                " (fixed)"
+            },
+            h_sigma_r = params.h_sigma_r,
+            h_sigma_theta = params.h_sigma_theta,
+            h_sigma_z = params.h_sigma_z,
             alpha_ngp = params.alpha_ngp,
             delta_ngp = params.delta_ngp,
             l_ncp = params.l_ncp,
@@ -598,7 +1491,9 @@ impl<F> Model<F> {
         );
         let name = "fit_params";
         let records = vec![fit_params];
-        output::serialize_to(&self.output_dir, name, &header, &records)?;
+        output::serialize_to_renamed(&self.output_dir, name, &header, &records, |column| {
+            params.shape_column(column)
+        })?;
         Ok(())
     }
     /// Write the header to the plain file
@@ -613,7 +1508,8 @@ impl<F> Model<F> {
                 "
             Fits of the models (parameters)
             {sample_description}
-            Numbers under the errors of `theta_i`, i >= 2, are absolute values of `\\sigma_{{\\theta_i}} / \\theta_i`.
+            Numbers under the errors of `theta_i`, i >= 2, (or the shape parameters of the other families
+            of the rotation curve) are absolute values of `\\sigma_{{\\theta_i}} / \\theta_i`.
 
             Optimization results:
             ",
@@ -690,9 +1586,9 @@ impl<F> Model<F> {
         }
 
         let params = &self.params;
-        let params_vec = params.to_vec(n, false);
+        let params_vec = params.to_vec(n);
         let fit_params = self.fit_params.as_ref().unwrap();
-        let fit_params_vec = fit_params.to_vec(n, false);
+        let fit_params_vec = fit_params.to_vec(n);
         let fit_ep_vec = fit_params.to_ep_vec(n);
         let fit_em_vec = fit_params.to_em_vec(n);
         write!(
@@ -702,6 +1598,10 @@ impl<F> Model<F> {
 
                       n: {n}
                     L_1: {best_cost}
+                  fixed: {fixed}
+            par_offsets: {par_offsets}
+                 floors: {error_floors}
+                 priors: {priors}
 
                     R_0: {r_0:>21.15} -> {fit_r_0:>21.15}  + {fit_r_0_ep:>18.15}  - {fit_r_0_em:>18.15}
                 omega_0: {omega_0:>21.15} -> {fit_omega_0:>21.15}  + {fit_omega_0_ep:>18.15}  - {fit_omega_0_em:>18.15}
@@ -715,6 +1615,10 @@ impl<F> Model<F> {
             ",
                 n = self.n.unwrap(),
                 best_cost = self.best_cost.as_ref().unwrap(),
+                fixed = params.format_fixed(),
+                par_offsets = params.format_par_offsets(),
+                error_floors = params.format_error_floors(),
+                priors = self.priors.format(),
                 r_0 = params.r_0,
                 omega_0 = params.omega_0,
                 a = params.a,
@@ -753,6 +1657,7 @@ impl<F> Model<F> {
                 fit_sigma_z_em = fit_params.sigma_z_em,
         ))?;
 
+        let names = params.names(n);
         for i in (PARAMS_N - N_MAX + 1)..(PARAMS_N - N_MAX + n) {
             let initial = params_vec[i];
             let fit = fit_params_vec[i];
@@ -760,9 +1665,8 @@ impl<F> Model<F> {
             let fit_em = fit_em_vec[i];
             writeln!(
                 plain_writer,
-                "{s:4}theta_{n}: {initial:>21.15} -> {fit:>21.15}  + {fit_ep:>18.15}  - {fit_em:>18.15}",
-                s = "",
-                n = i - 7,
+                "{name:>11}: {initial:>21.15} -> {fit:>21.15}  + {fit_ep:>18.15}  - {fit_em:>18.15}",
+                name = names[i],
             )?;
             writeln!(
                 plain_writer,
@@ -772,6 +1676,17 @@ impl<F> Model<F> {
                 s = "",
             )?;
         }
+        for i in (PARAMS_N - N_MAX + n)..params_vec.len() {
+            writeln!(
+                plain_writer,
+                "{name:>11}: {initial:>21.15} -> {fit:>21.15}  + {fit_ep:>18.15}  - {fit_em:>18.15}",
+                name = names[i],
+                initial = params_vec[i],
+                fit = fit_params_vec[i],
+                fit_ep = fit_ep_vec[i],
+                fit_em = fit_em_vec[i],
+            )?;
+        }
 
         write!(
             plain_writer,
@@ -806,3 +1721,36 @@ impl<F> Model<F> {
         Ok(())
     }
 }
+
+#[test]
+fn test() -> Result<()> {
+    let mut params = Params::<f64> {
+        rotcurve: RotationCurveKind::Taylor,
+        ..Default::default()
+    };
+    params.try_fix("R_0", Some(8.15))?;
+    params.try_fix("w_sun", None)?;
+    ensure!(params.try_fix("R_1", None).is_err());
+    ensure!((params.r_0 - 8.15).abs() < f64::EPSILON && params.fixed == ["R_0", "w_sun"]);
+    // The fixed parameters are removed from the free point...
+    let n = 2;
+    let p = params.to_vec(n);
+    let free_p = params.to_free_vec(n, true);
+    ensure!(free_p.len() == p.len() - 5);
+    ensure!(params.free_index(n, 1, true) == 0 && params.free_index(n, 9, true) == 4);
+    // ...and restored from the initial values
+    ensure!(params.insert_frozen(n, &free_p, true) == p);
+    ensure!(params.is_fixed(n, 0) && !params.is_fixed(n, 1));
+    // The columns of the shape parameters are renamed after
+    // the family of the rotation curve, the unused ones are dropped
+    ensure!(params.shape_column("theta_2_ep").as_deref() == Some("theta_2_ep"));
+    params.rotcurve = RotationCurveKind::Universal;
+    ensure!(params.shape_column("theta_3_ep").as_deref() == Some("a_3_ep"));
+    ensure!(params.shape_column("theta_4").is_none());
+    ensure!(params.shape_column("theta_0").as_deref() == Some("theta_0"));
+    ensure!(params.shape_column("theta_sun").as_deref() == Some("theta_sun"));
+    ensure!(params
+        .format_descriptions()
+        .contains("# 34 theta_0: The constant term of the rotation curve"));
+    Ok(())
+}
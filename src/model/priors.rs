@@ -0,0 +1,298 @@
+//! Priors of the model parameters
+
+use core::fmt::{Debug, Display};
+use core::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use autodiff::{F as Dual, FT};
+use num::Float;
+use numeric_literals::replace_float_literals;
+
+/// Prior distribution of a parameter
+#[derive(Debug, Clone, Copy)]
+pub enum Prior<F> {
+    /// Uniform distribution on the interval
+    Uniform {
+        /// Lower bound
+        low: F,
+        /// Upper bound
+        high: F,
+    },
+    /// Normal distribution
+    Normal {
+        /// Mean
+        mean: F,
+        /// Standard deviation
+        std_dev: F,
+    },
+    /// Log-normal distribution (e.g., for the sigmas)
+    LogNormal {
+        /// Median
+        median: F,
+        /// Standard deviation of the natural logarithm
+        std_dev: F,
+    },
+}
+
+impl<F> Prior<F> {
+    /// Compute the logarithm of the (unnormalized) density
+    pub fn ln_density(&self, x: F) -> F
+    where
+        F: Float + Debug,
+    {
+        self.ln_density_with(x, |y| y)
+    }
+    /// Compute the logarithm of the (unnormalized) density
+    /// in another (e.g., dual) number type
+    #[allow(clippy::unwrap_used)]
+    #[replace_float_literals(D::from(literal).unwrap())]
+    pub fn ln_density_with<D, L>(&self, x: D, lift: L) -> D
+    where
+        F: Float + Debug,
+        D: Float,
+        L: Fn(F) -> D,
+    {
+        match *self {
+            Self::Uniform { low, high } => {
+                if lift(low) <= x && x <= lift(high) {
+                    0.
+                } else {
+                    D::neg_infinity()
+                }
+            }
+            Self::Normal { mean, std_dev } => -0.5 * ((x - lift(mean)) / lift(std_dev)).powi(2),
+            Self::LogNormal { median, std_dev } => {
+                if x > 0. {
+                    -x.ln() - 0.5 * ((x.ln() - lift(median).ln()) / lift(std_dev)).powi(2)
+                } else {
+                    D::neg_infinity()
+                }
+            }
+        }
+    }
+}
+
+impl<F> Display for Prior<F>
+where
+    F: Copy + Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            Self::Uniform { low, high } => write!(f, "uniform:{low}:{high}"),
+            Self::Normal { mean, std_dev } => write!(f, "normal:{mean}:{std_dev}"),
+            Self::LogNormal { median, std_dev } => write!(f, "log-normal:{median}:{std_dev}"),
+        }
+    }
+}
+
+/// Priors of the parameters
+///
+/// The parameters without a prior have a flat one. The priors enter
+/// the cost function as penalties (the negative logarithms of the densities).
+#[derive(Debug, Clone)]
+pub struct Priors<F> {
+    /// Pairs of the names of the parameters
+    /// (as in `Params::names`) and their priors
+    pub vec: Vec<(&'static str, Prior<F>)>,
+}
+
+impl<F> Default for Priors<F> {
+    fn default() -> Self {
+        Self { vec: Vec::new() }
+    }
+}
+
+impl<F> Priors<F> {
+    /// Try to parse the priors from the specifications in the
+    /// `NAME=uniform:LOW:HIGH`, `NAME=normal:MEAN:STD_DEV`, or
+    /// `NAME=log-normal:MEDIAN:STD_DEV` form, where `NAME` is one of the `names`
+    pub fn try_parse_from(specs: &[String], names: &[&'static str]) -> Result<Self>
+    where
+        F: Float + Debug + FromStr,
+        <F as FromStr>::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let vec = specs
+            .iter()
+            .map(|spec| {
+                try_parse_prior(spec, names)
+                    .with_context(|| format!("Couldn't parse the prior from the string {spec:?}"))
+            })
+            .collect::<Result<Vec<(&'static str, Prior<F>)>>>()?;
+        Ok(Self { vec })
+    }
+    /// Compute the logarithm of the (unnormalized) joint density at
+    /// the point in the parameter space with the parameters `names`
+    ///
+    /// The priors of the parameters that are not present are skipped.
+    pub fn ln_density(&self, names: &[&str], p: &[F]) -> F
+    where
+        F: Float + Debug,
+    {
+        self.vec
+            .iter()
+            .filter_map(|&(name, ref prior)| {
+                let index = names.iter().position(|&x| x == name)?;
+                p.get(index).map(|&x| prior.ln_density(x))
+            })
+            .fold(F::zero(), |acc, x| acc + x)
+    }
+    /// Compute the gradient of the penalty (the negative
+    /// logarithm of the joint density) at the point
+    pub fn penalty_gradient(&self, names: &[&str], p: &[F]) -> Vec<F>
+    where
+        F: Float + Debug,
+    {
+        let mut gradient = vec![F::zero(); p.len()];
+        for &(name, ref prior) in &self.vec {
+            if let Some(index) = names.iter().position(|&x| x == name) {
+                if let (Some(partial), Some(&x)) = (gradient.get_mut(index), p.get(index)) {
+                    *partial = *partial - prior.ln_density_with(FT::var(x), FT::cst).deriv();
+                }
+            }
+        }
+        gradient
+    }
+    /// Compute the diagonal of the Hessian of the penalty (the negative
+    /// logarithm of the joint density) at the point
+    ///
+    /// The priors are independent, so the other elements are zeros.
+    pub fn penalty_hessian_diagonal(&self, names: &[&str], p: &[F]) -> Vec<F>
+    where
+        F: Float + Debug,
+    {
+        let lift = |x: F| Dual::cst(FT::<F>::cst(x));
+        let mut diagonal = vec![F::zero(); p.len()];
+        for &(name, ref prior) in &self.vec {
+            if let Some(index) = names.iter().position(|&x| x == name) {
+                if let (Some(second), Some(&x)) = (diagonal.get_mut(index), p.get(index)) {
+                    let hyper_dual_x =
+                        Dual::new(FT::<F>::new(x, F::one()), FT::<F>::new(F::one(), F::zero()));
+                    let ln_density = prior.ln_density_with(hyper_dual_x, lift);
+                    *second = *second - ln_density.deriv().deriv();
+                }
+            }
+        }
+        diagonal
+    }
+    /// Format the priors in the form of the specifications
+    pub fn format(&self) -> String
+    where
+        F: Copy + Display,
+    {
+        if self.vec.is_empty() {
+            "none".to_owned()
+        } else {
+            self.vec
+                .iter()
+                .map(|&(name, ref prior)| format!("{name}={prior}"))
+                .collect::<Vec<String>>()
+                .join(", ")
+        }
+    }
+}
+
+/// Try to parse a prior from the specification
+fn try_parse_prior<F>(spec: &str, names: &[&'static str]) -> Result<(&'static str, Prior<F>)>
+where
+    F: Float + Debug + FromStr,
+    <F as FromStr>::Err: std::error::Error + Send + Sync + 'static,
+{
+    let (given, distribution) = spec
+        .split_once('=')
+        .ok_or_else(|| anyhow!("The name of the parameter should be followed by `=`"))?;
+    let name = names
+        .iter()
+        .copied()
+        .find(|&x| x == given)
+        .ok_or_else(|| anyhow!("Unknown parameter `{given}`"))?;
+    let mut parts = distribution.split(':');
+    let kind = parts.next().unwrap_or_default();
+    let values = parts
+        .map(|part| {
+            part.parse::<F>()
+                .with_context(|| format!("Couldn't parse {part:?}"))
+        })
+        .collect::<Result<Vec<F>>>()?;
+    let prior = match (kind, values.as_slice()) {
+        ("uniform", &[low, high]) => {
+            ensure!(
+                low < high,
+                "The lower bound should be less than the upper one"
+            );
+            Prior::Uniform { low, high }
+        }
+        ("normal", &[mean, std_dev]) => {
+            ensure!(
+                std_dev > F::zero(),
+                "The standard deviation should be positive"
+            );
+            Prior::Normal { mean, std_dev }
+        }
+        ("log-normal", &[median, std_dev]) => {
+            ensure!(median > F::zero(), "The median should be positive");
+            ensure!(
+                std_dev > F::zero(),
+                "The standard deviation should be positive"
+            );
+            Prior::LogNormal { median, std_dev }
+        }
+        _ => bail!(
+            "Expected `uniform:LOW:HIGH`, `normal:MEAN:STD_DEV`, or `log-normal:MEDIAN:STD_DEV`"
+        ),
+    };
+    Ok((name, prior))
+}
+
+#[test]
+fn test() -> Result<()> {
+    let names = ["R_0", "omega_0", "sigma_R"];
+    let priors = Priors::<f64>::try_parse_from(
+        &[
+            "R_0=normal:8.15:0.15".to_owned(),
+            "omega_0=uniform:20:40".to_owned(),
+        ],
+        &names,
+    )?;
+    let a = priors.ln_density(&names, &[8.3, 30.]);
+    ensure!(
+        (a + 0.5).abs() < 1e-12,
+        "The logarithm of the density should be -0.5: {a:?}"
+    );
+    let b = priors.ln_density(&names, &[8.15, 50.]);
+    ensure!(
+        b == f64::NEG_INFINITY,
+        "The point should be outside of the support: {b:?}"
+    );
+    ensure!(
+        Priors::<f64>::try_parse_from(&["bogus=normal:0:1".to_owned()], &names).is_err(),
+        "Unknown names should be rejected"
+    );
+    // The derivatives of the penalties should match the analytic ones
+    let penalties = Priors::<f64>::try_parse_from(
+        &[
+            "R_0=normal:8.15:0.15".to_owned(),
+            "sigma_R=log-normal:10:0.5".to_owned(),
+        ],
+        &names,
+    )?;
+    let p = [8.3, 30., 12.];
+    let gradient = penalties.penalty_gradient(&names, &p);
+    let diagonal = penalties.penalty_hessian_diagonal(&names, &p);
+    let z = (12_f64.ln() - 10_f64.ln()) / 0.25;
+    let close = |values: &[f64], expected: [f64; 3]| {
+        values.len() == expected.len()
+            && values
+                .iter()
+                .zip(expected)
+                .all(|(value, expected_value)| (value - expected_value).abs() < 1e-9)
+    };
+    ensure!(
+        close(&gradient, [0.15 / 0.0225, 0., (1. + z) / 12.]),
+        "Unexpected gradient of the penalty: {gradient:?}"
+    );
+    ensure!(
+        close(&diagonal, [1. / 0.0225, 0., (1. / 0.25 - 1. - z) / 144.]),
+        "Unexpected Hessian of the penalty: {diagonal:?}"
+    );
+    Ok(())
+}
@@ -0,0 +1,460 @@
+//! Injection-recovery campaign
+
+use super::io::output;
+use super::{FitSettings, Model, Object, Params, SpatialDistribution};
+use crate::utils::FiniteDiff;
+
+use core::fmt::{Debug, Display};
+use core::iter::Sum;
+use core::str::FromStr;
+use std::error::Error;
+
+use anyhow::{ensure, Context, Result};
+use argmin::core::ArgminFloat;
+use argmin_math::{
+    ArgminAdd, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax, ArgminMul, ArgminSignum,
+    ArgminSub, ArgminZeroLike,
+};
+use indoc::formatdoc;
+use itertools::izip;
+use nalgebra::ComplexField;
+use num::Float;
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+use rand_distr::{Exp1, StandardNormal};
+use serde::Serialize;
+
+/// Recovered value of a parameter in a trial
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveryTrial<F> {
+    /// Index of the trial (starting from 1)
+    pub trial: usize,
+    /// Degree of the polynomial of the rotation curve
+    pub n: usize,
+    /// Name of the parameter
+    pub name: &'static str,
+    /// Number of the objects after the L' = 1 run
+    pub n_objects: usize,
+    /// Injected value
+    #[serde(rename = "true")]
+    pub true_value: F,
+    /// Recovered value
+    #[serde(rename = "fit")]
+    pub fit_value: F,
+    /// Uncertainty in `fit` (from the covariance matrix)
+    pub error: F,
+    /// Pull, `(fit - true) / error` (NaN if the parameter is fixed)
+    pub pull: F,
+}
+
+/// Summary of the recovered values of a parameter
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoverySummary<F> {
+    /// Degree of the polynomial of the rotation curve
+    pub n: usize,
+    /// Name of the parameter
+    pub name: &'static str,
+    /// Number of the successful trials
+    pub n_trials: usize,
+    /// Injected value
+    #[serde(rename = "true")]
+    pub true_value: F,
+    /// Mean of the recovered values
+    pub mean: F,
+    /// Bias, `mean - true`
+    pub bias: F,
+    /// Uncertainty in `bias`
+    pub bias_e: F,
+    /// Standard deviation of the recovered values
+    pub scatter: F,
+    /// Mean of the pulls (NaN if the parameter is fixed)
+    pub pull_mean: F,
+    /// Standard deviation of the pulls
+    pub pull_std: F,
+}
+
+/// Failed trial of the injection-recovery campaign
+#[derive(Debug, Clone)]
+pub struct RecoveryFailure {
+    /// Index of the trial (starting from 1)
+    pub trial: usize,
+    /// Chain of the errors of the fit
+    pub error: String,
+}
+
+/// Results of the injection-recovery campaign
+#[derive(Debug, Clone)]
+pub struct RecoveryResults<F> {
+    /// Recovered values (one per trial, degree, and parameter)
+    pub trials: Vec<RecoveryTrial<F>>,
+    /// Summaries (one per degree and parameter)
+    pub summaries: Vec<RecoverySummary<F>>,
+    /// Failed trials
+    pub failures: Vec<RecoveryFailure>,
+}
+
+/// Compute the mean and the standard deviation of the values
+#[allow(clippy::unwrap_used)]
+fn compute_mean_std<F>(values: &[F]) -> (F, F)
+where
+    F: Float + Sum,
+{
+    let len = F::from(values.len()).unwrap();
+    let mean = values.iter().copied().sum::<F>() / len;
+    let var = values.iter().map(|&x| (x - mean).powi(2)).sum::<F>() / (len - F::one());
+    (mean, var.sqrt())
+}
+
+impl<F> Model<F>
+where
+    F: Float
+        + Debug
+        + Default
+        + Display
+        + FromStr
+        + Sync
+        + Send
+        + Sum
+        + ArgminFloat
+        + ArgminL2Norm<F>
+        + ArgminSub<F, F>
+        + ArgminAdd<F, F>
+        + ArgminDot<F, F>
+        + ArgminMul<F, F>
+        + ArgminZeroLike
+        + ArgminMul<Vec<F>, Vec<F>>
+        + ComplexField,
+    <F as FromStr>::Err: Error + Send + Sync + 'static,
+    Vec<F>: ArgminSub<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminSub<F, Vec<F>>,
+    Vec<F>: ArgminAdd<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminAdd<F, Vec<F>>,
+    Vec<F>: ArgminMul<F, Vec<F>>,
+    Vec<F>: ArgminMul<Vec<F>, Vec<F>>,
+    Vec<F>: ArgminL1Norm<F>,
+    Vec<F>: ArgminSignum,
+    Vec<F>: ArgminMinMax,
+    Vec<F>: ArgminDot<Vec<F>, F>,
+    Vec<F>: ArgminL2Norm<F>,
+    Vec<F>: FiniteDiff<F>,
+{
+    /// Run the injection-recovery campaign: simulate catalogues
+    /// using the initial parameters of the model, fit them the same
+    /// way as the real data, and compare the results to the injection
+    ///
+    /// Each fit starts from a point perturbed from the injection, so that
+    /// the biases of the optimizer are measured, too. The free parameters
+    /// are multiplied by `1 + start_jitter * z` (or shifted by
+    /// `start_jitter * z` if zero), where `z` is a standard normal variate.
+    #[allow(clippy::indexing_slicing)]
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    pub fn run_recovery<R>(
+        &self,
+        settings: &FitSettings,
+        distribution: Option<&SpatialDistribution<F>>,
+        n_trials: usize,
+        start_jitter: F,
+        rng: &mut R,
+    ) -> Result<RecoveryResults<F>>
+    where
+        R: Rng,
+        Standard: Distribution<F>,
+        StandardNormal: Distribution<F>,
+        Exp1: Distribution<F>,
+    {
+        ensure!(n_trials >= 2, "There should be at least two trials");
+
+        let mut trials = Vec::new();
+        let mut failures = Vec::new();
+        for trial in 1..=n_trials {
+            let catalogue = self
+                .simulate(distribution, rng)
+                .with_context(|| "Couldn't simulate a catalogue")?;
+            let objects = catalogue
+                .records
+                .into_iter()
+                .map(Object::try_from)
+                .collect::<Result<Vec<Object<F>>>>()
+                .with_context(|| "Couldn't parse the simulated records into objects")?;
+            let mut model = self.with_objects(objects);
+            model.params = self.perturb_start(settings.n_max, start_jitter, rng);
+
+            let results = match model.fit(settings) {
+                Ok(results) => results,
+                Err(err) => {
+                    failures.push(RecoveryFailure {
+                        trial,
+                        error: format!("{err:#}"),
+                    });
+                    continue;
+                }
+            };
+
+            for (i, fitted_model) in results.models.iter().enumerate() {
+                let n = i + 1;
+                let Some(ref fit_params) = fitted_model.fit_params else {
+                    continue;
+                };
+                let covariance_matrix = fitted_model.covariance_matrix.as_ref().unwrap();
+                // The fixed parameters have no uncertainties, so they have no pulls either
+                for (j, name, true_value, fit_value, frozen) in izip!(
+                    0..,
+                    self.params.names(n),
+                    self.params.to_vec(n),
+                    fit_params.to_vec(n),
+                    fitted_model.params.frozen_mask(n, false),
+                ) {
+                    let error = Float::sqrt(covariance_matrix[(j, j)]);
+                    let pull = if frozen {
+                        F::nan()
+                    } else {
+                        (fit_value - true_value) / error
+                    };
+                    trials.push(RecoveryTrial {
+                        trial,
+                        n,
+                        name,
+                        n_objects: fitted_model.l_stroke_1_n.unwrap(),
+                        true_value,
+                        fit_value,
+                        error,
+                        pull,
+                    });
+                }
+            }
+        }
+
+        ensure!(
+            n_trials - failures.len() >= 2,
+            "There should be at least two successful trials"
+        );
+
+        let summaries = self.summarize_trials(settings.n_max, &trials);
+
+        Ok(RecoveryResults {
+            trials,
+            summaries,
+            failures,
+        })
+    }
+}
+
+impl<F> Model<F> {
+    /// Summarize the recovered values of the parameters of the degrees up to `n_max`
+    #[allow(clippy::unwrap_used)]
+    fn summarize_trials(&self, n_max: usize, trials: &[RecoveryTrial<F>]) -> Vec<RecoverySummary<F>>
+    where
+        F: Float + Debug + Sum,
+    {
+        let mut summaries = Vec::new();
+        for n in 1..=n_max {
+            for (name, true_value) in izip!(self.params.names(n), self.params.to_vec(n)) {
+                let records: Vec<&RecoveryTrial<F>> = trials
+                    .iter()
+                    .filter(|record| record.n == n && record.name == name)
+                    .collect();
+                if records.len() < 2 {
+                    continue;
+                }
+                let fit_values: Vec<F> = records.iter().map(|record| record.fit_value).collect();
+                let pulls: Vec<F> = records
+                    .iter()
+                    .map(|record| record.pull)
+                    .filter(|pull| !pull.is_nan())
+                    .collect();
+                let (mean, scatter) = compute_mean_std(&fit_values);
+                let (pull_mean, pull_std) = if pulls.len() < 2 {
+                    (F::nan(), F::nan())
+                } else {
+                    compute_mean_std(&pulls)
+                };
+                summaries.push(RecoverySummary {
+                    n,
+                    name,
+                    n_trials: records.len(),
+                    true_value,
+                    mean,
+                    bias: mean - true_value,
+                    bias_e: scatter / Float::sqrt(F::from(records.len()).unwrap()),
+                    scatter,
+                    pull_mean,
+                    pull_std,
+                });
+            }
+        }
+        summaries
+    }
+    /// Perturb the free initial parameters of the degree `n`
+    fn perturb_start<R>(&self, n: usize, start_jitter: F, rng: &mut R) -> Params<F>
+    where
+        F: Float + Debug,
+        R: Rng,
+        StandardNormal: Distribution<F>,
+    {
+        let mut params = self.params.clone();
+        let p: Vec<F> = izip!(self.params.to_vec(n), self.params.frozen_mask(n, false))
+            .map(|(x, frozen)| {
+                if frozen {
+                    return x;
+                }
+                let z: F = rng.sample(StandardNormal);
+                if x.is_zero() {
+                    start_jitter * z
+                } else {
+                    x * (F::one() + start_jitter * z)
+                }
+            })
+            .collect();
+        params.update_with(&p);
+        params
+    }
+    /// Serialize the results of the injection-recovery campaign
+    pub fn serialize_to_recovery(&self, results: &RecoveryResults<F>, seed: u64) -> Result<()>
+    where
+        F: Serialize,
+    {
+        let n_failed = results.failures.len();
+        let failures_description = results
+            .failures
+            .iter()
+            .map(|failure| format!("# Trial #{} failed: {}\n", failure.trial, failure.error))
+            .collect::<Vec<String>>()
+            .concat();
+        let trials_header = formatdoc!(
+            "
+            # Recovered values of the parameters in the injection-recovery campaign
+            #
+            # Descriptions:
+            #
+            # 1 trial: Index of the trial (starting from 1)
+            # 2 n: Degree of the polynomial of the rotation curve
+            # 3 name: Name of the parameter
+            # 4 n_objects: Number of the objects after the L' = 1 run
+            # 5 true: Injected value
+            # 6 fit: Recovered value
+            # 7 error: Uncertainty in `fit` (from the covariance matrix)
+            # 8 pull: Pull, `(fit - true) / error` (NaN if the parameter is fixed)
+            #
+            # Seed of the random number generator: {seed}
+            # Number of the failed trials: {n_failed}
+            {failures_description}#
+            "
+        );
+        output::serialize_to(
+            &self.output_dir,
+            "recovery_trials",
+            &trials_header,
+            &results.trials,
+        )
+        .with_context(|| "Couldn't write the recovered values")?;
+
+        let summaries_header = formatdoc!(
+            "
+            # Bias, scatter, and pulls of the parameters
+            # in the injection-recovery campaign
+            #
+            # Descriptions:
+            #
+            #  1 n: Degree of the polynomial of the rotation curve
+            #  2 name: Name of the parameter
+            #  3 n_trials: Number of the successful trials
+            #  4 true: Injected value
+            #  5 mean: Mean of the recovered values
+            #  6 bias: Bias, `mean - true`
+            #  7 bias_e: Uncertainty in `bias`
+            #  8 scatter: Standard deviation of the recovered values
+            #  9 pull_mean: Mean of the pulls (NaN if the parameter is fixed)
+            # 10 pull_std: Standard deviation of the pulls (NaN if the parameter is fixed)
+            #
+            # Seed of the random number generator: {seed}
+            # Number of the failed trials: {n_failed}
+            {failures_description}#
+            "
+        );
+        output::serialize_to(
+            &self.output_dir,
+            "recovery",
+            &summaries_header,
+            &results.summaries,
+        )
+        .with_context(|| "Couldn't write the summaries of the recovered values")
+    }
+}
+
+#[cfg(test)]
+use super::io::input;
+#[cfg(test)]
+use super::simulate::test_params;
+#[cfg(test)]
+use rand::SeedableRng;
+#[cfg(test)]
+use rand_chacha::ChaCha8Rng;
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test() -> Result<()> {
+    let params = test_params();
+    // The template only provides the uncertainties. The parallax one is
+    // small, since the inner optimization is disabled below for speed
+    let template = Object::try_from(input::Record {
+        name: "G049.19-00.33".to_owned(),
+        alpha: "19:22:57.7705".to_owned(),
+        delta: "+14:16:09.983".to_owned(),
+        par: 0.197,
+        par_e: 0.001,
+        v_lsr: 0.,
+        v_lsr_e: 3.,
+        mu_x: 0.,
+        mu_x_e: 0.05,
+        mu_y: 0.,
+        mu_y_e: 0.05,
+        obj_type: "HMSFR".to_owned(),
+        source: "Reid".to_owned(),
+        reference: String::new(),
+        mu_x_mu_y_corr: None,
+        par_mu_x_corr: None,
+        par_mu_y_corr: None,
+        par_ep: None,
+        par_em: None,
+        v_lsr_ep: None,
+        v_lsr_em: None,
+    })?;
+    let model = Model::builder()
+        .params(params.clone())
+        .objects(vec![template])
+        .disable_inner(true)
+        .build()?;
+    let distribution = SpatialDistribution {
+        n_objects: 100,
+        r_g_scale: 3.,
+        z_scale: 0.05,
+        r_h_max: 6.,
+    };
+    let settings = FitSettings {
+        n_max: 1,
+        disable_outliers: true,
+        ..Default::default()
+    };
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let results = model.run_recovery(&settings, Some(&distribution), 3, 0.05, &mut rng)?;
+    ensure!(results.failures.is_empty());
+    // The fits start away from the injection...
+    ensure!(results
+        .trials
+        .iter()
+        .all(|trial| f64::abs(trial.fit_value - trial.true_value) > 0.));
+    // ...but the recovered values of the main parameters should be close to it
+    for summary in &results.summaries {
+        if ["R_0", "omega_0", "A"].contains(&summary.name) {
+            ensure!(
+                f64::abs(summary.bias / summary.true_value) < 0.05,
+                "The bias in `{}` should be small: {}",
+                summary.name,
+                summary.bias
+            );
+        }
+        ensure!(summary.pull_mean.is_finite() && summary.pull_std.is_finite());
+    }
+    Ok(())
+}
@@ -0,0 +1,583 @@
+//! Synthetic catalogues
+
+use super::fit::prepare_inner_problem;
+use super::io::{input, output};
+use super::params::{ALPHA_RZ_NAME, L_V_NAME, Z_SUN_NAME};
+use super::{Model, Object, Params, RotationCurveModel};
+use crate::utils;
+
+use core::fmt::{Debug, Display};
+
+use anyhow::{ensure, Context, Result};
+use indoc::formatdoc;
+use itertools::izip;
+use num::Float;
+use numeric_literals::replace_float_literals;
+use rand::distributions::{Distribution, Standard};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand_distr::{Exp1, StandardNormal};
+use serde::Serialize;
+
+/// Spatial distribution of the simulated objects
+///
+/// The objects are distributed in an exponential disk with a Gaussian
+/// vertical profile. Only those of them that are not farther from
+/// the Sun than the specified heliocentric distance are kept.
+#[derive(Debug, Clone)]
+pub struct SpatialDistribution<F> {
+    /// Number of the objects
+    pub n_objects: usize,
+    /// Radial scale length of the disk (kpc)
+    pub r_g_scale: F,
+    /// Vertical scale height of the disk (kpc)
+    pub z_scale: F,
+    /// Maximum heliocentric distance (kpc)
+    pub r_h_max: F,
+}
+
+/// Simulated catalogue
+#[derive(Debug, Clone)]
+pub struct SimulatedCatalogue<F> {
+    /// Records of the objects (as in an input file)
+    pub records: Vec<input::Record<F>>,
+    /// Number of the objects whose observed parallaxes were redrawn
+    /// since they turned out non-positive (see `Model::simulate`)
+    pub n_redrawn: usize,
+}
+
+impl<F> SimulatedCatalogue<F> {
+    /// Collect the simulated records, counting those with the redrawn parallaxes
+    fn from_simulated(simulated: Vec<(input::Record<F>, bool)>) -> Self {
+        let n_redrawn = simulated.iter().filter(|pair| pair.1).count();
+        let records = simulated.into_iter().map(|pair| pair.0).collect();
+        Self { records, n_redrawn }
+    }
+}
+
+/// Sample a value from the normal distribution
+fn sample_normal<F, R>(rng: &mut R, mean: F, std_dev: F) -> F
+where
+    F: Float,
+    R: Rng,
+    StandardNormal: Distribution<F>,
+{
+    let z: F = rng.sample(StandardNormal);
+    mean + std_dev * z
+}
+
+impl<F> Model<F> {
+    /// Simulate a catalogue using the initial parameters of the model
+    ///
+    /// The objects of the model are used as templates: if no spatial
+    /// distribution is specified, their positions and parallaxes are
+    /// treated as the true ones; otherwise, the positions are sampled
+    /// from the distribution, and the uncertainties (as well as the
+    /// types and the sources) are taken from randomly chosen objects.
+    ///
+    /// The velocities and the proper motions are computed via the
+    /// forward model, then disturbed by the natural dispersions
+    /// and, after that, by the observational errors.
+    ///
+    /// The observed parallaxes are resampled until they turn out positive,
+    /// since the fitting routines can't handle the non-positive ones. So,
+    /// they follow the error distribution truncated at zero, which biases
+    /// them up for the objects with large relative errors. The number of
+    /// such objects is reported in the catalogue.
+    #[allow(clippy::unwrap_in_result)]
+    #[allow(clippy::unwrap_used)]
+    pub fn simulate<R>(
+        &self,
+        distribution: Option<&SpatialDistribution<F>>,
+        rng: &mut R,
+    ) -> Result<SimulatedCatalogue<F>>
+    where
+        F: Float + Debug + Default,
+        R: Rng,
+        Standard: Distribution<F>,
+        StandardNormal: Distribution<F>,
+        Exp1: Distribution<F>,
+    {
+        let params = &self.params;
+        let templates = self.objects.borrow();
+        ensure!(!templates.is_empty(), "There should be at least one object");
+
+        let Some(spatial) = distribution else {
+            let simulated = templates
+                .iter()
+                .map(|template| {
+                    let mut object = template.clone();
+                    object.compute_l_b(params);
+                    simulate_object(object, params, rng)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(SimulatedCatalogue::from_simulated(simulated));
+        };
+
+        ensure!(
+            spatial.r_g_scale > F::zero()
+                && spatial.z_scale > F::zero()
+                && spatial.r_h_max > F::zero(),
+            "The scales of the spatial distribution should be positive"
+        );
+
+        let simulated = (0..spatial.n_objects)
+            .map(|i| {
+                let template = templates.choose(rng).unwrap();
+                let mut object = Object {
+                    name: Some(format!("S{}", i + 1)),
+                    obj_type: template.obj_type.clone(),
+                    source: template.source.clone(),
+                    par_e: template.par_e,
+                    par_ep: template.par_ep,
+                    par_em: template.par_em,
+                    v_lsr_e: template.v_lsr_e,
+                    v_lsr_ep: template.v_lsr_ep,
+                    v_lsr_em: template.v_lsr_em,
+                    mu_x_e: template.mu_x_e,
+                    mu_y_e: template.mu_y_e,
+                    mu_x_mu_y_corr: template.mu_x_mu_y_corr,
+                    par_mu_x_corr: template.par_mu_x_corr,
+                    par_mu_y_corr: template.par_mu_y_corr,
+                    ..Default::default()
+                };
+                sample_position(&mut object, spatial, params, rng);
+                simulate_object(object, params, rng)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SimulatedCatalogue::from_simulated(simulated))
+    }
+    /// Serialize the simulated catalogue
+    ///
+    /// The result can be read back as an input file.
+    #[allow(clippy::too_many_lines)]
+    pub fn serialize_to_simulated(&self, catalogue: &SimulatedCatalogue<F>, seed: u64) -> Result<()>
+    where
+        F: Float + Debug + Display + Serialize,
+    {
+        let params = &self.params;
+        // List all of the parameters of the model used (including
+        // the extra ones that are enabled, fitted or not)
+        let n = params.rotcurve.n_max();
+        let names = params.names(n);
+        let mut values: Vec<String> = izip!(&names, params.to_vec(n))
+            .map(|(name, value)| format!("# {name}: {value}"))
+            .collect();
+        for (name, value) in [
+            (Z_SUN_NAME, params.z_sun),
+            (L_V_NAME, params.l_v),
+            (ALPHA_RZ_NAME, params.alpha_rz),
+        ] {
+            if !names.contains(&name) {
+                values.push(format!("# {name}: {value}"));
+            }
+        }
+        let header = formatdoc!(
+            "
+            # Synthetic catalogue: equatorial spherical coordinates of
+            # the objects, their parallaxes, proper motions in equatorial
+            # coordinates, and LSR velocities
+            #
+            # Descriptions:
+            #
+            #  1 name: Name of the object
+            #  2 alpha: Right ascension [HMS angle]
+            #  3 delta: Declination [DMS angle]
+            #  4 par: Trigonometric parallax [mas]
+            #  5 par_e: Uncertainty in `par` [mas]
+            #  6 v_lsr: Local Standard of Rest velocity [km/s]
+            #  7 v_lsr_e: Uncertainty in `v_lsr` [km/s]
+            #  8 mu_x: Eastward proper motion (1) [mas/yr]
+            #  9 mu_x_e: Uncertainty in `mu_x` [mas/yr]
+            # 10 mu_y: Northward proper motion [mas/yr]
+            # 11 mu_y_e: Uncertainty in `mu_y` [mas/yr]
+            # 12 type: Type of the object
+            # 13 source: Source of the data (2)
+            # 14 reference: Reference(s)
+            # 15 mu_x_mu_y_corr: Correlation coefficient of `mu_x` and `mu_y` (2)
+            # 16 par_mu_x_corr: Correlation coefficient of `par` and `mu_x` (2)
+            # 17 par_mu_y_corr: Correlation coefficient of `par` and `mu_y` (2)
+            # 18 par_ep: Plus uncertainty in `par` (2) [mas]
+            # 19 par_em: Minus uncertainty in `par` (2) [mas]
+            # 20 v_lsr_ep: Plus uncertainty in `v_lsr` (2) [km/s]
+            # 21 v_lsr_em: Minus uncertainty in `v_lsr` (2) [km/s]
+            #
+            # Notes:
+            #   1. Motion in RA multiplied by cos(Dec)
+            #   2. Inherited from the template objects
+            #
+            # Seed of the random number generator: {seed}
+            #
+            # The observed parallaxes follow the error distributions truncated
+            # at zero: those of {n_redrawn} objects were redrawn to be positive.
+            #
+            # Family of the rotation curve: {rotcurve}
+            # Number of the spiral arms: {spiral_arms}
+            # Perturbation by the bar: {bar}
+            # Global radial flow: {radial_flow}
+            # Zero-point offsets of the parallaxes: {par_offsets}
+            # Error floors of the velocities: {error_floors}
+            # Radial profile of the natural standard deviations: {sigma_profile}
+            #
+            # Parameters used:
+            #
+            {values}
+            #
+            ",
+            n_redrawn = catalogue.n_redrawn,
+            rotcurve = params.rotcurve,
+            spiral_arms = params.spiral_arms.map_or_else(
+                || "none (no spiral perturbation)".to_owned(),
+                |m| m.to_string()
+            ),
+            bar = if params.bar { "enabled" } else { "disabled" },
+            radial_flow = params.radial_flow,
+            par_offsets = params.format_par_offsets(),
+            error_floors = params.format_error_floors(),
+            sigma_profile = params.sigma_profile,
+            values = values.join("\n"),
+        );
+        output::serialize_to(&self.output_dir, "simulated", &header, &catalogue.records)
+            .with_context(|| "Couldn't write the simulated catalogue")
+    }
+}
+
+/// Sample the position of an object from the spatial distribution
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+fn sample_position<F, R>(
+    object: &mut Object<F>,
+    distribution: &SpatialDistribution<F>,
+    params: &Params<F>,
+    rng: &mut R,
+) where
+    F: Float + Debug,
+    R: Rng,
+    Standard: Distribution<F>,
+    StandardNormal: Distribution<F>,
+    Exp1: Distribution<F>,
+{
+    let (sin_tilt, cos_tilt) = params.compute_tilt();
+    let r_0 = params.r_0 * cos_tilt;
+    let z_sun = params.z_sun / 1000.;
+    loop {
+        // The surface density is proportional to `exp(-R / h)`,
+        // so the radius follows the Gamma distribution with the
+        // shape parameter of 2 (a sum of two exponential values)
+        let e_1: F = rng.sample(Exp1);
+        let e_2: F = rng.sample(Exp1);
+        let r_g = distribution.r_g_scale * (e_1 + e_2);
+        let lambda = F::from(core::f64::consts::TAU).unwrap() * rng.gen::<F>();
+        let z_g = sample_normal(rng, 0., distribution.z_scale);
+        // Compute the heliocentric Cartesian coordinates in the frame aligned
+        // with the Galactocentric one (the X axis points toward the Galactic
+        // center), and rotate them to the heliocentric Galactic frame
+        let x_tilted = r_0 - r_g * lambda.cos();
+        let y = r_g * lambda.sin();
+        let z_tilted = z_g - z_sun;
+        let x = x_tilted * cos_tilt - z_tilted * sin_tilt;
+        let z = x_tilted * sin_tilt + z_tilted * cos_tilt;
+        let r_h = (x.powi(2) + y.powi(2) + z.powi(2)).sqrt();
+        if r_h > distribution.r_h_max || r_h == 0. {
+            continue;
+        }
+        object.l = Some(y.atan2(x));
+        object.b = Some((z / r_h).asin());
+        object.par = Some(1. / r_h);
+        // Recompute the Galactic coordinates from the equatorial ones, so that
+        // the longitude falls in the same range as for the objects read from
+        // the input files (the proper motions are converted via differences)
+        object.compute_alpha_delta(params);
+        object.compute_l_b(params);
+        break;
+    }
+}
+
+/// Simulate the observed values of an object
+///
+/// The object must have the coordinates, the true
+/// parallax, the uncertainties, and the source set.
+/// Also return whether the observed parallax was redrawn.
+#[allow(clippy::similar_names)]
+#[allow(clippy::unwrap_in_result)]
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+fn simulate_object<F, R>(
+    mut object: Object<F>,
+    params: &Params<F>,
+    rng: &mut R,
+) -> Result<(input::Record<F>, bool)>
+where
+    F: Float + Debug + Default,
+    R: Rng,
+    StandardNormal: Distribution<F>,
+{
+    // Unpack the data
+    let par = object.par.unwrap();
+    let par_e = object.par_e.unwrap();
+    let par_ep = object.par_ep.unwrap_or(par_e);
+    let par_em = object.par_em.unwrap_or(par_e);
+    let v_lsr_e = object.v_lsr_e.unwrap();
+    let v_lsr_ep = object.v_lsr_ep.unwrap_or(v_lsr_e);
+    let v_lsr_em = object.v_lsr_em.unwrap_or(v_lsr_e);
+    let mu_x_e = object.mu_x_e.unwrap();
+    let mu_y_e = object.mu_y_e.unwrap();
+    let rho_xy = object.mu_x_mu_y_corr.unwrap_or(0.);
+    let rho_px = object.par_mu_x_corr.unwrap_or(0.);
+    let rho_py = object.par_mu_y_corr.unwrap_or(0.);
+    // The Cholesky factor below exists only if
+    // the correlation matrix is positive definite
+    let det = 1. - rho_xy.powi(2) - rho_px.powi(2) - rho_py.powi(2) + 2. * rho_xy * rho_px * rho_py;
+    ensure!(
+        [rho_xy, rho_px, rho_py].iter().all(|rho| rho.abs() < 1.) && det > 0.,
+        "The correlation coefficients of the object {:?} should form a positive definite matrix",
+        object.name,
+    );
+    // With the observational errors zeroed, the errors of the inner
+    // problem consist of the natural dispersions only (plus the
+    // error floors of the source, see Reid et al. (2019))
+    object.compute_r_h_nominal();
+    object.compute_r_g_nominal(params);
+    object.v_r = Some(0.);
+    object.v_r_e = Some(0.);
+    object.v_r_ep = Some(0.);
+    object.v_r_em = Some(0.);
+    object.mu_x = Some(0.);
+    object.mu_x_e = Some(0.);
+    object.mu_y = Some(0.);
+    object.mu_y_e = Some(0.);
+    object.mu_l_cos_b = Some(0.);
+    object.mu_b = Some(0.);
+    let problem = prepare_inner_problem(&object, params);
+    // Compute the model values at the true parallax
+    let triples = problem.compute_triples(par);
+    let mut models = triples.iter().map(|triple| triple.model);
+    let v_r = models.next().unwrap();
+    let mu_l_cos_b = models.next().unwrap();
+    let mu_b = models.next().unwrap();
+    // Add the natural dispersions
+    object.v_r = Some(sample_normal(rng, v_r, problem.v_r_error));
+    object.mu_l_cos_b = Some(sample_normal(rng, mu_l_cos_b, problem.mu_l_cos_b_error));
+    object.mu_b = Some(sample_normal(rng, mu_b, problem.mu_b_error));
+    // Convert the values to the observed ones
+    object.compute_v_lsr(params);
+    object.compute_mu_x_mu_y(params);
+    // Add the observational errors. The correlated ones are drawn via the
+    // Cholesky factor of the correlation matrix of `mu_x`, `mu_y`, and `par`.
+    // The asymmetric ones follow the split normal distributions of the
+    // inner problem: the observed values above the true ones are scattered
+    // with the minus uncertainties (and vice versa)
+    let split = |z: F, ep: F, em: F| if z < 0. { z * ep } else { z * em };
+    let l_21 = rho_xy;
+    let l_22 = (1. - l_21.powi(2)).sqrt();
+    let l_31 = rho_px;
+    let l_32 = (rho_py - l_31 * l_21) / l_22;
+    let l_33 = (1. - l_31.powi(2) - l_32.powi(2)).sqrt();
+    let v_lsr = object.v_lsr.unwrap() + split(sample_normal(rng, 0., 1.), v_lsr_ep, v_lsr_em);
+    let z_1 = sample_normal(rng, 0., 1.);
+    let z_2 = sample_normal(rng, 0., 1.);
+    let mu_x = object.mu_x.unwrap() + mu_x_e * z_1;
+    let mu_y = object.mu_y.unwrap() + mu_y_e * (l_21 * z_1 + l_22 * z_2);
+    // The parallax is redrawn until it's positive (see `Model::simulate`)
+    let mut redrawn = false;
+    let par_obs = loop {
+        let z_3 = sample_normal(rng, 0., 1.);
+        let par_draw = par + split(l_31 * z_1 + l_32 * z_2 + l_33 * z_3, par_ep, par_em);
+        if par_draw > 0. {
+            break par_draw;
+        }
+        redrawn = true;
+    };
+    let record = input::Record {
+        name: object.name.unwrap(),
+        alpha: utils::rad2hms(object.alpha.unwrap()),
+        delta: utils::rad2dms(object.delta.unwrap()),
+        par: par_obs,
+        par_e,
+        v_lsr,
+        v_lsr_e,
+        mu_x,
+        mu_x_e,
+        mu_y,
+        mu_y_e,
+        obj_type: object.obj_type.unwrap(),
+        source: object.source.unwrap(),
+        reference: "Simulated".to_owned(),
+        mu_x_mu_y_corr: Some(rho_xy),
+        par_mu_x_corr: Some(rho_px),
+        par_mu_y_corr: Some(rho_py),
+        par_ep: Some(par_ep),
+        par_em: Some(par_em),
+        v_lsr_ep: Some(v_lsr_ep),
+        v_lsr_em: Some(v_lsr_em),
+    };
+    Ok((record, redrawn))
+}
+
+#[cfg(test)]
+use rand::SeedableRng;
+#[cfg(test)]
+use rand_chacha::ChaCha8Rng;
+
+/// Get the parameters of the model used in the tests
+/// (close to those of Reid et al. (2019))
+#[cfg(test)]
+pub(in crate::model) fn test_params() -> Params<f64> {
+    Params {
+        r_0: 8.15,
+        omega_0: 28.,
+        a: 17.,
+        u_sun: 10.7,
+        v_sun: 19.,
+        w_sun: 7.7,
+        sigma_r_g: 8.,
+        sigma_theta: 6.,
+        sigma_z: 4.,
+        alpha_ngp: 3.366_033_392_377_493,
+        delta_ngp: 0.473_478_800_270_973_6,
+        l_ncp: 2.145_568_156_061_669_3,
+        k: 4.7406,
+        u_sun_standard: 10.3,
+        v_sun_standard: 15.3,
+        w_sun_standard: 7.7,
+        ..Default::default()
+    }
+}
+
+/// Simulate a small catalogue for the tests of the fitting routines
+///
+/// The objects are distributed in the disk within 6 kpc from the Sun and
+/// have the uncertainties typical for the masers (correlated, too).
+#[cfg(test)]
+pub(in crate::model) fn simulate_test_objects(
+    params: &Params<f64>,
+    n_objects: usize,
+    seed: u64,
+) -> Result<Vec<Object<f64>>> {
+    let template = Object::try_from(input::Record {
+        name: "G049.19-00.33".to_owned(),
+        alpha: "19:22:57.7705".to_owned(),
+        delta: "+14:16:09.983".to_owned(),
+        par: 0.197,
+        par_e: 0.02,
+        v_lsr: 0.,
+        v_lsr_e: 3.,
+        mu_x: 0.,
+        mu_x_e: 0.05,
+        mu_y: 0.,
+        mu_y_e: 0.05,
+        obj_type: "HMSFR".to_owned(),
+        source: "Reid".to_owned(),
+        reference: String::new(),
+        mu_x_mu_y_corr: Some(0.3),
+        par_mu_x_corr: Some(-0.2),
+        par_mu_y_corr: Some(0.1),
+        par_ep: None,
+        par_em: None,
+        v_lsr_ep: None,
+        v_lsr_em: None,
+    })?;
+    let model = Model::builder()
+        .params(params.clone())
+        .objects(vec![template])
+        .build()?;
+    let distribution = SpatialDistribution {
+        n_objects,
+        r_g_scale: 3.,
+        z_scale: 0.05,
+        r_h_max: 6.,
+    };
+    model
+        .simulate(Some(&distribution), &mut ChaCha8Rng::seed_from_u64(seed))?
+        .records
+        .into_iter()
+        .map(Object::try_from)
+        .collect()
+}
+
+#[test]
+#[allow(clippy::indexing_slicing)]
+#[allow(clippy::unwrap_used)]
+fn test() -> Result<()> {
+    // Define the parameters without the dispersions
+    let params = Params {
+        r_0: 8.15,
+        omega_0: 28.,
+        a: 17.,
+        u_sun: 10.7,
+        v_sun: 19.,
+        w_sun: 7.7,
+        theta_2: -1.,
+        alpha_ngp: 3.366_033_392_377_493,
+        delta_ngp: 0.473_478_800_270_973_6,
+        l_ncp: 2.145_568_156_061_669_3,
+        k: 4.7406,
+        u_sun_standard: 10.3,
+        v_sun_standard: 15.3,
+        w_sun_standard: 7.7,
+        ..Default::default()
+    };
+    // Prepare an error-free template
+    let template = Object::try_from(input::Record {
+        name: "G049.19-00.33".to_owned(),
+        alpha: "19:22:57.7705".to_owned(),
+        delta: "+14:16:09.983".to_owned(),
+        par: 0.197,
+        par_e: 0.,
+        v_lsr: 0.,
+        v_lsr_e: 0.,
+        mu_x: 0.,
+        mu_x_e: 0.,
+        mu_y: 0.,
+        mu_y_e: 0.,
+        obj_type: "HMSFR".to_owned(),
+        source: "Reid".to_owned(),
+        reference: String::new(),
+        mu_x_mu_y_corr: None,
+        par_mu_x_corr: None,
+        par_mu_y_corr: None,
+        par_ep: None,
+        par_em: None,
+        v_lsr_ep: None,
+        v_lsr_em: None,
+    })?;
+    let model = Model::builder()
+        .params(params.clone())
+        .objects(vec![template])
+        .build()?;
+    // Simulate the catalogues (with the template itself and with the
+    // positions sampled all around the Sun) and read them back
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let distribution = SpatialDistribution {
+        n_objects: 50,
+        r_g_scale: 3.,
+        z_scale: 0.05,
+        r_h_max: 15.,
+    };
+    let mut records = model.simulate(None, &mut rng)?.records;
+    records.extend(model.simulate(Some(&distribution), &mut rng)?.records);
+    for record in records {
+        let mut object = Object::try_from(record)?;
+        object.compute(&params);
+        // The observed values should match the model ones
+        let problem = prepare_inner_problem(&object, &params);
+        for triple in problem.compute_triples(object.par.unwrap()) {
+            ensure!(
+                (triple.observed - triple.model).abs() < 1e-6,
+                "The values should be the same: {:?} vs. {:?}",
+                triple.observed,
+                triple.model,
+            );
+        }
+    }
+    // The correlated errors can't be drawn if the
+    // correlation matrix is not positive definite
+    let mut degenerate = model.objects.borrow()[0].clone();
+    degenerate.mu_x_mu_y_corr = Some(1.);
+    let degenerate_model = Model::builder()
+        .params(params)
+        .objects(vec![degenerate])
+        .build()?;
+    ensure!(degenerate_model.simulate(None, &mut rng).is_err());
+    Ok(())
+}
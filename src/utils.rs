@@ -4,10 +4,18 @@ mod cast;
 mod dms2rad;
 mod finite_diff;
 mod hms2rad;
+mod parse_angle;
+mod quantile;
+mod rad2dms;
+mod rad2hms;
 mod str2vec;
 
 pub use cast::cast;
 pub use dms2rad::dms2rad;
 pub use finite_diff::{central_diff, forward_diff, FiniteDiff};
 pub use hms2rad::hms2rad;
+pub use parse_angle::{parse_angle, AngleUnit};
+pub use quantile::quantile;
+pub use rad2dms::rad2dms;
+pub use rad2hms::rad2hms;
 pub use str2vec::str2vec;
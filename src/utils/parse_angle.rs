@@ -0,0 +1,134 @@
+//! Parse an angle in one of the supported forms
+
+use super::str2vec::SEPARATORS;
+use super::{dms2rad, hms2rad, str2vec};
+
+use core::fmt::{Debug, Display};
+use core::str::FromStr;
+use std::error::Error;
+
+use anyhow::{anyhow, bail, Result};
+use num::Float;
+
+/// Form of an angle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AngleUnit {
+    /// Sexagesimal hours, minutes, and seconds
+    Hms,
+    /// Sexagesimal degrees, minutes, and seconds
+    Dms,
+    /// Decimal degrees
+    Degrees,
+    /// Radians
+    Radians,
+}
+
+impl AngleUnit {
+    /// Detect the form of the angle in the string
+    ///
+    /// The string is taken as a sexagesimal angle in the specified form if it
+    /// has more than one component, and as decimal degrees otherwise.
+    pub fn detect(string: &str, sexagesimal: Self) -> Self {
+        let n_components = string.split(SEPARATORS).filter(|s| !s.is_empty()).count();
+        if n_components > 1 {
+            sexagesimal
+        } else {
+            Self::Degrees
+        }
+    }
+}
+
+impl FromStr for AngleUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let lowercase = string.trim().to_lowercase();
+        match lowercase.strip_suffix(" angle").unwrap_or(&lowercase) {
+            "hms" => Ok(Self::Hms),
+            "dms" => Ok(Self::Dms),
+            "degrees" | "deg" => Ok(Self::Degrees),
+            "radians" | "rad" => Ok(Self::Radians),
+            _ => Err(anyhow!(
+                "Expected `HMS angle`, `DMS angle`, `degrees`, or `radians`"
+            )),
+        }
+    }
+}
+
+impl Display for AngleUnit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let string = match *self {
+            Self::Hms => "HMS angle",
+            Self::Dms => "DMS angle",
+            Self::Degrees => "degrees",
+            Self::Radians => "radians",
+        };
+        write!(f, "{string}")
+    }
+}
+
+/// Parse the string for an angle in the specified form and convert it to radians
+///
+/// The sexagesimal forms may omit the trailing components (e.g., `12:28.5`).
+pub fn parse_angle<F>(string: &str, unit: AngleUnit) -> Result<F>
+where
+    F: Float + Debug + FromStr,
+    <F as FromStr>::Err: Error + Send + Sync + 'static,
+{
+    let zero = F::zero();
+    let values = str2vec::<F>(string)?;
+    let angle = match (unit, values.as_slice()) {
+        (AngleUnit::Hms, &[hours]) => hms2rad(hours, zero, zero),
+        (AngleUnit::Hms, &[hours, minutes]) => hms2rad(hours, minutes, zero),
+        (AngleUnit::Hms, &[hours, minutes, seconds]) => hms2rad(hours, minutes, seconds),
+        (AngleUnit::Dms, &[degrees]) => dms2rad(degrees, zero, zero),
+        (AngleUnit::Dms, &[degrees, minutes]) => dms2rad(degrees, minutes, zero),
+        (AngleUnit::Dms, &[degrees, minutes, seconds]) => dms2rad(degrees, minutes, seconds),
+        (AngleUnit::Hms | AngleUnit::Dms, _) => bail!("One to three values were expected"),
+        (AngleUnit::Degrees, &[degrees]) => degrees.to_radians(),
+        (AngleUnit::Radians, &[radians]) => radians,
+        (AngleUnit::Degrees | AngleUnit::Radians, _) => bail!("A single value was expected"),
+    };
+    Ok(angle)
+}
+
+#[cfg(test)]
+use anyhow::ensure;
+
+#[test]
+fn test() -> Result<()> {
+    let a = dms2rad(-5., 35., 5.108);
+    for (string, sexagesimal) in [
+        ("-05:35:05.108", AngleUnit::Dms),
+        ("-05d35m05.108s", AngleUnit::Dms),
+        ("-5.584752222222222", AngleUnit::Dms),
+    ] {
+        let unit = AngleUnit::detect(string, sexagesimal);
+        let b: f64 = parse_angle(string, unit)?;
+        ensure!(
+            (a - b).abs() < 1e-12,
+            "The result should be the same for {string:?}: {a:?} vs. {b:?}"
+        );
+    }
+    let b: f64 = parse_angle(&a.to_string(), AngleUnit::Radians)?;
+    ensure!(
+        (a - b).abs() < f64::epsilon(),
+        "The result should be the same: {a:?} vs. {b:?}"
+    );
+    let c = hms2rad(12., 28., 30.);
+    let d: f64 = parse_angle("12h28.5m", AngleUnit::Hms)?;
+    ensure!(
+        (c - d).abs() < f64::epsilon(),
+        "The result should be the same: {c:?} vs. {d:?}"
+    );
+    for unit in [
+        AngleUnit::Hms,
+        AngleUnit::Dms,
+        AngleUnit::Degrees,
+        AngleUnit::Radians,
+    ] {
+        ensure!(unit.to_string().parse::<AngleUnit>()? == unit);
+    }
+    ensure!(parse_angle::<f64>("1:2:3:4", AngleUnit::Hms).is_err());
+    Ok(())
+}
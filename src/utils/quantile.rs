@@ -0,0 +1,42 @@
+//! Compute a quantile of a sample
+
+use core::fmt::Debug;
+
+use num::Float;
+
+/// Compute a quantile of a sorted sample, linearly
+/// interpolating between the closest ranks
+///
+/// The sample must not be empty.
+#[allow(clippy::indexing_slicing)]
+#[allow(clippy::unwrap_used)]
+pub fn quantile<F>(sorted: &[F], q: F) -> F
+where
+    F: Float + Debug,
+{
+    let last = sorted.len() - 1;
+    let position = q * F::from(last).unwrap();
+    let i = position.floor().to_usize().unwrap().min(last);
+    let j = (i + 1).min(last);
+    let weight = position - F::from(i).unwrap();
+    sorted[i] + weight * (sorted[j] - sorted[i])
+}
+
+#[cfg(test)]
+use anyhow::{ensure, Result};
+
+#[test]
+fn test() -> Result<()> {
+    let sorted = [1., 2., 3., 4., 5.];
+    let a = [
+        quantile(&sorted, 0.),
+        quantile(&sorted, 0.5),
+        quantile(&sorted, 0.875),
+    ];
+    let b = [1., 3., 4.5];
+    ensure!(
+        a.iter().zip(b).all(|(x, y)| f64::abs(x - y) < f64::EPSILON),
+        "The result should be the same: {a:?} vs. {b:?}"
+    );
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! Convert an angle in radians to a degrees-minutes-seconds string
+
+use core::fmt::Debug;
+
+use num::Float;
+use numeric_literals::replace_float_literals;
+
+/// Convert an angle in radians to a degrees-minutes-seconds string
+///
+/// The sign is always written, so that the angles
+/// in the (-1, 0) degrees range are parsed back properly.
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+pub fn rad2dms<F>(angle: F) -> String
+where
+    F: Float + Debug,
+{
+    // Count the angle in microseconds of arc, so
+    // that the rounding is carried over properly
+    let microseconds = (angle.to_degrees().abs() * 3600. * 1e6)
+        .round()
+        .to_u64()
+        .unwrap();
+    let seconds = microseconds.div_euclid(1_000_000);
+    format!(
+        "{}{:02}:{:02}:{:02}.{:06}",
+        if angle.is_sign_negative() { '-' } else { '+' },
+        seconds.div_euclid(3600),
+        seconds.rem_euclid(3600).div_euclid(60),
+        seconds.rem_euclid(60),
+        microseconds.rem_euclid(1_000_000)
+    )
+}
+
+#[cfg(test)]
+use anyhow::{ensure, Result};
+
+#[test]
+fn test() -> Result<()> {
+    let a = "-00:01:05.519000";
+    let b = rad2dms(super::dms2rad(-0., 1., 5.519));
+    ensure!(a == b, "The result should be the same: {a:?} vs. {b:?}");
+    Ok(())
+}
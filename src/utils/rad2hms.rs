@@ -0,0 +1,44 @@
+//! Convert an angle in radians to an hours-minutes-seconds string
+
+use core::fmt::Debug;
+
+use num::Float;
+use numeric_literals::replace_float_literals;
+
+/// Convert an angle in radians to an hours-minutes-seconds
+/// string (the angle is wrapped into the [0, 24) hours range)
+#[allow(clippy::unwrap_used)]
+#[replace_float_literals(F::from(literal).unwrap())]
+pub fn rad2hms<F>(angle: F) -> String
+where
+    F: Float + Debug,
+{
+    // Count the angle in microseconds of time, so
+    // that the rounding is carried over properly
+    let full_turn = 86_400_000_000_i64;
+    let microseconds = (angle.to_degrees() * 240. * 1e6)
+        .round()
+        .to_i64()
+        .unwrap()
+        .rem_euclid(full_turn)
+        .unsigned_abs();
+    let seconds = microseconds.div_euclid(1_000_000);
+    format!(
+        "{:02}:{:02}:{:02}.{:06}",
+        seconds.div_euclid(3600),
+        seconds.rem_euclid(3600).div_euclid(60),
+        seconds.rem_euclid(60),
+        microseconds.rem_euclid(1_000_000)
+    )
+}
+
+#[cfg(test)]
+use anyhow::{ensure, Result};
+
+#[test]
+fn test() -> Result<()> {
+    let a = "12:28:55.850000";
+    let b = rad2hms(super::hms2rad(12., 28., 55.85));
+    ensure!(a == b, "The result should be the same: {a:?} vs. {b:?}");
+    Ok(())
+}
@@ -7,14 +7,22 @@ use std::error::Error;
 use anyhow::{Context, Result};
 use num::Float;
 
-/// Parse a string for a vector of floats, splitting by colons
+/// Separators of the sexagesimal components
+pub const SEPARATORS: [char; 11] = [
+    ':', ' ', 'h', 'd', 'm', 's', '\u{b0}', '\'', '\u{2032}', '"', '\u{2033}',
+];
+
+/// Parse a string for a vector of floats, splitting by colons, spaces,
+/// or unit marks (e.g., `12h28m55.85s` or `-5°35′05.108″`)
 pub fn str2vec<F>(str: &str) -> Result<Vec<F>>
 where
     F: Float + Debug + FromStr,
     <F as FromStr>::Err: Error + Send + Sync + 'static,
 {
-    // Split a string by colons
-    str.split(':')
+    // Split a string by the separators
+    str.split(SEPARATORS)
+        // Skip the empty slices left by trailing marks or repeated spaces
+        .filter(|s| !s.is_empty())
         // For each slice
         .map(|s| {
             // Parse the slice as a floating point number
@@ -33,5 +41,14 @@ fn test() -> Result<()> {
     let a = vec![-5., 35., 5.108];
     let b: Vec<f64> = str2vec("-05:35:05.108").with_context(|| "Couldn't parse the string")?;
     ensure!(a == b, "The result should be the same: {a:?} vs. {b:?}");
+    for string in [
+        "-05 35 05.108",
+        "-05d35m05.108s",
+        "-05\u{b0}35\u{2032}05.108\u{2033}",
+        "-05\u{b0}35'05.108\"",
+    ] {
+        let c: Vec<f64> = str2vec(string)?;
+        ensure!(a == c, "The result should be the same: {a:?} vs. {c:?}");
+    }
     Ok(())
 }